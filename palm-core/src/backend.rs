@@ -0,0 +1,1155 @@
+use std::{
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+    sync::{
+        atomic::{AtomicU16, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::bail;
+use atomic_enum::atomic_enum;
+use chrono::{DateTime, TimeDelta};
+use log::info;
+use rand::Rng;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{tcp::OwnedWriteHalf, TcpListener, TcpStream},
+    runtime::Handle,
+    select,
+    sync::{
+        broadcast::{self, error::RecvError},
+        mpsc, watch,
+    },
+    time::{sleep, timeout},
+};
+
+use crate::{
+    logstore::LogStore,
+    stats::{Stats, StatsRecorder},
+};
+
+/// A TCP connection, either started as a client with [`Connection::start_client`] or accepted by a [`Server`].
+///
+/// Everything that happens on the connection is recorded as a [`Log`]. Logs are kept until they are
+/// moved into [`Connection::logs`] by [`Connection::poll_logs`], and are also broadcast to anybody
+/// who [subscribed](Connection::subscribe) as they happen.
+pub struct Connection {
+    address: Option<String>,
+    net_state: Arc<AtomicNetState>,
+    endpoints: Arc<Mutex<Option<Endpoints>>>,
+    logs: LogStore,
+    stats: StatsRecorder,
+
+    shutdown_tx: watch::Sender<bool>,
+    shutdown_rx: watch::Receiver<bool>,
+    log_tx: LogSink,
+    log_rx: mpsc::UnboundedReceiver<Log>,
+    sender_tx: broadcast::Sender<OutgoingPacket>,
+    // Never read, only held so that sending doesn't fail while there are no other receivers
+    _sender_rx: broadcast::Receiver<OutgoingPacket>,
+}
+
+impl Default for Connection {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Connection {
+    pub fn new() -> Self {
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let (log_tx, log_rx) = mpsc::unbounded_channel();
+        let (sender_tx, sender_rx) = broadcast::channel(1024);
+
+        Self {
+            address: None,
+            net_state: Arc::new(AtomicNetState::new(NetState::default())),
+            endpoints: Arc::default(),
+            logs: LogStore::default(),
+            stats: StatsRecorder::default(),
+            shutdown_tx,
+            shutdown_rx,
+            log_tx: LogSink::new(log_tx),
+            log_rx,
+            sender_tx,
+            _sender_rx: sender_rx,
+        }
+    }
+
+    /// Connects to `address` on the current runtime, resolving once the connection is established.
+    pub async fn connect(address: impl Into<String>) -> anyhow::Result<Self> {
+        let mut conn = Self::new();
+        let mut events = conn.subscribe();
+        conn.start_client(address.into(), &Handle::current())?;
+
+        loop {
+            match events.recv().await {
+                Ok(log) => match log.data {
+                    LogData::ClientConnect(_) => return Ok(conn),
+                    LogData::ConnectError(error) => bail!("Failed to connect: {error}"),
+                    LogData::ConnectTimedOut => bail!("Failed to connect: Timed Out"),
+                    _ => {}
+                },
+                Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => unreachable!("`conn` owns the sender"),
+            }
+        }
+    }
+
+    /// Starts connecting to `address` in the background, which is reported by a
+    /// [`LogData::ClientConnect`] or an error log once done.
+    /// Fails if the connection is already establishing or established.
+    pub fn start_client(&mut self, address: String, rt: &Handle) -> anyhow::Result<()> {
+        if self.net_state() != NetState::Inactive {
+            bail!("Connection is already establishing or established");
+        }
+
+        self.address = Some(address.clone());
+
+        let shutdown_rx = self.shutdown_rx.clone();
+        let shutdown_tx = self.shutdown_tx.clone();
+        let log_tx = self.log_tx.clone();
+        let sender_rx = self.sender_tx.subscribe();
+        let net_state = self.net_state.clone();
+        let endpoints = self.endpoints.clone();
+        let stats = self.stats.clone();
+        net_state.store(NetState::Establishing, Ordering::Relaxed);
+
+        rt.spawn(async move {
+            let stream = match timeout(Duration::from_secs(8), TcpStream::connect(&address)).await {
+                Ok(Ok(active_stream)) => active_stream,
+                Ok(Err(error)) => {
+                    info!("Failed to establish connection to {}", address);
+                    log_tx.send(Log::connect_error(error));
+                    net_state.store(NetState::Inactive, Ordering::Relaxed);
+                    return;
+                }
+                Err(_) => {
+                    info!("Failed to establish connection to {}: Timed Out", address);
+                    log_tx.send(Log::connect_timed_out());
+                    net_state.store(NetState::Inactive, Ordering::Relaxed);
+                    return;
+                }
+            };
+            *endpoints.lock().unwrap() = Endpoints::of(&stream);
+            net_state.store(NetState::Active, Ordering::Relaxed);
+            log_tx.send(Log::connect(address.clone()));
+            info!("Connected to {}", address);
+
+            Self::manage(
+                stream,
+                address,
+                net_state,
+                shutdown_tx,
+                shutdown_rx,
+                log_tx,
+                sender_rx,
+                stats,
+                None,
+                None,
+            )
+            .await
+        });
+        Ok(())
+    }
+
+    fn start_established(
+        &mut self,
+        stream: TcpStream,
+        address: String,
+        server_log_tx: Option<LogSink>,
+        external_shutdown_rx: Option<watch::Receiver<bool>>,
+    ) {
+        self.address = Some(address.clone());
+        let stats = self.stats.clone();
+        *self.endpoints.lock().unwrap() = Endpoints::of(&stream);
+        // Make the traffic of this connection part of the owning server's events
+        self.log_tx.parent_events_tx = server_log_tx.as_ref().map(|s| s.events_tx.clone());
+
+        let shutdown_rx = self.shutdown_rx.clone();
+        let shutdown_tx = self.shutdown_tx.clone();
+        let log_tx = self.log_tx.clone();
+        let sender_rx = self.sender_tx.subscribe();
+        let net_state = self.net_state.clone();
+        // Already active for whoever hears of the connection from its server
+        net_state.store(NetState::Active, Ordering::Relaxed);
+        tokio::spawn(async move {
+            log_tx.send(Log::connect(address.clone()));
+
+            Self::manage(
+                stream,
+                address,
+                net_state,
+                shutdown_tx,
+                shutdown_rx,
+                log_tx,
+                sender_rx,
+                stats,
+                server_log_tx,
+                external_shutdown_rx,
+            )
+            .await
+        });
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn manage(
+        stream: TcpStream,
+        address: String,
+        net_state: Arc<AtomicNetState>,
+        shutdown_tx: watch::Sender<bool>,
+        shutdown_rx: watch::Receiver<bool>,
+        log_tx: LogSink,
+        mut sender_rx: broadcast::Receiver<OutgoingPacket>,
+        stats: StatsRecorder,
+        server_log_tx: Option<LogSink>,
+        external_shutdown_rx: Option<watch::Receiver<bool>>,
+    ) {
+        let (mut reader, mut writer) = stream.into_split();
+        stats.start();
+
+        let r_address = address.clone();
+        let mut shutdown_rx_r = shutdown_rx.clone();
+        let shutdown_tx_r = shutdown_tx.clone();
+        let r_log_tx = log_tx.clone();
+        let r_stats = stats.clone();
+
+        let (_fake_tx, fake_rx) = watch::channel(false);
+        let mut external_shutdown_rx = external_shutdown_rx.unwrap_or(fake_rx);
+
+        let reader_task = async move {
+            let mut read_data = [0u8; 2048];
+            loop {
+                select! {
+                    _ = shutdown_rx_r.changed() => {
+                        if *shutdown_rx_r.borrow() {
+                            break;
+                        }
+                    },
+                    _ = external_shutdown_rx.changed() => {
+                        if *external_shutdown_rx.borrow() {
+                            shutdown_tx_r.send_replace(true);
+                        }
+                    },
+                    result = reader.read(&mut read_data) => {
+                        let read_bytes = match result {
+                            Ok(c) => c,
+                            Err(error) => {
+                                if error.kind() == std::io::ErrorKind::Interrupted {
+                                    continue;
+                                } else {
+                                    info!("Connection Closed Due to Fatal Read Error: {error}");
+                                    r_log_tx.send(Log::fatal_read_error(error));
+                                    shutdown_tx_r.send_replace(true);
+                                    break;
+                                }
+                            }
+                        };
+
+                        if read_bytes == 0 { // Peer closed connection
+                            info!("Peer {r_address} closed connection");
+                            shutdown_tx_r.send_replace(true);
+                        } else {
+                            r_stats.received(read_bytes);
+                            r_log_tx.send(Log::received(DataPacket::new(r_address.clone(), read_data[0..read_bytes].to_vec())));
+                        }
+                    }
+                }
+            }
+        };
+
+        let mut shutdown_rx_w = shutdown_rx.clone();
+        let shutdown_tx_w = shutdown_tx.clone();
+        let w_log_tx = log_tx.clone();
+        let w_stats = stats.clone();
+        let writer_task = async move {
+            loop {
+                select! {
+                    _ = shutdown_rx_w.changed() => {
+                        if *shutdown_rx_w.borrow() {
+                            break;
+                        }
+                    },
+                    send_data = sender_rx.recv() => {
+                        let send_data = match send_data {
+                            Ok(send_data) => send_data,
+                            Err(RecvError::Lagged(_)) => continue,
+                            // The connection and all of its handles were dropped
+                            Err(RecvError::Closed) => {
+                                shutdown_tx_w.send_replace(true);
+                                break;
+                            }
+                        };
+
+                        let len = send_data.packet.data.len();
+                        let result = if send_data.packet.fragments.is_empty() {
+                            Self::write(&mut writer, &send_data.packet.data).await
+                        } else {
+                            // Disable Nagle's algorithm so that every fragment is put on the wire separately
+                            let nodelay = writer.as_ref().nodelay().unwrap_or(false);
+                            let _ = writer.as_ref().set_nodelay(true);
+
+                            let mut written = 0;
+                            let result = select! {
+                                _ = shutdown_rx_w.wait_for(|&shutdown| shutdown) => None,
+                                result = Self::write_fragmented(&mut writer, &send_data, &mut written) => Some(result),
+                            };
+
+                            let _ = writer.as_ref().set_nodelay(nodelay);
+                            match result {
+                                Some(result) => result,
+                                // Shut down between fragments, so only count what made it out
+                                None => {
+                                    if written > 0 {
+                                        w_stats.sent(written);
+                                    }
+                                    w_log_tx.send(Log::fatal_write_error(std::io::Error::new(
+                                        std::io::ErrorKind::Interrupted,
+                                        format!("Shut down after sending {written} of {len} bytes"),
+                                    )));
+                                    break;
+                                }
+                            }
+                        };
+
+                        if let Err(error) = result {
+                            info!("Connection Closed Due to Fatal Write Error: {error}");
+                            w_log_tx.send(Log::fatal_write_error(error));
+                            shutdown_tx_w.send_replace(true);
+                            break;
+                        }
+                        w_stats.sent(len);
+                    }
+                }
+            }
+        };
+
+        tokio::join!(reader_task, writer_task);
+        stats.stop();
+        shutdown_tx.send_replace(false);
+        net_state.store(NetState::Inactive, Ordering::Relaxed);
+        info!("Disconnected from {}", address);
+        let disconnect_log = Log::disconnect(address);
+        if let Some(server_log_tx) = server_log_tx {
+            server_log_tx.send(disconnect_log.clone());
+        }
+        log_tx.send(disconnect_log);
+    }
+
+    async fn write(writer: &mut OwnedWriteHalf, data: &[u8]) -> std::io::Result<()> {
+        writer.write_all(data).await?;
+        writer.flush().await
+    }
+
+    /// Writes the fragments of `outgoing` one by one, counting the bytes written into `written` so
+    /// that it's known how much was sent if the write is cancelled.
+    async fn write_fragmented(
+        writer: &mut OwnedWriteHalf,
+        outgoing: &OutgoingPacket,
+        written: &mut usize,
+    ) -> std::io::Result<()> {
+        let mut start = 0;
+        for (i, &end) in outgoing.packet.fragments.iter().enumerate() {
+            if i != 0 {
+                sleep(outgoing.delay).await;
+            }
+            let mut fragment = &outgoing.packet.data[start..end];
+            while !fragment.is_empty() {
+                let len = writer.write(fragment).await?;
+                if len == 0 {
+                    return Err(std::io::ErrorKind::WriteZero.into());
+                }
+                *written += len;
+                fragment = &fragment[len..];
+            }
+            writer.flush().await?;
+            start = end;
+        }
+        Ok(())
+    }
+
+    pub fn send_data(&self, data: Vec<u8>) -> anyhow::Result<()> {
+        self.send_data_with(data, &SendOptions::default())
+    }
+
+    pub fn send_data_with(&self, data: Vec<u8>, options: &SendOptions) -> anyhow::Result<()> {
+        self.handle().send_data_with(data, options)
+    }
+
+    /// Returns a handle that can be used to interact with this connection from other threads or tasks.
+    pub fn handle(&self) -> ConnectionHandle {
+        ConnectionHandle {
+            address: self.address.clone().unwrap_or_default(),
+            net_state: self.net_state.clone(),
+            endpoints: self.endpoints.clone(),
+            shutdown_tx: self.shutdown_tx.clone(),
+            log_tx: self.log_tx.clone(),
+            sender_tx: self.sender_tx.clone(),
+        }
+    }
+
+    /// Subscribes to every log of this connection as it happens.
+    pub fn subscribe(&self) -> broadcast::Receiver<Log> {
+        self.log_tx.events_tx.subscribe()
+    }
+
+    /// Moves logs that arrived since the last call into the log and returns just those, minus any
+    /// that had to be dropped right away because more arrived than the log can hold.
+    pub fn poll_logs(&mut self) -> impl DoubleEndedIterator<Item = &Log> {
+        let prior_end = self.logs.end();
+        while let Ok(log) = self.log_rx.try_recv() {
+            self.logs.push(log);
+        }
+        self.logs.range(prior_end..self.logs.end())
+    }
+
+    /// All logs as of the last call to [`Self::poll_logs`] that weren't dropped yet.
+    pub fn logs(&self) -> &LogStore {
+        &self.logs
+    }
+
+    /// Changes how many logs are kept, see [`LogStore::set_capacity`].
+    pub fn set_log_capacity(&mut self, capacity: usize) {
+        self.logs.set_capacity(capacity);
+    }
+
+    pub fn shutdown(&self) {
+        self.shutdown_tx.send_replace(true);
+    }
+
+    pub fn address(&self) -> Option<&str> {
+        self.address.as_deref()
+    }
+
+    pub fn net_state(&self) -> NetState {
+        self.net_state.load(Ordering::Relaxed)
+    }
+
+    /// The socket addresses of the current or last established connection.
+    pub fn endpoints(&self) -> Option<Endpoints> {
+        *self.endpoints.lock().unwrap()
+    }
+
+    /// Traffic of the current or last established connection.
+    pub fn stats(&self) -> Stats {
+        self.stats.snapshot()
+    }
+}
+
+/// Both ends of an established connection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Endpoints {
+    pub local: SocketAddr,
+    pub peer: SocketAddr,
+}
+
+impl Endpoints {
+    fn of(stream: &TcpStream) -> Option<Self> {
+        Some(Self {
+            local: stream.local_addr().ok()?,
+            peer: stream.peer_addr().ok()?,
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct ConnectionHandle {
+    address: String,
+    net_state: Arc<AtomicNetState>,
+    endpoints: Arc<Mutex<Option<Endpoints>>>,
+    shutdown_tx: watch::Sender<bool>,
+    log_tx: LogSink,
+    sender_tx: broadcast::Sender<OutgoingPacket>,
+}
+
+impl ConnectionHandle {
+    pub fn send_data(&self, data: Vec<u8>) -> anyhow::Result<()> {
+        self.send_data_with(data, &SendOptions::default())
+    }
+
+    pub fn send_data_with(&self, data: Vec<u8>, options: &SendOptions) -> anyhow::Result<()> {
+        if self.net_state() != NetState::Active {
+            anyhow::bail!("Connection is not active");
+        }
+
+        let fragments = options.fragmentation.boundaries(data.len());
+        let packet = DataPacket::fragmented(self.address.clone(), data, fragments);
+        // Log first so that the packet can't end up after a reply to it
+        self.log(Log::new(LogData::SentPacket(packet.clone())))?;
+        self.sender_tx.send(OutgoingPacket {
+            packet,
+            delay: options.delay,
+        })?;
+        Ok(())
+    }
+
+    /// Adds `log` to the connection's log. Fails if the connection was dropped.
+    pub fn log(&self, log: Log) -> anyhow::Result<()> {
+        self.log_tx.log(log)
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Log> {
+        self.log_tx.events_tx.subscribe()
+    }
+
+    pub fn shutdown(&self) {
+        self.shutdown_tx.send_replace(true);
+    }
+
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    pub fn net_state(&self) -> NetState {
+        self.net_state.load(Ordering::Relaxed)
+    }
+
+    /// The socket addresses of the current or last established connection.
+    pub fn endpoints(&self) -> Option<Endpoints> {
+        *self.endpoints.lock().unwrap()
+    }
+}
+
+/// A TCP server accepting any number of [`Connection`]s on localhost.
+///
+/// Logs work like those of a [`Connection`]. Subscribers additionally receive the traffic and
+/// fatal errors of every connection, while the rest of a connection's logs stay with the
+/// connection.
+pub struct Server {
+    /// The port the server is bound to, 0 while it isn't
+    port: Arc<AtomicU16>,
+    net_state: Arc<AtomicNetState>,
+    connections: Arc<RwLock<Vec<Connection>>>,
+    logs: LogStore,
+    /// Traffic of all connections since the server started
+    stats: StatsRecorder,
+
+    shutdown_tx: watch::Sender<bool>,
+    shutdown_rx: watch::Receiver<bool>,
+
+    log_tx: LogSink,
+    log_rx: mpsc::UnboundedReceiver<Log>,
+}
+
+impl Default for Server {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Server {
+    pub fn new() -> Self {
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let (log_tx, log_rx) = mpsc::unbounded_channel();
+
+        Self {
+            port: Arc::default(),
+            net_state: Arc::new(AtomicNetState::new(NetState::default())),
+            connections: Arc::default(),
+            logs: LogStore::default(),
+            stats: StatsRecorder::default(),
+
+            shutdown_tx,
+            shutdown_rx,
+            log_tx: LogSink::new(log_tx),
+            log_rx,
+        }
+    }
+
+    /// Starts a server listening on `port` of the current runtime, resolving once it's listening.
+    /// Pass 0 to let the OS pick a free port, which can then be read with [`Self::port`].
+    pub async fn listen(port: u16) -> anyhow::Result<Self> {
+        let mut server = Self::new();
+        let mut events = server.subscribe();
+        server.start(port, &Handle::current())?;
+
+        loop {
+            match events.recv().await {
+                Ok(log) => match log.data {
+                    LogData::ServerStarted => return Ok(server),
+                    LogData::ServerStartError(error) => bail!("Failed to start server: {error}"),
+                    _ => {}
+                },
+                Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => unreachable!("`server` owns the sender"),
+            }
+        }
+    }
+
+    /// Starts listening on `port` in the background, which is reported by a
+    /// [`LogData::ServerStarted`] or [`LogData::ServerStartError`] log once done.
+    /// Fails if the server is already starting or started.
+    pub fn start(&mut self, port: u16, rt: &Handle) -> anyhow::Result<()> {
+        if self.net_state() != NetState::Inactive {
+            bail!("Server is already starting or started");
+        }
+
+        let mut shutdown_rx = self.shutdown_rx.clone();
+        let log_tx = self.log_tx.clone();
+        let connections = self.connections.clone();
+        let net_state = self.net_state.clone();
+        let bound_port = self.port.clone();
+        let stats = self.stats.clone();
+        net_state.store(NetState::Establishing, Ordering::Relaxed);
+        rt.spawn(async move {
+            let listener = match TcpListener::bind(SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), port))
+                .await {
+                    Ok(listener) => listener,
+                    Err(error) => {
+                        info!("Failed to to start server on port {port}: {error}");
+                        log_tx.send(Log::server_start_error(error));
+                        net_state.store(NetState::Inactive, Ordering::Relaxed);
+                        return;
+                    }
+                };
+            let port = listener.local_addr().map_or(port, |addr| addr.port());
+            bound_port.store(port, Ordering::Relaxed);
+
+            stats.start();
+            net_state.store(NetState::Active, Ordering::Relaxed);
+            info!("Server Started on Port {}", port);
+            log_tx.send(Log::server_started());
+
+            loop {
+                select! {
+                    _ = shutdown_rx.changed() => {
+                        if *shutdown_rx.borrow() {
+                            break;
+                        }
+                    },
+                    accept_res = listener.accept() => {
+                        let (stream, addr) = match accept_res {
+                            Ok(accepted) => accepted,
+                            Err(error) => {
+                                info!("Failed to accept connection: {error}");
+                                continue;
+                            }
+                        };
+                        let address_str = addr.to_string();
+
+                        let mut conn = Connection::new();
+                        conn.stats = StatsRecorder::for_server_connection(&stats);
+
+                        // Hold the lock until the connection is added so that it can be found as soon as anybody hears of it
+                        let mut connections = connections.write().unwrap();
+                        log_tx.send(Log::connect(address_str.clone()));
+                        conn.start_established(stream, address_str, Some(log_tx.clone()), Some(shutdown_rx.clone()));
+                        connections.push(conn);
+                    }
+                }
+            }
+
+            bound_port.store(0, Ordering::Relaxed);
+            stats.stop();
+            net_state.store(NetState::Inactive, Ordering::Relaxed);
+            info!("Server on Port {} Stopped", port);
+            log_tx.send(Log::server_stopped());
+        });
+        Ok(())
+    }
+
+    pub fn shutdown(&self) {
+        self.shutdown_tx.send_replace(true);
+    }
+
+    /// The port the server is listening on, if it is.
+    pub fn port(&self) -> Option<u16> {
+        match self.port.load(Ordering::Relaxed) {
+            0 => None,
+            port => Some(port),
+        }
+    }
+
+    /// Moves logs that arrived since the last call into the log and returns just those, minus any
+    /// that had to be dropped right away because more arrived than the log can hold.
+    pub fn poll_logs(&mut self) -> impl DoubleEndedIterator<Item = &Log> {
+        let prior_end = self.logs.end();
+        while let Ok(log) = self.log_rx.try_recv() {
+            self.logs.push(log);
+        }
+        self.logs.range(prior_end..self.logs.end())
+    }
+
+    /// Same as [`Self::poll_logs`] for all connections, discarding the result.
+    /// Connections keep as many logs as the server.
+    pub fn poll_connection_logs(&self) {
+        for conn in self.connections.write().unwrap().iter_mut() {
+            if conn.logs.capacity() != self.logs.capacity() {
+                conn.set_log_capacity(self.logs.capacity());
+            }
+            let _ = conn.poll_logs();
+        }
+    }
+
+    /// All logs as of the last call to [`Self::poll_logs`] that weren't dropped yet.
+    pub fn logs(&self) -> &LogStore {
+        &self.logs
+    }
+
+    /// Changes how many logs the server and each of its connections keep, see
+    /// [`LogStore::set_capacity`]. Connections pick it up the next time they're polled.
+    pub fn set_log_capacity(&mut self, capacity: usize) {
+        self.logs.set_capacity(capacity);
+    }
+
+    /// Traffic of all connections since the server last started, including those that were
+    /// closed since.
+    pub fn stats(&self) -> Stats {
+        self.stats.snapshot()
+    }
+
+    /// Returns a handle that can be used to interact with this server from other threads or tasks.
+    pub fn handle(&self) -> ServerHandle {
+        ServerHandle {
+            net_state: self.net_state.clone(),
+            connections: self.connections.clone(),
+            shutdown_tx: self.shutdown_tx.clone(),
+            log_tx: self.log_tx.clone(),
+        }
+    }
+
+    /// Subscribes to every log of this server as well as the traffic and fatal errors of all its
+    /// connections.
+    pub fn subscribe(&self) -> broadcast::Receiver<Log> {
+        self.log_tx.events_tx.subscribe()
+    }
+
+    pub fn with_connection<T>(&self, address: &str, f: impl FnOnce(Option<&Connection>) -> T) -> T {
+        f(self
+            .connections
+            .read()
+            .unwrap()
+            .iter()
+            .find(|c| c.address.as_deref() == Some(address)))
+    }
+
+    pub fn with_connections<T>(&self, f: impl FnOnce(&[Connection]) -> T) -> T {
+        f(&self.connections.read().unwrap())
+    }
+
+    pub fn with_connection_mut<T>(
+        &self,
+        address: &str,
+        f: impl FnOnce(Option<&mut Connection>) -> T,
+    ) -> T {
+        f(self
+            .connections
+            .write()
+            .unwrap()
+            .iter_mut()
+            .find(|c| c.address.as_deref() == Some(address)))
+    }
+
+    pub fn net_state(&self) -> NetState {
+        self.net_state.load(Ordering::Relaxed)
+    }
+}
+
+/// Handle to a [`Server`] that can be used from other threads or tasks.
+#[derive(Clone)]
+pub struct ServerHandle {
+    net_state: Arc<AtomicNetState>,
+    connections: Arc<RwLock<Vec<Connection>>>,
+    shutdown_tx: watch::Sender<bool>,
+    log_tx: LogSink,
+}
+
+impl ServerHandle {
+    pub fn connection(&self, address: &str) -> Option<ConnectionHandle> {
+        self.connections
+            .read()
+            .unwrap()
+            .iter()
+            .find(|c| c.address.as_deref() == Some(address))
+            .map(|c| c.handle())
+    }
+
+    pub fn connections(&self) -> Vec<ConnectionHandle> {
+        self.connections
+            .read()
+            .unwrap()
+            .iter()
+            .map(|c| c.handle())
+            .collect()
+    }
+
+    /// Sends `data` to every active connection.
+    pub fn broadcast(&self, data: Vec<u8>) -> anyhow::Result<()> {
+        for conn in self.connections() {
+            if conn.net_state() == NetState::Active {
+                conn.send_data(data.clone())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds `log` to the server's log. Fails if the server was dropped.
+    pub fn log(&self, log: Log) -> anyhow::Result<()> {
+        self.log_tx.log(log)
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Log> {
+        self.log_tx.events_tx.subscribe()
+    }
+
+    pub fn shutdown(&self) {
+        self.shutdown_tx.send_replace(true);
+    }
+
+    pub fn net_state(&self) -> NetState {
+        self.net_state.load(Ordering::Relaxed)
+    }
+}
+
+/// Delivers logs to their owner and broadcasts them to anybody subscribed.
+#[derive(Clone)]
+struct LogSink {
+    log_tx: mpsc::UnboundedSender<Log>,
+    events_tx: broadcast::Sender<Log>,
+    /// Events of the server owning the connection, which only receive its traffic and fatal errors.
+    parent_events_tx: Option<broadcast::Sender<Log>>,
+}
+
+impl LogSink {
+    fn new(log_tx: mpsc::UnboundedSender<Log>) -> Self {
+        Self {
+            log_tx,
+            events_tx: broadcast::channel(1024).0,
+            parent_events_tx: None,
+        }
+    }
+
+    /// Drops `log` if the owner is gone, as there's nobody left to care.
+    fn send(&self, log: Log) {
+        // Log before broadcasting so that anything a subscriber does in reaction ends up after it
+        let _ = self.log_tx.send(log.clone());
+        self.broadcast(&log);
+    }
+
+    fn log(&self, log: Log) -> anyhow::Result<()> {
+        if self.log_tx.is_closed() {
+            bail!("The log's owner was dropped");
+        }
+        self.send(log);
+        Ok(())
+    }
+
+    fn broadcast(&self, log: &Log) {
+        // Failing just means that nobody is subscribed
+        let _ = self.events_tx.send(log.clone());
+        if let Some(parent_events_tx) = &self.parent_events_tx {
+            if matches!(
+                log.data,
+                LogData::ReceivedPacket(_)
+                    | LogData::SentPacket(_)
+                    | LogData::FatalReadError(_)
+                    | LogData::FatalWriteError(_)
+            ) {
+                let _ = parent_events_tx.send(log.clone());
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Log {
+    pub data: LogData,
+    pub timestamp: DateTime<chrono::Local>,
+    /// When the log was made on a clock that isn't affected by changes to the system time,
+    /// `None` for logs that weren't made by this process
+    pub instant: Option<Instant>,
+}
+
+impl Log {
+    fn new(data: LogData) -> Self {
+        Self {
+            data,
+            timestamp: chrono::Local::now(),
+            instant: Some(Instant::now()),
+        }
+    }
+
+    /// The time from `earlier` to this log, using the monotonic clock if both logs have it.
+    pub fn since(&self, earlier: &Log) -> TimeDelta {
+        match (self.instant, earlier.instant) {
+            (Some(instant), Some(earlier_instant)) if instant >= earlier_instant => {
+                TimeDelta::from_std(instant - earlier_instant).unwrap_or(TimeDelta::MAX)
+            }
+            (Some(instant), Some(earlier_instant)) => {
+                -TimeDelta::from_std(earlier_instant - instant).unwrap_or(TimeDelta::MAX)
+            }
+            _ => self.timestamp - earlier.timestamp,
+        }
+    }
+
+    pub fn connect(address: String) -> Self {
+        Self::new(LogData::ClientConnect(address))
+    }
+
+    pub fn disconnect(address: String) -> Self {
+        Self::new(LogData::ClientDisconnect(address))
+    }
+
+    pub fn server_started() -> Self {
+        Self::new(LogData::ServerStarted)
+    }
+
+    pub fn server_stopped() -> Self {
+        Self::new(LogData::ServerStopped)
+    }
+
+    pub fn received(data: DataPacket) -> Self {
+        Self::new(LogData::ReceivedPacket(data))
+    }
+
+    pub fn sent(data: DataPacket) -> Self {
+        Self::new(LogData::SentPacket(data))
+    }
+
+    /// Replaces the time the log was created at, e.g. for logs made up from a capture.
+    pub fn at(mut self, timestamp: DateTime<chrono::Local>) -> Self {
+        self.timestamp = timestamp;
+        self.instant = None;
+        self
+    }
+
+    pub fn script(message: String) -> Self {
+        Self::new(LogData::Script(message))
+    }
+
+    pub fn script_error(message: String) -> Self {
+        Self::new(LogData::ScriptError(message))
+    }
+
+    pub fn connect_error(error: std::io::Error) -> Self {
+        Self::new(LogData::ConnectError(Arc::new(error)))
+    }
+
+    pub fn connect_timed_out() -> Self {
+        Self::new(LogData::ConnectTimedOut)
+    }
+
+    pub fn server_start_error(error: std::io::Error) -> Self {
+        Self::new(LogData::ServerStartError(Arc::new(error)))
+    }
+
+    pub fn fatal_read_error(error: std::io::Error) -> Self {
+        Self::new(LogData::FatalReadError(Arc::new(error)))
+    }
+
+    pub fn fatal_write_error(error: std::io::Error) -> Self {
+        Self::new(LogData::FatalWriteError(Arc::new(error)))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum LogData {
+    ClientConnect(String),
+    ClientDisconnect(String),
+    ServerStarted,
+    ServerStopped,
+    ReceivedPacket(DataPacket),
+    SentPacket(DataPacket),
+    ConnectError(Arc<std::io::Error>),
+    ConnectTimedOut,
+    ServerStartError(Arc<std::io::Error>),
+    FatalReadError(Arc<std::io::Error>),
+    FatalWriteError(Arc<std::io::Error>),
+    /// Output of a script attached to the connection or server
+    Script(String),
+    ScriptError(String),
+}
+
+#[derive(Clone, Debug)]
+pub struct DataPacket {
+    /// Address of the peer the packet was received from or sent to
+    pub address: String,
+    pub data: Vec<u8>,
+    /// End offsets of the fragments `data` was written in, empty if it was written all at once.
+    pub fragments: Vec<usize>,
+}
+
+impl DataPacket {
+    pub fn new(address: String, data: Vec<u8>) -> Self {
+        Self::fragmented(address, data, Vec::new())
+    }
+
+    pub fn fragmented(address: String, data: Vec<u8>, fragments: Vec<usize>) -> Self {
+        Self {
+            address,
+            data,
+            fragments,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct OutgoingPacket {
+    packet: DataPacket,
+    delay: Duration,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SendOptions {
+    pub fragmentation: Fragmentation,
+    /// Time to wait between writing consecutive fragments.
+    pub delay: Duration,
+}
+
+/// How a payload is split up into separate writes.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum Fragmentation {
+    #[default]
+    None,
+    /// Fragments of the given size, the last one may be shorter.
+    FixedSize(usize),
+    /// Split at each of the given byte offsets.
+    Offsets(Vec<usize>),
+    /// Fragments of a random size between `min` and `max` (inclusive).
+    Random { min: usize, max: usize },
+}
+
+impl Fragmentation {
+    /// Computes the end offset of every fragment of a `len` byte long payload.
+    /// Returns an empty `Vec` if the payload wouldn't be split at all.
+    pub fn boundaries(&self, len: usize) -> Vec<usize> {
+        let mut boundaries = match self {
+            Self::None => return Vec::new(),
+            Self::FixedSize(size) => (1..=len.div_ceil((*size).max(1)))
+                .map(|i| (i * (*size).max(1)).min(len))
+                .collect(),
+            Self::Offsets(offsets) => {
+                let mut boundaries: Vec<usize> = offsets
+                    .iter()
+                    .copied()
+                    .filter(|o| *o != 0 && *o < len)
+                    .collect();
+                boundaries.sort_unstable();
+                boundaries.dedup();
+                boundaries.push(len);
+                boundaries
+            }
+            Self::Random { min, max } => {
+                let min = (*min).max(1);
+                let max = (*max).max(min);
+                let mut rng = rand::rng();
+                let mut boundaries = Vec::new();
+                let mut offset = 0;
+                while offset < len {
+                    offset = (offset + rng.random_range(min..=max)).min(len);
+                    boundaries.push(offset);
+                }
+                boundaries
+            }
+        };
+
+        if boundaries.len() <= 1 {
+            boundaries.clear();
+        }
+        boundaries
+    }
+}
+
+#[atomic_enum]
+#[derive(Default, PartialEq, Eq)]
+pub enum NetState {
+    #[default]
+    Inactive,
+    Establishing,
+    Active,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unfragmented_payloads_have_no_boundaries() {
+        assert!(Fragmentation::None.boundaries(100).is_empty());
+        // A single fragment is the same as no fragmentation
+        assert!(Fragmentation::FixedSize(10).boundaries(10).is_empty());
+        assert!(Fragmentation::Offsets(vec![0, 10, 20])
+            .boundaries(10)
+            .is_empty());
+        assert!(Fragmentation::Random { min: 10, max: 20 }
+            .boundaries(5)
+            .is_empty());
+    }
+
+    #[test]
+    fn empty_payloads_have_no_boundaries() {
+        for fragmentation in [
+            Fragmentation::FixedSize(3),
+            Fragmentation::FixedSize(0),
+            Fragmentation::Offsets(vec![1, 2]),
+            Fragmentation::Random { min: 1, max: 3 },
+        ] {
+            assert!(fragmentation.boundaries(0).is_empty(), "{fragmentation:?}");
+        }
+    }
+
+    #[test]
+    fn fixed_size_fragments() {
+        assert_eq!(Fragmentation::FixedSize(3).boundaries(10), [3, 6, 9, 10]);
+        assert_eq!(Fragmentation::FixedSize(5).boundaries(10), [5, 10]);
+        // Treated as fragments of one byte rather than never ending
+        assert_eq!(Fragmentation::FixedSize(0).boundaries(3), [1, 2, 3]);
+    }
+
+    #[test]
+    fn offsets_are_sorted_and_kept_inside_the_payload() {
+        assert_eq!(
+            Fragmentation::Offsets(vec![7, 2, 0, 7, 10, 12]).boundaries(10),
+            [2, 7, 10]
+        );
+    }
+
+    #[test]
+    fn random_fragments_stay_within_bounds() {
+        for _ in 0..100 {
+            let boundaries = Fragmentation::Random { min: 2, max: 5 }.boundaries(50);
+            assert_eq!(boundaries.last(), Some(&50));
+            let sizes: Vec<usize> = std::iter::once(0)
+                .chain(boundaries.iter().copied())
+                .collect::<Vec<_>>()
+                .windows(2)
+                .map(|pair| pair[1] - pair[0])
+                .collect();
+            // Only the last fragment can be cut short by the end of the payload
+            let (last, rest) = sizes.split_last().unwrap();
+            assert!(rest.iter().all(|size| (2..=5).contains(size)), "{sizes:?}");
+            assert!((1..=5).contains(last), "{sizes:?}");
+        }
+        // Bounds the wrong way around or empty fragments are fixed up
+        assert_eq!(
+            Fragmentation::Random { min: 0, max: 0 }.boundaries(3),
+            [1, 2, 3]
+        );
+        assert_eq!(
+            Fragmentation::Random { min: 2, max: 1 }.boundaries(5),
+            [2, 4, 5]
+        );
+    }
+
+    #[test]
+    fn boundaries_increase_up_to_the_payload_length() {
+        let fragmentations = [
+            Fragmentation::FixedSize(1),
+            Fragmentation::FixedSize(7),
+            Fragmentation::Offsets(vec![99, 1, 50, 50, 200]),
+            Fragmentation::Random { min: 1, max: 30 },
+        ];
+        for fragmentation in fragmentations {
+            for len in [60, 99, 100] {
+                let boundaries = fragmentation.boundaries(len);
+                assert_eq!(boundaries.last(), Some(&len), "{fragmentation:?}");
+                assert!(
+                    boundaries.windows(2).all(|pair| pair[0] < pair[1]),
+                    "{fragmentation:?}: {boundaries:?}"
+                );
+                assert!(boundaries[0] > 0);
+            }
+        }
+    }
+}
@@ -0,0 +1,910 @@
+//! Import of pcap and pcapng captures. Packets are reassembled into [`Flow`]s, which can be
+//! viewed as logs or turned into a [`Scenario`] that replays one side of the conversation.
+
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::Path,
+    time::Duration,
+};
+
+use anyhow::{bail, Context};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    backend::{DataPacket, Log},
+    scenario::{Action, Matcher, Scenario, Step, Target, DEFAULT_TIMEOUT_MS},
+};
+
+const PCAP_MAGIC_MICROS: u32 = 0xA1B2_C3D4;
+const PCAP_MAGIC_NANOS: u32 = 0xA1B2_3C4D;
+const PCAPNG_SECTION_HEADER_BLOCK: u32 = 0x0A0D_0D0A;
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+const PCAPNG_INTERFACE_DESCRIPTION_BLOCK: u32 = 1;
+const PCAPNG_PACKET_BLOCK: u32 = 2;
+const PCAPNG_SIMPLE_PACKET_BLOCK: u32 = 3;
+const PCAPNG_ENHANCED_PACKET_BLOCK: u32 = 6;
+const PCAPNG_OPT_IF_TSRESOL: u16 = 9;
+
+const LINKTYPE_NULL: u32 = 0;
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const LINKTYPE_IPV4: u32 = 228;
+const LINKTYPE_IPV6: u32 = 229;
+const LINKTYPE_LINUX_SLL2: u32 = 276;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86DD;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+
+const IP_PROTOCOL_TCP: u8 = 6;
+const IP_PROTOCOL_UDP: u8 = 17;
+
+const TCP_FIN: u8 = 0x01;
+const TCP_SYN: u8 = 0x02;
+const TCP_RST: u8 = 0x04;
+const TCP_ACK: u8 = 0x10;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Protocol {
+    Tcp,
+    Udp,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    ClientToServer,
+    ServerToClient,
+}
+
+/// One side of a flow.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Side {
+    #[default]
+    Client,
+    Server,
+}
+
+#[derive(Clone, Debug)]
+pub struct Message {
+    pub timestamp: DateTime<Local>,
+    pub direction: Direction,
+    pub data: Vec<u8>,
+}
+
+/// A reassembled conversation between two endpoints.
+#[derive(Clone, Debug)]
+pub struct Flow {
+    pub protocol: Protocol,
+    /// The side that opened the connection, or sent first if that isn't part of the capture
+    pub client: SocketAddr,
+    pub server: SocketAddr,
+    /// Payloads in capture order. Consecutive TCP payloads in the same direction are merged,
+    /// UDP datagrams are kept as they are.
+    pub messages: Vec<Message>,
+    pub start: DateTime<Local>,
+    /// Set if the capture contains the connection being closed
+    pub end: Option<DateTime<Local>>,
+}
+
+/// How [`Flow::to_scenario`] replays a flow.
+#[derive(Clone, Debug, Default)]
+pub struct ReplayOptions {
+    /// The side Palm plays, the other one is expected to be a live peer
+    pub side: Side,
+    /// Wait as long between sending payloads as the original peers did
+    pub timing: bool,
+    pub replies: Replies,
+}
+
+/// What to do about the payloads the live peer sent in the capture.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Replies {
+    /// Don't wait for them
+    Ignore,
+    /// Wait for the same number of bytes before sending on
+    #[default]
+    Length,
+    /// Wait for them and fail unless they are identical
+    Exact,
+}
+
+impl Flow {
+    pub fn total_len(&self) -> usize {
+        self.messages.iter().map(|m| m.data.len()).sum()
+    }
+
+    /// The flow as logged by `side`.
+    pub fn logs(&self, side: Side) -> Vec<Log> {
+        let (local_direction, peer) = match side {
+            Side::Client => (Direction::ClientToServer, self.server),
+            Side::Server => (Direction::ServerToClient, self.client),
+        };
+        let peer = peer.to_string();
+
+        let mut logs = Vec::with_capacity(self.messages.len() + 2);
+        if self.protocol == Protocol::Tcp {
+            logs.push(Log::connect(peer.clone()).at(self.start));
+        }
+        for message in &self.messages {
+            let packet = DataPacket::new(peer.clone(), message.data.clone());
+            let log = if message.direction == local_direction {
+                Log::sent(packet)
+            } else {
+                Log::received(packet)
+            };
+            logs.push(log.at(message.timestamp));
+        }
+        if let Some(end) = self.end {
+            logs.push(Log::disconnect(peer).at(end));
+        }
+        logs
+    }
+
+    /// Makes a scenario sending the payloads of `options.side` against a live peer.
+    pub fn to_scenario(&self, options: &ReplayOptions) -> Scenario {
+        let (local_direction, target) = match options.side {
+            Side::Client => (
+                Direction::ClientToServer,
+                Target::Connect(self.server.to_string()),
+            ),
+            Side::Server => (
+                Direction::ServerToClient,
+                Target::Listen(self.server.port()),
+            ),
+        };
+        let timeout = Duration::from_millis(DEFAULT_TIMEOUT_MS);
+
+        let mut steps = Vec::new();
+        // Also keep the time between connecting and sending first
+        let mut previous = Some(self.start);
+        for message in &self.messages {
+            let action = if message.direction == local_direction {
+                if let Some(previous) = previous.filter(|_| options.timing) {
+                    let gap = (message.timestamp - previous).to_std().unwrap_or_default();
+                    if !gap.is_zero() {
+                        steps.push(Step {
+                            name: None,
+                            action: Action::Sleep(gap),
+                        });
+                    }
+                }
+                Some(Action::Send(message.data.clone()))
+            } else {
+                match options.replies {
+                    Replies::Ignore => None,
+                    Replies::Length => Some(Action::Expect {
+                        matcher: Matcher::Length(message.data.len()),
+                        timeout,
+                    }),
+                    Replies::Exact => Some(Action::Expect {
+                        matcher: Matcher::Exact(message.data.clone()),
+                        timeout,
+                    }),
+                }
+            };
+            previous = Some(message.timestamp);
+
+            if let Some(action) = action {
+                steps.push(Step { name: None, action });
+            }
+        }
+
+        Scenario {
+            name: format!("Replay of {self}"),
+            target: Some(target),
+            steps,
+        }
+    }
+}
+
+impl std::fmt::Display for Flow {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let protocol = match self.protocol {
+            Protocol::Tcp => "TCP",
+            Protocol::Udp => "UDP",
+        };
+        write!(f, "{protocol} {} → {}", self.client, self.server)
+    }
+}
+
+/// Reads the capture at `path` and returns every flow with a payload, in order of appearance.
+pub fn read(path: &Path) -> anyhow::Result<Vec<Flow>> {
+    let data = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    parse(&data)
+}
+
+/// Same as [`read`] for a capture that was already loaded.
+pub fn parse(data: &[u8]) -> anyhow::Result<Vec<Flow>> {
+    let mut assembler = Assembler::default();
+
+    let magic = Reader::new(data, false).u32(0)?;
+    if magic == PCAPNG_SECTION_HEADER_BLOCK {
+        parse_pcapng(data, &mut assembler)?;
+    } else if [PCAP_MAGIC_MICROS, PCAP_MAGIC_NANOS].contains(&magic)
+        || [PCAP_MAGIC_MICROS, PCAP_MAGIC_NANOS].contains(&magic.swap_bytes())
+    {
+        parse_pcap(data, &mut assembler)?;
+    } else {
+        bail!("Not a pcap or pcapng file");
+    }
+
+    if assembler.flows.is_empty() {
+        if let Some(link_type) = assembler.unsupported_link_type {
+            bail!("Unsupported link type {link_type}");
+        }
+    }
+    Ok(assembler.finish())
+}
+
+fn parse_pcap(data: &[u8], assembler: &mut Assembler) -> anyhow::Result<()> {
+    let magic = Reader::new(data, false).u32(0)?;
+    let reader = Reader::new(data, !matches!(magic, PCAP_MAGIC_MICROS | PCAP_MAGIC_NANOS));
+    let units_per_second = match reader.u32(0)? {
+        PCAP_MAGIC_NANOS => 1_000_000_000,
+        _ => 1_000_000,
+    };
+    let link_type = reader.u32(20)? & 0x0FFF_FFFF;
+
+    let mut offset = 24;
+    while offset + 16 <= data.len() {
+        let seconds = u64::from(reader.u32(offset)?);
+        let fraction = u64::from(reader.u32(offset + 4)?);
+        let captured_len = reader.u32(offset + 8)? as usize;
+        let packet = reader.bytes(offset + 16, captured_len)?;
+        let timestamp = timestamp(seconds * units_per_second + fraction, units_per_second);
+        assembler.packet(link_type, timestamp, packet);
+        offset += 16 + captured_len;
+    }
+    Ok(())
+}
+
+fn parse_pcapng(data: &[u8], assembler: &mut Assembler) -> anyhow::Result<()> {
+    // Link type and timestamp units per second of every interface of the current section
+    let mut interfaces: Vec<(u32, u64)> = Vec::new();
+    let mut reader = Reader::new(data, false);
+
+    let mut offset = 0;
+    while offset + 12 <= data.len() {
+        let block_type = reader.u32(offset)?;
+        if block_type == PCAPNG_SECTION_HEADER_BLOCK {
+            // Every section may have a different byte order
+            let byte_order = Reader::new(data, false).u32(offset + 8)?;
+            reader = Reader::new(data, byte_order != PCAPNG_BYTE_ORDER_MAGIC);
+            interfaces.clear();
+        }
+
+        let block_len = reader.u32(offset + 4)? as usize;
+        if block_len < 12 || !block_len.is_multiple_of(4) {
+            bail!("Invalid block length {block_len} at offset {offset}");
+        }
+        let body = reader.sub(offset + 8, block_len - 12)?;
+
+        match block_type {
+            PCAPNG_INTERFACE_DESCRIPTION_BLOCK => {
+                let link_type = u32::from(body.u16(0)?);
+                let mut units_per_second = 1_000_000;
+                body.options(8, |code, value| {
+                    if let (PCAPNG_OPT_IF_TSRESOL, [resolution]) = (code, value) {
+                        units_per_second = if resolution & 0x80 == 0 {
+                            10u64.saturating_pow(u32::from(*resolution))
+                        } else {
+                            2u64.saturating_pow(u32::from(resolution & 0x7F))
+                        };
+                    }
+                });
+                interfaces.push((link_type, units_per_second));
+            }
+            PCAPNG_ENHANCED_PACKET_BLOCK | PCAPNG_PACKET_BLOCK => {
+                let interface = if block_type == PCAPNG_PACKET_BLOCK {
+                    usize::from(body.u16(0)?)
+                } else {
+                    body.u32(0)? as usize
+                };
+                let Some(&(link_type, units_per_second)) = interfaces.get(interface) else {
+                    bail!("Packet of unknown interface {interface} at offset {offset}");
+                };
+                let ticks = u64::from(body.u32(4)?) << 32 | u64::from(body.u32(8)?);
+                let captured_len = body.u32(12)? as usize;
+                let packet = body.bytes(20, captured_len)?;
+                assembler.packet(link_type, timestamp(ticks, units_per_second), packet);
+            }
+            PCAPNG_SIMPLE_PACKET_BLOCK => {
+                let Some(&(link_type, _)) = interfaces.first() else {
+                    bail!("Packet of unknown interface 0 at offset {offset}");
+                };
+                // Simple packet blocks have no timestamp, so they are placed at the previous packet
+                let captured_len = (body.u32(0)? as usize).min(body.data.len().saturating_sub(4));
+                let timestamp = assembler.last_timestamp.unwrap_or_default();
+                assembler.packet(link_type, timestamp, body.bytes(4, captured_len)?);
+            }
+            _ => {}
+        }
+
+        offset += block_len;
+    }
+    Ok(())
+}
+
+fn timestamp(ticks: u64, units_per_second: u64) -> DateTime<Local> {
+    let seconds = ticks / units_per_second;
+    let nanos = (ticks % units_per_second) as u128 * 1_000_000_000 / units_per_second as u128;
+    DateTime::from_timestamp(seconds as i64, nanos as u32)
+        .unwrap_or_default()
+        .with_timezone(&Local)
+}
+
+/// Bounds checked reads of integers in either byte order.
+#[derive(Clone, Copy)]
+struct Reader<'a> {
+    data: &'a [u8],
+    swapped: bool,
+}
+
+impl<'a> Reader<'a> {
+    /// `swapped` reads big endian integers instead of little endian ones.
+    fn new(data: &'a [u8], swapped: bool) -> Self {
+        Self { data, swapped }
+    }
+
+    fn bytes(&self, offset: usize, len: usize) -> anyhow::Result<&'a [u8]> {
+        self.data
+            .get(offset..offset.saturating_add(len))
+            .with_context(|| format!("Capture is truncated at offset {offset}"))
+    }
+
+    fn sub(&self, offset: usize, len: usize) -> anyhow::Result<Self> {
+        Ok(Self::new(self.bytes(offset, len)?, self.swapped))
+    }
+
+    fn u16(&self, offset: usize) -> anyhow::Result<u16> {
+        let bytes = self.bytes(offset, 2)?.try_into().unwrap();
+        Ok(match self.swapped {
+            false => u16::from_le_bytes(bytes),
+            true => u16::from_be_bytes(bytes),
+        })
+    }
+
+    fn u32(&self, offset: usize) -> anyhow::Result<u32> {
+        let bytes = self.bytes(offset, 4)?.try_into().unwrap();
+        Ok(match self.swapped {
+            false => u32::from_le_bytes(bytes),
+            true => u32::from_be_bytes(bytes),
+        })
+    }
+
+    /// Calls `f` with the code and value of every option starting at `offset`.
+    fn options(&self, mut offset: usize, mut f: impl FnMut(u16, &[u8])) {
+        while let (Ok(code), Ok(len)) = (self.u16(offset), self.u16(offset + 2)) {
+            let Ok(value) = self.bytes(offset + 4, usize::from(len)) else {
+                break;
+            };
+            if code == 0 {
+                break;
+            }
+            f(code, value);
+            offset += 4 + usize::from(len).next_multiple_of(4);
+        }
+    }
+}
+
+/// A decoded TCP segment or UDP datagram.
+struct Segment<'a> {
+    protocol: Protocol,
+    source: SocketAddr,
+    destination: SocketAddr,
+    seq: u32,
+    flags: u8,
+    payload: &'a [u8],
+}
+
+/// Groups segments into flows and reassembles TCP streams.
+#[derive(Default)]
+struct Assembler {
+    flows: Vec<FlowState>,
+    /// Index into `flows` of the current flow between two endpoints, keyed with the lower address first
+    by_endpoints: HashMap<(Protocol, SocketAddr, SocketAddr), usize>,
+    last_timestamp: Option<DateTime<Local>>,
+    unsupported_link_type: Option<u32>,
+}
+
+struct FlowState {
+    flow: Flow,
+    /// Next expected sequence number from the client and server
+    next_seq: [Option<u32>; 2],
+}
+
+impl Assembler {
+    fn packet(&mut self, link_type: u32, timestamp: DateTime<Local>, packet: &[u8]) {
+        self.last_timestamp = Some(timestamp);
+
+        let ip = match link_type {
+            LINKTYPE_ETHERNET => {
+                let mut offset = 12;
+                let mut ethertype = read_u16_be(packet, offset);
+                while ethertype == Some(ETHERTYPE_VLAN) {
+                    offset += 4;
+                    ethertype = read_u16_be(packet, offset);
+                }
+                match ethertype {
+                    Some(ETHERTYPE_IPV4 | ETHERTYPE_IPV6) => packet.get(offset + 2..),
+                    _ => None,
+                }
+            }
+            LINKTYPE_NULL => packet.get(4..),
+            LINKTYPE_RAW | LINKTYPE_IPV4 | LINKTYPE_IPV6 => Some(packet),
+            LINKTYPE_LINUX_SLL => match read_u16_be(packet, 14) {
+                Some(ETHERTYPE_IPV4 | ETHERTYPE_IPV6) => packet.get(16..),
+                _ => None,
+            },
+            LINKTYPE_LINUX_SLL2 => match read_u16_be(packet, 0) {
+                Some(ETHERTYPE_IPV4 | ETHERTYPE_IPV6) => packet.get(20..),
+                _ => None,
+            },
+            _ => {
+                self.unsupported_link_type = Some(link_type);
+                None
+            }
+        };
+
+        if let Some(segment) = ip.and_then(decode_ip) {
+            self.segment(timestamp, segment);
+        }
+    }
+
+    fn segment(&mut self, timestamp: DateTime<Local>, segment: Segment) {
+        let key = if segment.source <= segment.destination {
+            (segment.protocol, segment.source, segment.destination)
+        } else {
+            (segment.protocol, segment.destination, segment.source)
+        };
+        let syn = segment.flags & TCP_SYN != 0;
+        let ack = segment.flags & TCP_ACK != 0;
+
+        let existing = self.by_endpoints.get(&key).copied();
+        // A new connection between the same endpoints starts a new flow
+        let reused = existing.is_some_and(|i| {
+            let state = &self.flows[i];
+            syn && !ack && (state.flow.end.is_some() || !state.flow.messages.is_empty())
+        });
+        let index = match existing {
+            Some(index) if !reused => index,
+            _ => {
+                // Whoever sends a SYN-ACK is the server, otherwise the first to send is the client
+                let (client, server) = if syn && ack {
+                    (segment.destination, segment.source)
+                } else {
+                    (segment.source, segment.destination)
+                };
+                self.flows.push(FlowState {
+                    flow: Flow {
+                        protocol: segment.protocol,
+                        client,
+                        server,
+                        messages: Vec::new(),
+                        start: timestamp,
+                        end: None,
+                    },
+                    next_seq: [None; 2],
+                });
+                self.by_endpoints.insert(key, self.flows.len() - 1);
+                self.flows.len() - 1
+            }
+        };
+
+        let state = &mut self.flows[index];
+        let direction = if segment.source == state.flow.client {
+            Direction::ClientToServer
+        } else {
+            Direction::ServerToClient
+        };
+
+        let mut payload = segment.payload;
+        if segment.protocol == Protocol::Tcp {
+            let next_seq = &mut state.next_seq[direction as usize];
+            let mut seq = segment.seq;
+            if syn {
+                seq = seq.wrapping_add(1);
+                next_seq.get_or_insert(seq);
+            }
+            let expected = *next_seq.get_or_insert(seq);
+
+            // Drop whatever was already received, e.g. retransmissions. Gaps are kept as they are.
+            let behind = expected.wrapping_sub(seq) as i32;
+            if behind > 0 {
+                payload = payload.get(behind as usize..).unwrap_or_default();
+                seq = expected;
+            }
+            if !payload.is_empty() {
+                *next_seq = Some(seq.wrapping_add(payload.len() as u32));
+            }
+
+            if segment.flags & (TCP_FIN | TCP_RST) != 0 {
+                state.flow.end = Some(timestamp);
+            }
+        }
+
+        if payload.is_empty() {
+            return;
+        }
+        match state.flow.messages.last_mut() {
+            Some(last) if segment.protocol == Protocol::Tcp && last.direction == direction => {
+                last.data.extend_from_slice(payload);
+            }
+            _ => state.flow.messages.push(Message {
+                timestamp,
+                direction,
+                data: payload.to_vec(),
+            }),
+        }
+    }
+
+    fn finish(self) -> Vec<Flow> {
+        self.flows
+            .into_iter()
+            .map(|state| state.flow)
+            .filter(|flow| !flow.messages.is_empty())
+            .collect()
+    }
+}
+
+fn read_u16_be(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(
+        data.get(offset..offset + 2)?.try_into().unwrap(),
+    ))
+}
+
+fn read_u32_be(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        data.get(offset..offset + 4)?.try_into().unwrap(),
+    ))
+}
+
+fn decode_ip(packet: &[u8]) -> Option<Segment<'_>> {
+    let (source, destination, mut protocol, mut payload) = match packet.first()? >> 4 {
+        4 => {
+            let header_len = usize::from(packet[0] & 0x0F) * 4;
+            let total_len = usize::from(read_u16_be(packet, 2)?);
+            // Fragments other than the first don't start with a transport header
+            if read_u16_be(packet, 6)? & 0x1FFF != 0 {
+                return None;
+            }
+            let source: [u8; 4] = packet.get(12..16)?.try_into().unwrap();
+            let destination: [u8; 4] = packet.get(16..20)?.try_into().unwrap();
+            (
+                IpAddr::from(Ipv4Addr::from(source)),
+                IpAddr::from(Ipv4Addr::from(destination)),
+                packet[9],
+                // Ethernet frames may be padded beyond the IP packet
+                packet.get(header_len..total_len.min(packet.len()))?,
+            )
+        }
+        6 => {
+            let payload_len = usize::from(read_u16_be(packet, 4)?);
+            let source: [u8; 16] = packet.get(8..24)?.try_into().unwrap();
+            let destination: [u8; 16] = packet.get(24..40)?.try_into().unwrap();
+            (
+                IpAddr::from(Ipv6Addr::from(source)),
+                IpAddr::from(Ipv6Addr::from(destination)),
+                packet[6],
+                packet.get(40..(40 + payload_len).min(packet.len()))?,
+            )
+        }
+        _ => return None,
+    };
+
+    // Skip IPv6 extension headers
+    loop {
+        match protocol {
+            // Hop-by-hop, routing and destination options
+            0 | 43 | 60 => {
+                let len = (usize::from(*payload.get(1)?) + 1) * 8;
+                protocol = payload[0];
+                payload = payload.get(len..)?;
+            }
+            // Fragment
+            44 => {
+                if read_u16_be(payload, 2)? & 0xFFF8 != 0 {
+                    return None;
+                }
+                protocol = payload[0];
+                payload = payload.get(8..)?;
+            }
+            _ => break,
+        }
+    }
+
+    let source_port = read_u16_be(payload, 0)?;
+    let destination_port = read_u16_be(payload, 2)?;
+    let (protocol, seq, flags, payload) = match protocol {
+        IP_PROTOCOL_TCP => {
+            let header_len = usize::from(payload.get(12)? >> 4) * 4;
+            (
+                Protocol::Tcp,
+                read_u32_be(payload, 4)?,
+                *payload.get(13)?,
+                payload.get(header_len..)?,
+            )
+        }
+        IP_PROTOCOL_UDP => {
+            let len = usize::from(read_u16_be(payload, 4)?);
+            (
+                Protocol::Udp,
+                0,
+                0,
+                payload.get(8..len.clamp(8, payload.len()))?,
+            )
+        }
+        _ => return None,
+    };
+
+    Some(Segment {
+        protocol,
+        source: SocketAddr::new(source, source_port),
+        destination: SocketAddr::new(destination, destination_port),
+        seq,
+        flags,
+        payload,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLIENT: &str = "10.0.0.1:50000";
+    const SERVER: &str = "10.0.0.2:80";
+
+    fn ipv4(src: &str, dst: &str, protocol: u8, transport: Vec<u8>) -> Vec<u8> {
+        let (src, dst): (SocketAddr, SocketAddr) = (src.parse().unwrap(), dst.parse().unwrap());
+        let (IpAddr::V4(src), IpAddr::V4(dst)) = (src.ip(), dst.ip()) else {
+            unreachable!()
+        };
+        let mut packet = vec![0x45, 0];
+        packet.extend(((20 + transport.len()) as u16).to_be_bytes());
+        packet.extend([0, 0, 0x40, 0, 64, protocol, 0, 0]);
+        packet.extend(src.octets());
+        packet.extend(dst.octets());
+        packet.extend(transport);
+        packet
+    }
+
+    fn tcp(src: &str, dst: &str, seq: u32, flags: u8, payload: &[u8]) -> Vec<u8> {
+        let ports = |addr: &str| addr.parse::<SocketAddr>().unwrap().port().to_be_bytes();
+        let mut segment = Vec::new();
+        segment.extend(ports(src));
+        segment.extend(ports(dst));
+        segment.extend(seq.to_be_bytes());
+        segment.extend(0u32.to_be_bytes());
+        segment.extend([5 << 4, flags, 0xFF, 0xFF, 0, 0, 0, 0]);
+        segment.extend(payload);
+        ipv4(src, dst, IP_PROTOCOL_TCP, segment)
+    }
+
+    fn udp(src: &str, dst: &str, payload: &[u8]) -> Vec<u8> {
+        let ports = |addr: &str| addr.parse::<SocketAddr>().unwrap().port().to_be_bytes();
+        let mut datagram = Vec::new();
+        datagram.extend(ports(src));
+        datagram.extend(ports(dst));
+        datagram.extend(((8 + payload.len()) as u16).to_be_bytes());
+        datagram.extend([0, 0]);
+        datagram.extend(payload);
+        ipv4(src, dst, IP_PROTOCOL_UDP, datagram)
+    }
+
+    /// A pcap file of raw IP `packets`, a second apart, in big endian if `swapped`.
+    fn pcap(packets: &[Vec<u8>], swapped: bool) -> Vec<u8> {
+        let u32 = |value: u32| match swapped {
+            false => value.to_le_bytes(),
+            true => value.to_be_bytes(),
+        };
+        let u16 = |value: u16| match swapped {
+            false => value.to_le_bytes(),
+            true => value.to_be_bytes(),
+        };
+        let mut file = Vec::new();
+        file.extend(u32(PCAP_MAGIC_MICROS));
+        file.extend(u16(2));
+        file.extend(u16(4));
+        file.extend(u32(0));
+        file.extend(u32(0));
+        file.extend(u32(65535));
+        file.extend(u32(LINKTYPE_RAW));
+        for (i, packet) in packets.iter().enumerate() {
+            file.extend(u32(i as u32));
+            file.extend(u32(0));
+            file.extend(u32(packet.len() as u32));
+            file.extend(u32(packet.len() as u32));
+            file.extend(packet);
+        }
+        file
+    }
+
+    fn messages(flow: &Flow) -> Vec<(Direction, &[u8])> {
+        flow.messages
+            .iter()
+            .map(|m| (m.direction, &m.data[..]))
+            .collect()
+    }
+
+    fn error(data: &[u8]) -> String {
+        format!("{:#}", parse(data).unwrap_err())
+    }
+
+    #[test]
+    fn tcp_streams_are_reassembled() {
+        let flows = parse(&pcap(
+            &[
+                tcp(CLIENT, SERVER, 100, TCP_SYN, &[]),
+                tcp(SERVER, CLIENT, 500, TCP_SYN | TCP_ACK, &[]),
+                tcp(CLIENT, SERVER, 101, TCP_ACK, &[]),
+                tcp(CLIENT, SERVER, 101, TCP_ACK, b"hel"),
+                tcp(CLIENT, SERVER, 104, TCP_ACK, b"lo"),
+                // A retransmission, then one that overlaps what was already received
+                tcp(CLIENT, SERVER, 101, TCP_ACK, b"hel"),
+                tcp(CLIENT, SERVER, 104, TCP_ACK, b"lo world"),
+                tcp(SERVER, CLIENT, 501, TCP_ACK, b"ok"),
+                tcp(CLIENT, SERVER, 112, TCP_FIN | TCP_ACK, &[]),
+            ],
+            false,
+        ))
+        .unwrap();
+
+        assert_eq!(flows.len(), 1);
+        let flow = &flows[0];
+        assert_eq!(flow.protocol, Protocol::Tcp);
+        assert_eq!(flow.client, CLIENT.parse().unwrap());
+        assert_eq!(flow.server, SERVER.parse().unwrap());
+        assert_eq!(
+            messages(flow),
+            [
+                (Direction::ClientToServer, &b"hello world"[..]),
+                (Direction::ServerToClient, b"ok"),
+            ]
+        );
+        assert_eq!(flow.start, timestamp(0, 1));
+        assert_eq!(flow.end, Some(timestamp(8, 1)));
+        assert_eq!(flow.messages[1].timestamp, timestamp(7, 1));
+    }
+
+    #[test]
+    fn sender_of_syn_ack_is_the_server() {
+        // The capture started after the client's SYN
+        let flows = parse(&pcap(
+            &[
+                tcp(SERVER, CLIENT, 500, TCP_SYN | TCP_ACK, &[]),
+                tcp(SERVER, CLIENT, 501, TCP_ACK, b"banner"),
+            ],
+            false,
+        ))
+        .unwrap();
+        assert_eq!(flows[0].client, CLIENT.parse().unwrap());
+        assert_eq!(
+            messages(&flows[0]),
+            [(Direction::ServerToClient, &b"banner"[..])]
+        );
+    }
+
+    #[test]
+    fn reconnecting_starts_a_new_flow() {
+        let flows = parse(&pcap(
+            &[
+                tcp(CLIENT, SERVER, 100, TCP_SYN, &[]),
+                tcp(CLIENT, SERVER, 101, TCP_ACK, b"first"),
+                tcp(CLIENT, SERVER, 106, TCP_FIN | TCP_ACK, &[]),
+                tcp(CLIENT, SERVER, 900, TCP_SYN, &[]),
+                tcp(CLIENT, SERVER, 901, TCP_ACK, b"second"),
+                // Handshakes without data don't make a flow
+                tcp("10.0.0.3:1", SERVER, 0, TCP_SYN, &[]),
+            ],
+            false,
+        ))
+        .unwrap();
+        let data: Vec<_> = flows
+            .iter()
+            .map(|flow| &flow.messages[0].data[..])
+            .collect();
+        assert_eq!(data, [&b"first"[..], b"second"]);
+        assert!(flows[0].end.is_some());
+        assert!(flows[1].end.is_none());
+    }
+
+    #[test]
+    fn udp_datagrams_are_kept_apart() {
+        let flows = parse(&pcap(
+            &[
+                udp(CLIENT, SERVER, b"one"),
+                udp(CLIENT, SERVER, b"two"),
+                udp(SERVER, CLIENT, b"three"),
+            ],
+            true,
+        ))
+        .unwrap();
+        assert_eq!(flows.len(), 1);
+        assert_eq!(flows[0].protocol, Protocol::Udp);
+        assert_eq!(
+            messages(&flows[0]),
+            [
+                (Direction::ClientToServer, &b"one"[..]),
+                (Direction::ClientToServer, b"two"),
+                (Direction::ServerToClient, b"three"),
+            ]
+        );
+    }
+
+    #[test]
+    fn malformed_packets_are_skipped() {
+        let mut truncated_header = tcp(CLIENT, SERVER, 101, TCP_ACK, b"lost");
+        truncated_header.truncate(30);
+        let flows = parse(&pcap(
+            &[
+                vec![0x45],
+                vec![0x70; 40],
+                truncated_header,
+                tcp(CLIENT, SERVER, 101, TCP_ACK, b"kept"),
+            ],
+            false,
+        ))
+        .unwrap();
+        assert_eq!(flows.len(), 1);
+        assert_eq!(flows[0].messages[0].data, b"kept");
+    }
+
+    #[test]
+    fn broken_files_fail() {
+        assert!(error(&[]).contains("truncated"));
+        assert_eq!(error(b"GIF89a"), "Not a pcap or pcapng file");
+
+        let file = pcap(&[tcp(CLIENT, SERVER, 101, TCP_ACK, b"data")], false);
+        assert!(error(&file[..file.len() - 1]).contains("truncated"));
+    }
+
+    #[test]
+    fn broken_pcapng_blocks_fail() {
+        let mut section = Vec::new();
+        section.extend(PCAPNG_SECTION_HEADER_BLOCK.to_le_bytes());
+        section.extend(28u32.to_le_bytes());
+        section.extend(PCAPNG_BYTE_ORDER_MAGIC.to_le_bytes());
+        section.extend([1, 0, 0, 0]);
+        section.extend((-1i64).to_le_bytes());
+        section.extend(28u32.to_le_bytes());
+
+        let mut interface = Vec::new();
+        interface.extend(PCAPNG_INTERFACE_DESCRIPTION_BLOCK.to_le_bytes());
+        interface.extend(20u32.to_le_bytes());
+        interface.extend((LINKTYPE_RAW as u16).to_le_bytes());
+        interface.extend([0, 0, 0, 0, 0, 0]);
+        interface.extend(20u32.to_le_bytes());
+
+        let packet = tcp(CLIENT, SERVER, 101, TCP_ACK, b"data");
+        let enhanced_packet = |interface: u32| {
+            let len = 32 + packet.len().next_multiple_of(4);
+            let mut block = Vec::new();
+            block.extend(PCAPNG_ENHANCED_PACKET_BLOCK.to_le_bytes());
+            block.extend((len as u32).to_le_bytes());
+            block.extend(interface.to_le_bytes());
+            block.extend([0; 8]);
+            block.extend((packet.len() as u32).to_le_bytes());
+            block.extend((packet.len() as u32).to_le_bytes());
+            block.extend(&packet);
+            block.resize(len - 4, 0);
+            block.extend((len as u32).to_le_bytes());
+            block
+        };
+
+        let valid = [&section[..], &interface, &enhanced_packet(0)].concat();
+        assert_eq!(parse(&valid).unwrap()[0].messages[0].data, b"data");
+
+        let unknown_interface = [&section[..], &interface, &enhanced_packet(1)].concat();
+        assert!(error(&unknown_interface).contains("unknown interface 1"));
+
+        let mut bad_length = valid.clone();
+        bad_length[32..36].copy_from_slice(&7u32.to_le_bytes());
+        assert!(error(&bad_length).contains("Invalid block length 7"));
+
+        assert!(error(&valid[..valid.len() - 8]).contains("truncated"));
+    }
+}
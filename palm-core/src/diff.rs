@@ -0,0 +1,123 @@
+//! Byte-level diffs of two payloads, aligned so that the bytes both have in common line up.
+
+use std::time::{Duration, Instant};
+
+use similar::{capture_diff_slices_deadline, Algorithm, DiffOp};
+
+/// How long to look for the smallest diff before settling for a coarser one, as that gets slow
+/// for large payloads that have little in common.
+const DEADLINE: Duration = Duration::from_millis(500);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Change {
+    Equal,
+    /// Only the left side has the byte
+    Deleted,
+    /// Only the right side has the byte
+    Inserted,
+    /// Both sides have a different byte
+    Changed,
+}
+
+/// A position of the aligned diff. A side is `None` where only the other side has a byte.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cell {
+    pub left: Option<u8>,
+    pub right: Option<u8>,
+    pub change: Change,
+}
+
+/// Aligns `left` and `right` so that equal runs end up at the same positions.
+pub fn diff(left: &[u8], right: &[u8]) -> Vec<Cell> {
+    let ops = capture_diff_slices_deadline(
+        Algorithm::Myers,
+        left,
+        right,
+        Some(Instant::now() + DEADLINE),
+    );
+
+    let mut cells = Vec::with_capacity(left.len().max(right.len()));
+    for op in ops {
+        let (old, new) = (op.old_range(), op.new_range());
+        let change = match op {
+            DiffOp::Equal { .. } => Change::Equal,
+            DiffOp::Delete { .. } => Change::Deleted,
+            DiffOp::Insert { .. } => Change::Inserted,
+            DiffOp::Replace { .. } => Change::Changed,
+        };
+        for i in 0..old.len().max(new.len()) {
+            let left = left[old.clone()].get(i).copied();
+            let right = right[new.clone()].get(i).copied();
+            // Whatever one side of a replacement has more of is effectively inserted or deleted
+            let change = match (left, right) {
+                (Some(_), None) => Change::Deleted,
+                (None, Some(_)) => Change::Inserted,
+                _ => change,
+            };
+            cells.push(Cell {
+                left,
+                right,
+                change,
+            });
+        }
+    }
+    cells
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The diff as a line per side plus one of change markers, with `.` where a side has no byte.
+    fn render(left: &str, right: &str) -> [String; 3] {
+        let cells = diff(left.as_bytes(), right.as_bytes());
+        let side = |byte: Option<u8>| byte.map_or('.', char::from);
+        [
+            cells.iter().map(|c| side(c.left)).collect(),
+            cells.iter().map(|c| side(c.right)).collect(),
+            cells
+                .iter()
+                .map(|c| match c.change {
+                    Change::Equal => '=',
+                    Change::Deleted => '-',
+                    Change::Inserted => '+',
+                    Change::Changed => '~',
+                })
+                .collect(),
+        ]
+    }
+
+    #[test]
+    fn equal() {
+        assert_eq!(render("abc", "abc"), ["abc", "abc", "==="]);
+        assert_eq!(render("", ""), ["", "", ""]);
+    }
+
+    #[test]
+    fn insert() {
+        assert_eq!(render("abc", "abXYc"), ["ab..c", "abXYc", "==++="]);
+        assert_eq!(render("", "ab"), ["..", "ab", "++"]);
+    }
+
+    #[test]
+    fn delete() {
+        assert_eq!(render("abXYc", "abc"), ["abXYc", "ab..c", "==--="]);
+        assert_eq!(render("ab", ""), ["ab", "..", "--"]);
+    }
+
+    #[test]
+    fn replace() {
+        assert_eq!(render("abXc", "abYc"), ["abXc", "abYc", "==~="]);
+        // The longer side of a replacement has the rest to itself
+        assert_eq!(render("aXYc", "aZc"), ["aXYc", "aZ.c", "=~-="]);
+        assert_eq!(render("aXc", "aYZc"), ["aX.c", "aYZc", "=~+="]);
+    }
+
+    #[test]
+    fn equal_runs_line_up() {
+        let [left, right, changes] = render("header 1 body", "header 22 body!");
+        assert_eq!(left.replace('.', ""), "header 1 body");
+        assert_eq!(right.replace('.', ""), "header 22 body!");
+        assert!(changes.starts_with("=======") && changes.ends_with("=====+"));
+    }
+}
@@ -0,0 +1,573 @@
+//! Reading bytes as numbers, times and text, and writing such values back as bytes.
+
+use anyhow::{anyhow, bail, Context};
+use std::fmt::{Display, LowerExp};
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Endian {
+    #[default]
+    Little,
+    Big,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Kind {
+    U8,
+    I8,
+    U16,
+    I16,
+    U32,
+    I32,
+    U64,
+    I64,
+    F32,
+    F64,
+    /// Unsigned LEB128, as used by protobuf
+    Varint,
+    /// Zigzag encoded LEB128, as used by protobuf's `sint` types
+    ZigzagVarint,
+    /// Seconds since the epoch in 32 bits
+    UnixSeconds,
+    /// Milliseconds since the epoch in 64 bits
+    UnixMillis,
+    Utf8,
+    Utf16,
+    Bits,
+}
+
+impl Kind {
+    pub const ALL: [Kind; 17] = [
+        Kind::U8,
+        Kind::I8,
+        Kind::U16,
+        Kind::I16,
+        Kind::U32,
+        Kind::I32,
+        Kind::U64,
+        Kind::I64,
+        Kind::F32,
+        Kind::F64,
+        Kind::Varint,
+        Kind::ZigzagVarint,
+        Kind::UnixSeconds,
+        Kind::UnixMillis,
+        Kind::Utf8,
+        Kind::Utf16,
+        Kind::Bits,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Kind::U8 => "u8",
+            Kind::I8 => "i8",
+            Kind::U16 => "u16",
+            Kind::I16 => "i16",
+            Kind::U32 => "u32",
+            Kind::I32 => "i32",
+            Kind::U64 => "u64",
+            Kind::I64 => "i64",
+            Kind::F32 => "f32",
+            Kind::F64 => "f64",
+            Kind::Varint => "varint",
+            Kind::ZigzagVarint => "varint (zigzag)",
+            Kind::UnixSeconds => "Unix time (s)",
+            Kind::UnixMillis => "Unix time (ms)",
+            Kind::Utf8 => "UTF-8",
+            Kind::Utf16 => "UTF-16",
+            Kind::Bits => "bits",
+        }
+    }
+}
+
+/// How many bytes of bits are read at most.
+const MAX_BITS_BYTES: usize = 8;
+
+/// Longest LEB128 encoding of a `u64`.
+const MAX_VARINT_LEN: usize = 10;
+
+const TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.3f";
+
+macro_rules! read_number {
+    ($t:ty, $bytes:expr, $endian:expr) => {{
+        const SIZE: usize = std::mem::size_of::<$t>();
+        let bytes: [u8; SIZE] = $bytes.get(..SIZE)?.try_into().unwrap();
+        let value = match $endian {
+            Endian::Little => <$t>::from_le_bytes(bytes),
+            Endian::Big => <$t>::from_be_bytes(bytes),
+        };
+        (value, SIZE)
+    }};
+}
+
+macro_rules! write_number {
+    ($value:expr, $endian:expr) => {{
+        let value = $value;
+        match $endian {
+            Endian::Little => value.to_le_bytes().to_vec(),
+            Endian::Big => value.to_be_bytes().to_vec(),
+        }
+    }};
+}
+
+/// Reads the start of `bytes` as `kind`, returning the value as text and how many bytes it took.
+/// Text and bits take all of `bytes`. `None` if there aren't enough bytes for the value.
+pub fn read(kind: Kind, bytes: &[u8], endian: Endian) -> Option<(String, usize)> {
+    let (text, len) = match kind {
+        Kind::U8 => to_text(read_number!(u8, bytes, endian)),
+        Kind::I8 => to_text(read_number!(i8, bytes, endian)),
+        Kind::U16 => to_text(read_number!(u16, bytes, endian)),
+        Kind::I16 => to_text(read_number!(i16, bytes, endian)),
+        Kind::U32 => to_text(read_number!(u32, bytes, endian)),
+        Kind::I32 => to_text(read_number!(i32, bytes, endian)),
+        Kind::U64 => to_text(read_number!(u64, bytes, endian)),
+        Kind::I64 => to_text(read_number!(i64, bytes, endian)),
+        Kind::F32 => {
+            let (value, len) = read_number!(f32, bytes, endian);
+            (format_float(value), len)
+        }
+        Kind::F64 => {
+            let (value, len) = read_number!(f64, bytes, endian);
+            (format_float(value), len)
+        }
+        Kind::Varint => to_text(read_varint(bytes)?),
+        Kind::ZigzagVarint => {
+            let (value, len) = read_varint(bytes)?;
+            to_text(((value >> 1) as i64 ^ -((value & 1) as i64), len))
+        }
+        Kind::UnixSeconds => {
+            let (seconds, len) = read_number!(u32, bytes, endian);
+            let time = DateTime::from_timestamp(seconds.into(), 0)?;
+            (time.format(TIME_FORMAT).to_string(), len)
+        }
+        Kind::UnixMillis => {
+            let (millis, len) = read_number!(i64, bytes, endian);
+            let time = DateTime::from_timestamp_millis(millis)?;
+            (time.format(TIME_FORMAT).to_string(), len)
+        }
+        Kind::Utf8 => (escape(&String::from_utf8_lossy(bytes)), bytes.len()),
+        Kind::Utf16 => {
+            let units = bytes.chunks_exact(2).map(|unit| match endian {
+                Endian::Little => u16::from_le_bytes([unit[0], unit[1]]),
+                Endian::Big => u16::from_be_bytes([unit[0], unit[1]]),
+            });
+            let text: String = char::decode_utf16(units)
+                .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                .collect();
+            (escape(&text), bytes.len() / 2 * 2)
+        }
+        Kind::Bits => {
+            let bytes = &bytes[..bytes.len().min(MAX_BITS_BYTES)];
+            let bits: Vec<String> = bytes.iter().map(|byte| format!("{byte:08b}")).collect();
+            (bits.join(" "), bytes.len())
+        }
+    };
+    (len > 0).then_some((text, len))
+}
+
+/// The bytes of `text` read as a value of `kind`, the reverse of [`read`].
+pub fn write(kind: Kind, text: &str, endian: Endian) -> anyhow::Result<Vec<u8>> {
+    let bytes = match kind {
+        Kind::U8 => write_number!(parse_int::<u8>(text)?, endian),
+        Kind::I8 => write_number!(parse_int::<i8>(text)?, endian),
+        Kind::U16 => write_number!(parse_int::<u16>(text)?, endian),
+        Kind::I16 => write_number!(parse_int::<i16>(text)?, endian),
+        Kind::U32 => write_number!(parse_int::<u32>(text)?, endian),
+        Kind::I32 => write_number!(parse_int::<i32>(text)?, endian),
+        Kind::U64 => write_number!(parse_int::<u64>(text)?, endian),
+        Kind::I64 => write_number!(parse_int::<i64>(text)?, endian),
+        Kind::F32 => write_number!(text.trim().parse::<f32>()?, endian),
+        Kind::F64 => write_number!(text.trim().parse::<f64>()?, endian),
+        Kind::Varint => write_varint(parse_int::<u64>(text)?),
+        Kind::ZigzagVarint => {
+            let value = parse_int::<i64>(text)?;
+            write_varint(((value << 1) ^ (value >> 63)) as u64)
+        }
+        Kind::UnixSeconds => {
+            let seconds = u32::try_from(parse_time(text)?.timestamp())
+                .context("Time doesn't fit in 32 bits")?;
+            write_number!(seconds, endian)
+        }
+        Kind::UnixMillis => write_number!(parse_time(text)?.timestamp_millis(), endian),
+        Kind::Utf8 => unescape(text)?.into_bytes(),
+        Kind::Utf16 => unescape(text)?
+            .encode_utf16()
+            .flat_map(|unit| write_number!(unit, endian))
+            .collect(),
+        Kind::Bits => {
+            let digits: Vec<char> = text.chars().filter(|c| !c.is_whitespace()).collect();
+            if digits.is_empty() || !digits.len().is_multiple_of(8) {
+                bail!("Bits must be whole bytes of 8 digits");
+            }
+            digits
+                .chunks(8)
+                .map(|byte| {
+                    u8::from_str_radix(&byte.iter().collect::<String>(), 2)
+                        .map_err(|_| anyhow!("Bits can only be 0 or 1"))
+                })
+                .collect::<anyhow::Result<_>>()?
+        }
+    };
+    Ok(bytes)
+}
+
+fn to_text<T: ToString>((value, len): (T, usize)) -> (String, usize) {
+    (value.to_string(), len)
+}
+
+/// Formats floats that would take many digits with an exponent.
+fn format_float<T: Copy + Display + LowerExp + Into<f64>>(value: T) -> String {
+    let magnitude = value.into().abs();
+    if magnitude == 0. || !magnitude.is_finite() || (1e-4..1e16).contains(&magnitude) {
+        value.to_string()
+    } else {
+        format!("{value:e}")
+    }
+}
+
+fn read_varint(bytes: &[u8]) -> Option<(u64, usize)> {
+    let mut value = 0u64;
+    for (i, &byte) in bytes.iter().take(MAX_VARINT_LEN).enumerate() {
+        let bits = u64::from(byte & 0x7f);
+        if i == MAX_VARINT_LEN - 1 && bits > 1 {
+            return None;
+        }
+        value |= bits << (7 * i);
+        if byte & 0x80 == 0 {
+            return Some((value, i + 1));
+        }
+    }
+    None
+}
+
+fn write_varint(mut value: u64) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(byte);
+            return bytes;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+/// Parses a decimal or `0x` prefixed hex integer that fits in `T`.
+fn parse_int<T: TryFrom<i128>>(text: &str) -> anyhow::Result<T> {
+    let text = text.trim().replace('_', "");
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text.as_str()),
+    };
+    let (digits, radix) = match digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        Some(hex) => (hex, 16),
+        None => (digits, 10),
+    };
+    // Only the leading minus is a sign, so that the value can always be negated
+    if digits.starts_with(['+', '-']) {
+        bail!("{text:?} isn't an integer");
+    }
+    let value = i128::from_str_radix(digits, radix)
+        .with_context(|| format!("{text:?} isn't an integer"))?;
+    let value = if negative { -value } else { value };
+    T::try_from(value)
+        .map_err(|_| anyhow!("{value} is out of range for {}", std::any::type_name::<T>()))
+}
+
+/// Parses a UTC time as formatted by [`read`] or as RFC 3339.
+fn parse_time(text: &str) -> anyhow::Result<DateTime<Utc>> {
+    let text = text.trim();
+    if let Ok(time) = DateTime::parse_from_rfc3339(text) {
+        return Ok(time.to_utc());
+    }
+    NaiveDateTime::parse_from_str(text, TIME_FORMAT)
+        .or_else(|_| NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S"))
+        .map(|time| time.and_utc())
+        .with_context(|| format!("{text:?} isn't a time like 2024-01-31 12:00:00"))
+}
+
+/// Escapes control characters and backslashes so that text fits on one line.
+fn escape(text: &str) -> String {
+    text.chars()
+        .flat_map(|c| match c {
+            '\\' | '\n' | '\r' | '\t' => c.escape_default().collect::<Vec<_>>(),
+            c if c.is_control() => c.escape_unicode().collect(),
+            c => vec![c],
+        })
+        .collect()
+}
+
+/// The reverse of [`escape`].
+fn unescape(text: &str) -> anyhow::Result<String> {
+    let mut unescaped = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        unescaped.push(match chars.next() {
+            Some('\\') => '\\',
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('t') => '\t',
+            Some('0') => '\0',
+            Some('u') => {
+                let code: String = chars.by_ref().take_while(|&c| c != '}').collect();
+                code.strip_prefix('{')
+                    .and_then(|code| u32::from_str_radix(code, 16).ok())
+                    .and_then(char::from_u32)
+                    .with_context(|| format!("Invalid escape \\u{code}}}"))?
+            }
+            other => bail!(
+                "Invalid escape \\{}",
+                other.map(String::from).unwrap_or_default()
+            ),
+        });
+    }
+    Ok(unescaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_text(kind: Kind, bytes: &[u8], endian: Endian) -> Option<String> {
+        read(kind, bytes, endian).map(|(text, _)| text)
+    }
+
+    #[test]
+    fn integers_read_and_write_in_both_byte_orders() {
+        let cases: [(Kind, &str, &[u8]); 8] = [
+            (Kind::U8, "254", &[0xFE]),
+            (Kind::I8, "-2", &[0xFE]),
+            (Kind::U16, "65534", &[0xFE, 0xFF]),
+            (Kind::I16, "-2", &[0xFE, 0xFF]),
+            (Kind::U32, "305419896", &[0x78, 0x56, 0x34, 0x12]),
+            (Kind::I32, "-2147483648", &[0, 0, 0, 0x80]),
+            (Kind::U64, "18446744073709551615", &[0xFF; 8]),
+            (
+                Kind::I64,
+                "-9223372036854775808",
+                &[0, 0, 0, 0, 0, 0, 0, 0x80],
+            ),
+        ];
+        for (kind, text, little) in cases {
+            let big: Vec<u8> = little.iter().rev().copied().collect();
+            assert_eq!(
+                write(kind, text, Endian::Little).unwrap(),
+                little,
+                "{kind:?}"
+            );
+            assert_eq!(write(kind, text, Endian::Big).unwrap(), big, "{kind:?}");
+            assert_eq!(
+                read(kind, little, Endian::Little),
+                Some((text.to_string(), little.len()))
+            );
+            assert_eq!(read_text(kind, &big, Endian::Big).unwrap(), text);
+            // Too few bytes for the value
+            assert_eq!(read(kind, &little[1..], Endian::Little), None);
+        }
+    }
+
+    #[test]
+    fn only_the_start_is_read() {
+        assert_eq!(
+            read(Kind::U16, &[1, 0, 9, 9], Endian::Little),
+            Some(("1".to_string(), 2))
+        );
+    }
+
+    #[test]
+    fn integers_parse_as_decimal_or_hex() {
+        assert_eq!(write(Kind::U8, " 0xFF ", Endian::Little).unwrap(), [0xFF]);
+        assert_eq!(write(Kind::I8, "-0x80", Endian::Little).unwrap(), [0x80]);
+        assert_eq!(
+            write(Kind::U16, "1_000", Endian::Big).unwrap(),
+            [0x03, 0xE8]
+        );
+        assert_eq!(write(Kind::U8, "0X1f", Endian::Little).unwrap(), [0x1F]);
+    }
+
+    #[test]
+    fn invalid_integers_fail() {
+        let error = |kind, text| format!("{:#}", write(kind, text, Endian::Little).unwrap_err());
+        assert_eq!(error(Kind::U8, "256"), "256 is out of range for u8");
+        assert_eq!(error(Kind::U32, "-1"), "-1 is out of range for u32");
+        assert!(error(Kind::I8, "twelve").starts_with("\"twelve\" isn't an integer"));
+        assert!(error(Kind::I64, "--5").starts_with("\"--5\" isn't an integer"));
+        assert!(error(Kind::I64, "-+5").starts_with("\"-+5\" isn't an integer"));
+        assert!(error(Kind::I64, "0x-5").starts_with("\"0x-5\" isn't an integer"));
+        // Would be i128::MIN after the second minus, which can't be negated
+        assert!(
+            error(Kind::I64, "--170141183460469231731687303715884105728")
+                .contains("isn't an integer")
+        );
+    }
+
+    #[test]
+    fn varints() {
+        let cases: [(u64, &[u8]); 5] = [
+            (0, &[0]),
+            (1, &[1]),
+            (127, &[0x7F]),
+            (300, &[0xAC, 0x02]),
+            (
+                u64::MAX,
+                &[0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01],
+            ),
+        ];
+        for (value, bytes) in cases {
+            let text = value.to_string();
+            assert_eq!(write(Kind::Varint, &text, Endian::Little).unwrap(), bytes);
+            assert_eq!(
+                read(Kind::Varint, &[bytes, &[0xAA]].concat(), Endian::Little),
+                Some((text, bytes.len()))
+            );
+        }
+        // Unterminated, and more than 64 bits
+        assert_eq!(read(Kind::Varint, &[0x80, 0x80], Endian::Little), None);
+        assert_eq!(
+            read(
+                Kind::Varint,
+                &[0xFF; 9].iter().chain(&[0x02]).copied().collect::<Vec<_>>(),
+                Endian::Little
+            ),
+            None
+        );
+        assert_eq!(read(Kind::Varint, &[0x80; 11], Endian::Little), None);
+    }
+
+    #[test]
+    fn zigzag_varints() {
+        let cases: [(&str, &[u8]); 6] = [
+            ("0", &[0]),
+            ("-1", &[1]),
+            ("1", &[2]),
+            ("-2", &[3]),
+            ("-64", &[0x7F]),
+            (
+                "-9223372036854775808",
+                &[0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01],
+            ),
+        ];
+        for (text, bytes) in cases {
+            assert_eq!(
+                write(Kind::ZigzagVarint, text, Endian::Little).unwrap(),
+                bytes
+            );
+            assert_eq!(
+                read_text(Kind::ZigzagVarint, bytes, Endian::Little).unwrap(),
+                text
+            );
+        }
+        assert_eq!(
+            read_text(
+                Kind::ZigzagVarint,
+                &[0xFE, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01],
+                Endian::Little
+            )
+            .unwrap(),
+            i64::MAX.to_string()
+        );
+    }
+
+    #[test]
+    fn floats() {
+        assert_eq!(
+            read_text(Kind::F32, &1.5f32.to_le_bytes(), Endian::Little).unwrap(),
+            "1.5"
+        );
+        assert_eq!(
+            read_text(Kind::F64, &1e20f64.to_be_bytes(), Endian::Big).unwrap(),
+            "1e20"
+        );
+        assert_eq!(
+            read_text(Kind::F64, &1e-7f64.to_le_bytes(), Endian::Little).unwrap(),
+            "1e-7"
+        );
+        assert_eq!(
+            write(Kind::F32, "1.5", Endian::Big).unwrap(),
+            1.5f32.to_be_bytes()
+        );
+        assert!(write(Kind::F64, "one", Endian::Little).is_err());
+    }
+
+    #[test]
+    fn times() {
+        let text = "2024-01-31 12:00:00.250";
+        let bytes = write(Kind::UnixMillis, text, Endian::Little).unwrap();
+        assert_eq!(bytes, 1_706_702_400_250i64.to_le_bytes());
+        assert_eq!(
+            read_text(Kind::UnixMillis, &bytes, Endian::Little).unwrap(),
+            text
+        );
+
+        let bytes = write(Kind::UnixSeconds, "2024-01-31T13:00:00+01:00", Endian::Big).unwrap();
+        assert_eq!(bytes, 1_706_702_400u32.to_be_bytes());
+        assert_eq!(
+            read_text(Kind::UnixSeconds, &bytes, Endian::Big).unwrap(),
+            "2024-01-31 12:00:00.000"
+        );
+        assert!(write(Kind::UnixSeconds, "1960-01-01 00:00:00", Endian::Big).is_err());
+        assert!(write(Kind::UnixSeconds, "noon", Endian::Big).is_err());
+    }
+
+    #[test]
+    fn text_is_escaped_onto_one_line() {
+        let text = "a\\b\n\tc\u{1}é";
+        let escaped = r"a\\b\n\tc\u{1}é";
+        for (kind, endian) in [
+            (Kind::Utf8, Endian::Little),
+            (Kind::Utf16, Endian::Little),
+            (Kind::Utf16, Endian::Big),
+        ] {
+            let bytes = write(kind, escaped, endian).unwrap();
+            assert_eq!(
+                read(kind, &bytes, endian),
+                Some((escaped.to_string(), bytes.len()))
+            );
+        }
+        assert_eq!(
+            write(Kind::Utf8, escaped, Endian::Little).unwrap(),
+            text.as_bytes()
+        );
+        // A trailing odd byte isn't part of any UTF-16 unit
+        assert_eq!(
+            read(Kind::Utf16, b"a\0b", Endian::Little),
+            Some(("a".into(), 2))
+        );
+        assert!(write(Kind::Utf8, r"\q", Endian::Little).is_err());
+        assert!(write(Kind::Utf8, r"\u{D800}", Endian::Little).is_err());
+    }
+
+    #[test]
+    fn bits() {
+        assert_eq!(
+            read_text(Kind::Bits, &[0b1010_0000, 1], Endian::Little).unwrap(),
+            "10100000 00000001"
+        );
+        assert_eq!(
+            read(Kind::Bits, &[0; 10], Endian::Little).unwrap().1,
+            MAX_BITS_BYTES
+        );
+        assert_eq!(
+            write(Kind::Bits, "1010 0000 00000001", Endian::Little).unwrap(),
+            [0xA0, 1]
+        );
+        assert!(write(Kind::Bits, "101", Endian::Little).is_err());
+        assert!(write(Kind::Bits, "10100002", Endian::Little).is_err());
+    }
+
+    #[test]
+    fn nothing_is_read_from_no_bytes() {
+        for kind in Kind::ALL {
+            assert_eq!(read(kind, &[], Endian::Little), None, "{kind:?}");
+        }
+    }
+}
@@ -0,0 +1,40 @@
+//! The networking backend of Palm: TCP clients and servers that record everything happening on
+//! them as [`backend::Log`]s, and [`scenario`]s to script and check conversations with them.
+//!
+//! Everything runs on a tokio runtime. Logs can either be polled (which is what the GUI does every
+//! frame) or received as they happen by subscribing to a connection's or server's event stream:
+//!
+//! ```
+//! use palm_core::backend::{Connection, LogData, Server};
+//!
+//! # #[tokio::main]
+//! # async fn main() -> anyhow::Result<()> {
+//! let server = Server::listen(0).await?;
+//! let mut server_events = server.subscribe();
+//!
+//! let client = Connection::connect(format!("127.0.0.1:{}", server.port().unwrap())).await?;
+//! client.send_data(b"ping".to_vec())?;
+//!
+//! loop {
+//!     if let LogData::ReceivedPacket(packet) = server_events.recv().await?.data {
+//!         assert_eq!(packet.data, b"ping");
+//!         break;
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod backend;
+pub mod capture;
+pub mod diff;
+pub mod inspect;
+pub mod literal;
+pub mod logfile;
+pub mod logstore;
+pub mod pcapng;
+pub mod scenario;
+pub mod session;
+pub mod stats;
+pub mod template;
+pub mod util;
@@ -0,0 +1,339 @@
+//! Bytes as literals of programming languages and other text formats, to copy payloads into code
+//! and back.
+
+use anyhow::{anyhow, bail, Context};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+
+use crate::util::hex_encode_formatted;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Hex,
+    /// `&[0xDE, 0xAD]`
+    RustSlice,
+    /// `b"\xDE\xAD"`
+    RustBytes,
+    /// `{ 0xDE, 0xAD }` in an array declaration
+    C,
+    /// `b"\xde\xad"`
+    Python,
+    /// `[]byte{0xDE, 0xAD}`
+    Go,
+    /// `new Uint8Array([0xDE, 0xAD])`
+    JavaScript,
+    Base64,
+    /// `\xDE\xAD` with every byte escaped
+    Escaped,
+}
+
+impl Format {
+    pub const ALL: [Format; 9] = [
+        Format::Hex,
+        Format::RustSlice,
+        Format::RustBytes,
+        Format::C,
+        Format::Python,
+        Format::Go,
+        Format::JavaScript,
+        Format::Base64,
+        Format::Escaped,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Format::Hex => "Hex",
+            Format::RustSlice => "Rust &[u8]",
+            Format::RustBytes => "Rust b\"…\"",
+            Format::C => "C Array",
+            Format::Python => "Python bytes",
+            Format::Go => "Go []byte",
+            Format::JavaScript => "JavaScript Uint8Array",
+            Format::Base64 => "Base64",
+            Format::Escaped => "\\x Escaped",
+        }
+    }
+}
+
+pub fn format(bytes: &[u8], format: Format) -> String {
+    let list = || {
+        bytes
+            .iter()
+            .map(|b| format!("0x{b:02X}"))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    match format {
+        Format::Hex => hex_encode_formatted(bytes),
+        Format::RustSlice => format!("&[{}]", list()),
+        Format::RustBytes => format!("b\"{}\"", escape(bytes)),
+        Format::C => format!(
+            "const unsigned char data[{}] = {{ {} }};",
+            bytes.len(),
+            list()
+        ),
+        Format::Python => format!("b\"{}\"", escape(bytes)),
+        Format::Go => format!("[]byte{{{}}}", list()),
+        Format::JavaScript => format!("new Uint8Array([{}])", list()),
+        Format::Base64 => BASE64.encode(bytes),
+        Format::Escaped => bytes.iter().map(|b| format!("\\x{b:02X}")).collect(),
+    }
+}
+
+/// Parses text in `format`, tolerating what's around the literal such as declarations.
+pub fn parse(text: &str, format: Format) -> anyhow::Result<Vec<u8>> {
+    match format {
+        Format::Hex => parse_hex(text),
+        Format::RustSlice | Format::C | Format::Go | Format::JavaScript => parse_list(text),
+        Format::RustBytes | Format::Python | Format::Escaped => parse_string(text),
+        Format::Base64 => {
+            let cleaned: String = text.chars().filter(|c| !c.is_whitespace()).collect();
+            BASE64.decode(cleaned).context("Invalid base64")
+        }
+    }
+}
+
+/// Parses text in whichever format it looks like, falling back to hex.
+pub fn parse_any(text: &str) -> anyhow::Result<Vec<u8>> {
+    let text = text.trim();
+    // Strings are checked first as they may contain brackets themselves
+    if text.ends_with(['"', '\'']) {
+        parse_string(text)
+    } else if text.contains(['[', '{']) {
+        parse_list(text)
+    } else if text.contains("\\x") {
+        parse_string(text)
+    } else {
+        parse_hex(text)
+    }
+}
+
+/// Escapes bytes that aren't printable ASCII, as Rust and Python both read them.
+fn escape(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|&b| match b {
+            b'\\' => "\\\\".to_string(),
+            b'"' => "\\\"".to_string(),
+            b'\n' => "\\n".to_string(),
+            b'\r' => "\\r".to_string(),
+            b'\t' => "\\t".to_string(),
+            0x20..=0x7e => (b as char).to_string(),
+            _ => format!("\\x{b:02X}"),
+        })
+        .collect()
+}
+
+/// Parses hex digits, with or without `0x` prefixes and separators between bytes.
+fn parse_hex(text: &str) -> anyhow::Result<Vec<u8>> {
+    let cleaned = text.replace("0x", " ").replace("0X", " ");
+    let digits: String = cleaned
+        .chars()
+        .filter(|c| !c.is_whitespace() && !matches!(c, ',' | ':' | '-'))
+        .collect();
+    hex::decode(&digits).with_context(|| format!("Invalid hex {digits:?}"))
+}
+
+/// Parses a list of byte values between brackets or braces, like most languages' array literals.
+/// The last bracketed list is taken, so that array lengths in declarations are skipped.
+fn parse_list(text: &str) -> anyhow::Result<Vec<u8>> {
+    let items = match text.rfind([']', '}']) {
+        Some(end) => {
+            let open = if text[end..].starts_with(']') {
+                '['
+            } else {
+                '{'
+            };
+            let start = text[..end].rfind(open).map_or(0, |start| start + 1);
+            &text[start..end]
+        }
+        None => text.trim_start_matches(['[', '{']),
+    };
+
+    items
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(|item| {
+            let number = item
+                .trim_end_matches("u8")
+                .trim_end_matches(['u', 'U'])
+                .replace('_', "");
+            let value = if let Some(hex) = number
+                .strip_prefix("0x")
+                .or_else(|| number.strip_prefix("0X"))
+            {
+                u32::from_str_radix(hex, 16)
+            } else if let Some(binary) = number.strip_prefix("0b") {
+                u32::from_str_radix(binary, 2)
+            } else {
+                number.parse()
+            }
+            .with_context(|| format!("Invalid byte {item:?}"))?;
+            u8::try_from(value).map_err(|_| anyhow!("{item} doesn't fit in a byte"))
+        })
+        .collect()
+}
+
+/// Parses a byte string with `\x` escapes, with or without a `b` prefix and quotes.
+fn parse_string(text: &str) -> anyhow::Result<Vec<u8>> {
+    let mut text = text.trim();
+    text = text
+        .strip_prefix(['b', 'B'])
+        .filter(|rest| rest.starts_with(['"', '\'']))
+        .unwrap_or(text);
+    for quote in ['"', '\''] {
+        if let Some(inner) = text
+            .strip_prefix(quote)
+            .and_then(|rest| rest.strip_suffix(quote))
+        {
+            text = inner;
+            break;
+        }
+    }
+
+    let mut bytes = Vec::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buffer = [0; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+            continue;
+        }
+        bytes.push(match chars.next() {
+            Some('x') => {
+                let digits: String = chars.by_ref().take(2).collect();
+                u8::from_str_radix(&digits, 16)
+                    .with_context(|| format!("Invalid escape \\x{digits}"))?
+            }
+            Some('n') => b'\n',
+            Some('r') => b'\r',
+            Some('t') => b'\t',
+            Some('0') => b'\0',
+            Some(c @ ('\\' | '"' | '\'')) => c as u8,
+            Some(c) => bail!("Invalid escape \\{c}"),
+            None => bail!("Trailing backslash"),
+        });
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samples() -> [Vec<u8>; 3] {
+        [
+            (0..=255).collect(),
+            b"Hi [0] {1}, \"quoted\" 'single' \\ back\r\n\t".to_vec(),
+            Vec::new(),
+        ]
+    }
+
+    #[test]
+    fn every_format_round_trips() {
+        for bytes in samples() {
+            for format in Format::ALL {
+                let text = super::format(&bytes, format);
+                assert_eq!(parse(&text, format).unwrap(), bytes, "{format:?}: {text}");
+            }
+        }
+    }
+
+    #[test]
+    fn any_format_but_base64_is_recognized() {
+        for bytes in samples() {
+            for format in Format::ALL {
+                if format == Format::Base64 {
+                    continue;
+                }
+                let text = super::format(&bytes, format);
+                assert_eq!(parse_any(&text).unwrap(), bytes, "{format:?}: {text}");
+            }
+        }
+    }
+
+    #[test]
+    fn formats() {
+        let bytes = [0xDE, 0xAD, b'"', b'a'];
+        assert_eq!(super::format(&bytes, Format::Hex), "DE AD 22 61");
+        assert_eq!(
+            super::format(&bytes, Format::RustSlice),
+            "&[0xDE, 0xAD, 0x22, 0x61]"
+        );
+        assert_eq!(
+            super::format(&bytes, Format::RustBytes),
+            r#"b"\xDE\xAD\"a""#
+        );
+        assert_eq!(
+            super::format(&bytes, Format::C),
+            "const unsigned char data[4] = { 0xDE, 0xAD, 0x22, 0x61 };"
+        );
+        assert_eq!(
+            super::format(&bytes, Format::Go),
+            "[]byte{0xDE, 0xAD, 0x22, 0x61}"
+        );
+        assert_eq!(
+            super::format(&bytes, Format::JavaScript),
+            "new Uint8Array([0xDE, 0xAD, 0x22, 0x61])"
+        );
+        assert_eq!(super::format(&bytes, Format::Base64), "3q0iYQ==");
+        assert_eq!(super::format(&bytes, Format::Escaped), r"\xDE\xAD\x22\x61");
+    }
+
+    #[test]
+    fn hex_with_prefixes_and_separators() {
+        let expected = vec![0xDE, 0xAD, 0xBE, 0xEF];
+        for text in [
+            "0xDE 0xAD 0xBE 0xEF",
+            "0xdeadbeef",
+            "0XDE,0XAD,0XBE,0XEF",
+            "de:ad:be:ef",
+            "DE-AD-BE-EF",
+            " de ad\nbe ef ",
+        ] {
+            assert_eq!(parse(text, Format::Hex).unwrap(), expected, "{text}");
+            assert_eq!(parse_any(text).unwrap(), expected, "{text}");
+        }
+        assert!(parse("DEA", Format::Hex).is_err());
+        assert!(parse("0xZZ", Format::Hex).is_err());
+    }
+
+    #[test]
+    fn c_declarations_skip_array_lengths() {
+        for text in [
+            "unsigned char buf[3] = {1, 2, 3};",
+            "static const uint8_t buf[3] = { 0x01, 0x02, 0x03, };",
+            "uint8_t buf[] = {0b1, 2u, 0x03U};",
+            "let buf: [u8; 3] = [1u8, 2, 3];",
+        ] {
+            assert_eq!(parse(text, Format::C).unwrap(), [1, 2, 3], "{text}");
+            assert_eq!(parse_any(text).unwrap(), [1, 2, 3], "{text}");
+        }
+    }
+
+    #[test]
+    fn strings_with_or_without_quotes() {
+        assert_eq!(parse(r"\x41\x42", Format::Escaped).unwrap(), b"AB");
+        assert_eq!(parse(r"B'\x41\''", Format::Python).unwrap(), b"A'");
+        assert_eq!(parse(r#""a\0""#, Format::RustBytes).unwrap(), b"a\0");
+        assert_eq!(parse_any(r"\x41b").unwrap(), b"Ab");
+        assert_eq!(parse_any("'[x]'").unwrap(), b"[x]");
+    }
+
+    #[test]
+    fn invalid_literals_fail() {
+        let error = |text: &str, format| format!("{:#}", parse(text, format).unwrap_err());
+        assert_eq!(
+            error("[1, 256]", Format::RustSlice),
+            "256 doesn't fit in a byte"
+        );
+        assert_eq!(
+            error("{1, x}", Format::C),
+            "Invalid byte \"x\": invalid digit found in string"
+        );
+        assert!(error(r"\xZZ", Format::Escaped).starts_with("Invalid escape \\xZZ"));
+        assert_eq!(error(r"\q", Format::Escaped), "Invalid escape \\q");
+        assert_eq!(error("ab\\", Format::Escaped), "Trailing backslash");
+        assert!(parse("!!", Format::Base64).is_err());
+    }
+}
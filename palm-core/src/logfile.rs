@@ -0,0 +1,468 @@
+//! Export and import of logs as JSON Lines or CSV, one record per [`Log`], so sessions can be
+//! attached to bug reports or processed with other tools.
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    path::Path,
+    sync::Arc,
+};
+
+use anyhow::{anyhow, bail, Context};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chrono::DateTime;
+use serde::{Deserialize, Serialize};
+
+use crate::backend::{DataPacket, Log, LogData};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    JsonLines,
+    Csv,
+}
+
+impl Format {
+    /// Guesses the format from the extension of `path`, defaulting to JSON Lines.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("csv") => Self::Csv,
+            _ => Self::JsonLines,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    In,
+    Out,
+}
+
+/// A log as written to a file. Only the fields that apply to the kind of event are set.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Record {
+    /// RFC 3339 timestamp with the local offset
+    pub timestamp: String,
+    pub event: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub peer: Option<String>,
+    /// Whether data was received or sent, redundant with the event but easier to filter on
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub direction: Option<Direction>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hex: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base64: Option<String>,
+    /// End offsets of the fragments the data was written in
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fragments: Vec<usize>,
+    /// Error or script output
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+/// The CSV crate can't (de)serialize sequences, so fragments are joined with spaces.
+#[derive(Serialize, Deserialize)]
+struct CsvRecord {
+    timestamp: String,
+    event: String,
+    peer: Option<String>,
+    direction: Option<Direction>,
+    hex: Option<String>,
+    base64: Option<String>,
+    fragments: Option<String>,
+    message: Option<String>,
+}
+
+impl From<&Log> for Record {
+    fn from(log: &Log) -> Self {
+        let mut record = Record {
+            timestamp: log.timestamp.to_rfc3339(),
+            event: event_name(&log.data).to_string(),
+            peer: None,
+            direction: None,
+            hex: None,
+            base64: None,
+            fragments: Vec::new(),
+            message: None,
+        };
+        let (peer, message) = match &log.data {
+            LogData::ClientConnect(address) | LogData::ClientDisconnect(address) => {
+                (Some(address), None)
+            }
+            LogData::ReceivedPacket(packet) | LogData::SentPacket(packet) => {
+                record.direction = Some(match log.data {
+                    LogData::ReceivedPacket(_) => Direction::In,
+                    _ => Direction::Out,
+                });
+                record.hex = Some(hex::encode(&packet.data));
+                record.base64 = Some(BASE64.encode(&packet.data));
+                record.fragments = packet.fragments.clone();
+                (Some(&packet.address), None)
+            }
+            LogData::ConnectError(e)
+            | LogData::ServerStartError(e)
+            | LogData::FatalReadError(e)
+            | LogData::FatalWriteError(e) => (None, Some(e.to_string())),
+            LogData::Script(message) | LogData::ScriptError(message) => {
+                (None, Some(message.clone()))
+            }
+            LogData::ServerStarted | LogData::ServerStopped | LogData::ConnectTimedOut => {
+                (None, None)
+            }
+        };
+        record.peer = peer.cloned();
+        record.message = message;
+        record
+    }
+}
+
+impl TryFrom<Record> for Log {
+    type Error = anyhow::Error;
+
+    fn try_from(record: Record) -> anyhow::Result<Self> {
+        let timestamp = DateTime::parse_from_rfc3339(&record.timestamp)
+            .with_context(|| format!("Invalid timestamp {:?}", record.timestamp))?
+            .with_timezone(&chrono::Local);
+        let peer = || record.peer.clone().ok_or_else(|| anyhow!("Missing peer"));
+        let message = || record.message.clone().unwrap_or_default();
+        let error = || Arc::new(std::io::Error::other(message()));
+
+        let data = match record.event.as_str() {
+            "connect" => LogData::ClientConnect(peer()?),
+            "disconnect" => LogData::ClientDisconnect(peer()?),
+            "server_started" => LogData::ServerStarted,
+            "server_stopped" => LogData::ServerStopped,
+            "received" | "sent" => {
+                let data = if let Some(hex) = &record.hex {
+                    hex::decode(hex.trim()).context("Invalid hex data")?
+                } else if let Some(base64) = &record.base64 {
+                    BASE64
+                        .decode(base64.trim())
+                        .context("Invalid base64 data")?
+                } else {
+                    bail!("Missing data");
+                };
+                check_fragments(&record.fragments, data.len())?;
+                let packet = DataPacket::fragmented(peer()?, data, record.fragments.clone());
+                match record.event.as_str() {
+                    "received" => LogData::ReceivedPacket(packet),
+                    _ => LogData::SentPacket(packet),
+                }
+            }
+            "connect_error" => LogData::ConnectError(error()),
+            "connect_timed_out" => LogData::ConnectTimedOut,
+            "server_start_error" => LogData::ServerStartError(error()),
+            "fatal_read_error" => LogData::FatalReadError(error()),
+            "fatal_write_error" => LogData::FatalWriteError(error()),
+            "script" => LogData::Script(message()),
+            "script_error" => LogData::ScriptError(message()),
+            event => bail!("Unknown event {event:?}"),
+        };
+        Ok(Log {
+            data,
+            timestamp,
+            instant: None,
+        })
+    }
+}
+
+/// Fails unless `fragments` are the ends of consecutive fragments that make up all `len` bytes,
+/// as anything else can't have been written.
+fn check_fragments(fragments: &[usize], len: usize) -> anyhow::Result<()> {
+    let Some(&last) = fragments.last() else {
+        return Ok(());
+    };
+    if fragments.iter().any(|&end| end > len) {
+        bail!("Fragment ends past the data");
+    }
+    let starts = std::iter::once(&0).chain(fragments);
+    if starts.zip(fragments).any(|(start, end)| start >= end) {
+        bail!("Fragments must end at increasing offsets");
+    }
+    if last != len {
+        bail!("Fragments end before the data does");
+    }
+    Ok(())
+}
+
+impl From<Record> for CsvRecord {
+    fn from(record: Record) -> Self {
+        let fragments = (!record.fragments.is_empty()).then(|| {
+            record
+                .fragments
+                .iter()
+                .map(|end| end.to_string())
+                .collect::<Vec<_>>()
+                .join(" ")
+        });
+        Self {
+            timestamp: record.timestamp,
+            event: record.event,
+            peer: record.peer,
+            direction: record.direction,
+            hex: record.hex,
+            base64: record.base64,
+            fragments,
+            message: record.message,
+        }
+    }
+}
+
+impl TryFrom<CsvRecord> for Record {
+    type Error = anyhow::Error;
+
+    fn try_from(record: CsvRecord) -> anyhow::Result<Self> {
+        let fragments = record
+            .fragments
+            .as_deref()
+            .unwrap_or_default()
+            .split_whitespace()
+            .map(|end| {
+                end.parse()
+                    .with_context(|| format!("Invalid fragment {end:?}"))
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self {
+            timestamp: record.timestamp,
+            event: record.event,
+            peer: record.peer,
+            direction: record.direction,
+            hex: record.hex,
+            base64: record.base64,
+            fragments,
+            message: record.message,
+        })
+    }
+}
+
+/// The name of the kind of event in records, e.g. `received` for [`LogData::ReceivedPacket`].
+pub fn event_name(data: &LogData) -> &'static str {
+    match data {
+        LogData::ClientConnect(_) => "connect",
+        LogData::ClientDisconnect(_) => "disconnect",
+        LogData::ServerStarted => "server_started",
+        LogData::ServerStopped => "server_stopped",
+        LogData::ReceivedPacket(_) => "received",
+        LogData::SentPacket(_) => "sent",
+        LogData::ConnectError(_) => "connect_error",
+        LogData::ConnectTimedOut => "connect_timed_out",
+        LogData::ServerStartError(_) => "server_start_error",
+        LogData::FatalReadError(_) => "fatal_read_error",
+        LogData::FatalWriteError(_) => "fatal_write_error",
+        LogData::Script(_) => "script",
+        LogData::ScriptError(_) => "script_error",
+    }
+}
+
+pub fn write(mut writer: impl Write, logs: &[Log], format: Format) -> anyhow::Result<()> {
+    match format {
+        Format::JsonLines => {
+            for log in logs {
+                serde_json::to_writer(&mut writer, &Record::from(log))?;
+                writer.write_all(b"\n")?;
+            }
+            writer.flush()?;
+        }
+        Format::Csv => {
+            let mut writer = csv::Writer::from_writer(writer);
+            for log in logs {
+                writer.serialize(CsvRecord::from(Record::from(log)))?;
+            }
+            writer.flush()?;
+        }
+    }
+    Ok(())
+}
+
+pub fn read(reader: impl Read, format: Format) -> anyhow::Result<Vec<Log>> {
+    let mut logs = Vec::new();
+    match format {
+        Format::JsonLines => {
+            for (i, line) in BufReader::new(reader).lines().enumerate() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let log = serde_json::from_str::<Record>(&line)
+                    .map_err(anyhow::Error::from)
+                    .and_then(Log::try_from)
+                    .with_context(|| format!("Line {}", i + 1))?;
+                logs.push(log);
+            }
+        }
+        Format::Csv => {
+            let mut reader = csv::Reader::from_reader(reader);
+            for (i, record) in reader.deserialize::<CsvRecord>().enumerate() {
+                let log = record
+                    .map_err(anyhow::Error::from)
+                    .and_then(Record::try_from)
+                    .and_then(Log::try_from)
+                    .with_context(|| format!("Record {}", i + 1))?;
+                logs.push(log);
+            }
+        }
+    }
+    Ok(logs)
+}
+
+/// Reads the logs in `path`, guessing the format from its extension.
+pub fn read_file(path: &Path) -> anyhow::Result<Vec<Log>> {
+    let file = std::fs::File::open(path)?;
+    read(file, Format::from_path(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Local, TimeDelta};
+
+    use super::*;
+
+    /// One log of every kind, with data that needs quoting in CSV.
+    fn logs() -> Vec<Log> {
+        let packet = |data: &[u8], fragments| {
+            DataPacket::fragmented("127.0.0.1:50000".into(), data.to_vec(), fragments)
+        };
+        let start = DateTime::from_timestamp(1_700_000_000, 123_456_789)
+            .unwrap()
+            .with_timezone(&Local);
+        [
+            Log::server_started(),
+            Log::connect("127.0.0.1:50000".into()),
+            Log::received(packet(b"GET / HTTP/1.1\r\n", Vec::new())),
+            Log::sent(packet(&[0, 1, 2, 0xFF, b',', b'"'], vec![1, 4, 6])),
+            Log::script("said \"hi\", twice\nand then\nleft".into()),
+            Log::script_error(String::new()),
+            Log::fatal_read_error(std::io::Error::other("reset, by peer")),
+            Log::disconnect("127.0.0.1:50000".into()),
+            Log::server_stopped(),
+            Log::connect_timed_out(),
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(i, log)| log.at(start + TimeDelta::nanoseconds(i as i64 * 1_001)))
+        .collect()
+    }
+
+    /// Logs as JSON, as they have no equality of their own.
+    fn json(logs: &[Log]) -> Vec<serde_json::Value> {
+        logs.iter()
+            .map(|log| serde_json::to_value(Record::from(log)).unwrap())
+            .collect()
+    }
+
+    fn round_trip(format: Format) -> Vec<Log> {
+        let mut file = Vec::new();
+        write(&mut file, &logs(), format).unwrap();
+        read(&file[..], format).unwrap()
+    }
+
+    #[test]
+    fn json_lines_round_trip() {
+        let logs = logs();
+        let read = round_trip(Format::JsonLines);
+        assert_eq!(json(&read), json(&logs));
+        for (read, written) in read.iter().zip(&logs) {
+            assert_eq!(read.timestamp, written.timestamp);
+        }
+    }
+
+    #[test]
+    fn csv_round_trip() {
+        assert_eq!(json(&round_trip(Format::Csv)), json(&logs()));
+    }
+
+    #[test]
+    fn fragments_survive() {
+        for format in [Format::JsonLines, Format::Csv] {
+            let read = round_trip(format);
+            let LogData::SentPacket(packet) = &read[3].data else {
+                panic!("{:?} isn't a sent packet", read[3].data);
+            };
+            assert_eq!(packet.data, [0, 1, 2, 0xFF, b',', b'"']);
+            assert_eq!(packet.fragments, [1, 4, 6]);
+        }
+    }
+
+    #[test]
+    fn data_may_be_base64_only() {
+        let line = r#"{"timestamp":"2025-01-01T00:00:00+01:00","event":"received","peer":"a","base64":"aGk="}"#;
+        let logs = read(format!("\n{line}\n\n").as_bytes(), Format::JsonLines).unwrap();
+        assert_eq!(logs.len(), 1);
+        let LogData::ReceivedPacket(packet) = &logs[0].data else {
+            panic!("{:?} isn't a received packet", logs[0].data);
+        };
+        assert_eq!(packet.data, b"hi");
+        assert!(packet.fragments.is_empty());
+    }
+
+    #[test]
+    fn invalid_records_fail_with_their_line() {
+        let error = |record: &str| {
+            let file = format!(
+                "{{\"timestamp\":\"2025-01-01T00:00:00Z\",\"event\":\"server_started\"}}\n{record}"
+            );
+            format!(
+                "{:#}",
+                read(file.as_bytes(), Format::JsonLines).unwrap_err()
+            )
+        };
+        let at = r#""timestamp":"2025-01-01T00:00:00Z""#;
+
+        assert_eq!(
+            error(&format!(
+                r#"{{{at},"event":"sent","peer":"a","hex":"0102","fragments":[3]}}"#
+            )),
+            "Line 2: Fragment ends past the data"
+        );
+        for (fragments, message) in [
+            ("[4,1]", "Fragments must end at increasing offsets"),
+            ("[2,2,4]", "Fragments must end at increasing offsets"),
+            ("[0,4]", "Fragments must end at increasing offsets"),
+            ("[1,3]", "Fragments end before the data does"),
+        ] {
+            assert_eq!(
+                error(&format!(
+                    r#"{{{at},"event":"sent","peer":"a","hex":"01020304","fragments":{fragments}}}"#
+                )),
+                format!("Line 2: {message}"),
+                "{fragments}"
+            );
+        }
+        assert_eq!(
+            error(&format!(r#"{{{at},"event":"connect"}}"#)),
+            "Line 2: Missing peer"
+        );
+        assert_eq!(
+            error(&format!(r#"{{{at},"event":"sent","peer":"a"}}"#)),
+            "Line 2: Missing data"
+        );
+        assert_eq!(
+            error(&format!(r#"{{{at},"event":"jumped"}}"#)),
+            "Line 2: Unknown event \"jumped\""
+        );
+        assert!(error(r#"{"timestamp":"noon","event":"connect"}"#)
+            .starts_with("Line 2: Invalid timestamp \"noon\""));
+        assert!(error("not json").starts_with("Line 2: "));
+    }
+
+    #[test]
+    fn invalid_csv_fragments_fail() {
+        let file = "timestamp,event,peer,direction,hex,base64,fragments,message\n\
+                    2025-01-01T00:00:00Z,sent,a,out,0102,,1 x,\n";
+        let error = read(file.as_bytes(), Format::Csv).unwrap_err();
+        assert_eq!(
+            format!("{error:#}"),
+            "Record 1: Invalid fragment \"x\": invalid digit found in string"
+        );
+    }
+
+    #[test]
+    fn format_from_extension() {
+        assert_eq!(Format::from_path(Path::new("logs.CSV")), Format::Csv);
+        assert_eq!(
+            Format::from_path(Path::new("logs.jsonl")),
+            Format::JsonLines
+        );
+        assert_eq!(Format::from_path(Path::new("logs")), Format::JsonLines);
+    }
+}
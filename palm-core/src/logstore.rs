@@ -0,0 +1,222 @@
+//! Bounded storage for logs, so that chatty connections don't grow without limit.
+
+use std::{
+    collections::VecDeque,
+    ops::Range,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::backend::Log;
+
+/// How many logs connections and servers keep by default.
+pub const DEFAULT_CAPACITY: usize = 100_000;
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Logs in the order they happened, dropping the oldest ones once more than the capacity are
+/// stored.
+///
+/// Logs keep their index for as long as they're stored: the first log ever pushed has index 0, and
+/// [`LogStore::start`] is the index of the oldest one that wasn't dropped yet.
+#[derive(Debug)]
+pub struct LogStore {
+    id: u64,
+    logs: VecDeque<Log>,
+    capacity: usize,
+    start: usize,
+}
+
+impl Default for LogStore {
+    fn default() -> Self {
+        Self::with_capacity(DEFAULT_CAPACITY)
+    }
+}
+
+impl Clone for LogStore {
+    fn clone(&self) -> Self {
+        Self {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            logs: self.logs.clone(),
+            capacity: self.capacity,
+            start: self.start,
+        }
+    }
+}
+
+impl From<Vec<Log>> for LogStore {
+    /// Makes a store that never drops logs.
+    fn from(logs: Vec<Log>) -> Self {
+        Self {
+            logs: logs.into(),
+            ..Self::with_capacity(usize::MAX)
+        }
+    }
+}
+
+impl LogStore {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            logs: VecDeque::new(),
+            capacity: capacity.max(1),
+            start: 0,
+        }
+    }
+
+    /// Unique for every store, to tell them apart when caching things derived from their logs.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Changes the capacity, dropping the oldest logs if there are more than that.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity.max(1);
+        self.truncate();
+    }
+
+    pub fn push(&mut self, log: Log) {
+        self.logs.push_back(log);
+        self.truncate();
+    }
+
+    fn truncate(&mut self) {
+        let excess = self.logs.len().saturating_sub(self.capacity);
+        self.logs.drain(..excess);
+        self.start += excess;
+    }
+
+    pub fn len(&self) -> usize {
+        self.logs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.logs.is_empty()
+    }
+
+    /// Index of the oldest stored log, which is also how many logs were dropped.
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    /// Index the next log will get.
+    pub fn end(&self) -> usize {
+        self.start + self.logs.len()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Log> {
+        self.logs.get(index.checked_sub(self.start)?)
+    }
+
+    /// The stored logs with indices in `range`.
+    pub fn range(&self, range: Range<usize>) -> impl DoubleEndedIterator<Item = &Log> {
+        let start = range.start.clamp(self.start, self.end()) - self.start;
+        let end = range.end.clamp(self.start, self.end()) - self.start;
+        self.logs.range(start..end.max(start))
+    }
+
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &Log> {
+        self.logs.iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a LogStore {
+    type Item = &'a Log;
+    type IntoIter = std::collections::vec_deque::Iter<'a, Log>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.logs.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::LogData;
+
+    /// A store of `capacity` after pushing script logs "0" up to `pushed - 1`.
+    fn store(capacity: usize, pushed: usize) -> LogStore {
+        let mut store = LogStore::with_capacity(capacity);
+        for i in 0..pushed {
+            store.push(Log::script(i.to_string()));
+        }
+        store
+    }
+
+    fn messages<'a>(logs: impl Iterator<Item = &'a Log>) -> Vec<String> {
+        logs.map(|log| match &log.data {
+            LogData::Script(message) => message.clone(),
+            data => panic!("{data:?} isn't a script log"),
+        })
+        .collect()
+    }
+
+    #[test]
+    fn logs_keep_their_index_after_older_ones_are_dropped() {
+        let store = store(3, 5);
+        assert_eq!(store.len(), 3);
+        assert_eq!((store.start(), store.end()), (2, 5));
+        assert_eq!(messages(store.iter()), ["2", "3", "4"]);
+        for index in 2..5 {
+            assert_eq!(messages(store.get(index).into_iter()), [index.to_string()]);
+        }
+        assert!(store.get(0).is_none());
+        assert!(store.get(1).is_none());
+        assert!(store.get(5).is_none());
+    }
+
+    #[test]
+    fn ranges_are_clamped_to_the_stored_logs() {
+        let store = store(3, 5);
+        assert_eq!(messages(store.range(0..4)), ["2", "3"]);
+        assert_eq!(messages(store.range(3..10)), ["3", "4"]);
+        assert_eq!(messages(store.range(0..2)), Vec::<String>::new());
+        assert_eq!(messages(store.range(5..8)), Vec::<String>::new());
+        #[allow(clippy::reversed_empty_ranges)]
+        let backwards = store.range(4..3);
+        assert_eq!(messages(backwards), Vec::<String>::new());
+        assert_eq!(messages(store.range(0..usize::MAX).rev()), ["4", "3", "2"]);
+    }
+
+    #[test]
+    fn shrinking_drops_the_oldest_logs() {
+        let mut store = store(10, 5);
+        assert_eq!((store.start(), store.end()), (0, 5));
+        store.set_capacity(2);
+        assert_eq!((store.start(), store.end()), (3, 5));
+        assert_eq!(messages(store.iter()), ["3", "4"]);
+
+        // Growing again keeps the indices going
+        store.set_capacity(10);
+        store.push(Log::script("5".into()));
+        assert_eq!((store.start(), store.end()), (3, 6));
+        assert_eq!(messages(store.get(5).into_iter()), ["5"]);
+    }
+
+    #[test]
+    fn capacity_is_at_least_one() {
+        let store = store(0, 3);
+        assert_eq!(store.capacity(), 1);
+        assert_eq!((store.start(), store.end()), (2, 3));
+    }
+
+    #[test]
+    fn stores_are_told_apart_by_their_id() {
+        let store = store(3, 1);
+        let clone = store.clone();
+        assert_ne!(store.id(), clone.id());
+        assert_ne!(store.id(), LogStore::default().id());
+        assert_eq!(messages(clone.iter()), ["0"]);
+    }
+
+    #[test]
+    fn stores_made_from_logs_keep_them_all() {
+        let logs: Vec<Log> = (0..5).map(|i| Log::script(i.to_string())).collect();
+        let mut store = LogStore::from(logs);
+        store.push(Log::script("5".into()));
+        assert_eq!((store.start(), store.end()), (0, 6));
+    }
+}
@@ -0,0 +1,566 @@
+//! Export of logged traffic as pcapng captures, see
+//! <https://www.ietf.org/archive/id/draft-ietf-opsawg-pcapng-03.html>.
+//!
+//! Palm only sees the payloads of a connection, so the Ethernet, IP and TCP headers around them
+//! are synthesized: connecting and disconnecting become a three-way handshake and a FIN exchange,
+//! and sequence numbers advance with the payloads so that Wireshark can follow the stream.
+
+use std::{
+    io::{self, Write},
+    net::{IpAddr, Ipv6Addr, SocketAddr},
+};
+
+use crate::{
+    backend::{DataPacket, LogData},
+    logstore::LogStore,
+};
+
+const SECTION_HEADER_BLOCK: u32 = 0x0A0D_0D0A;
+const INTERFACE_DESCRIPTION_BLOCK: u32 = 1;
+const ENHANCED_PACKET_BLOCK: u32 = 6;
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+const LINKTYPE_ETHERNET: u16 = 1;
+
+const OPT_END: u16 = 0;
+const OPT_SHB_USERAPPL: u16 = 4;
+const OPT_IF_NAME: u16 = 2;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86DD;
+const IP_PROTOCOL_TCP: u8 = 6;
+
+const TCP_FIN: u8 = 0x01;
+const TCP_SYN: u8 = 0x02;
+const TCP_PSH: u8 = 0x08;
+const TCP_ACK: u8 = 0x10;
+
+const LOCAL_MAC: [u8; 6] = [0x02, 0, 0, 0, 0, 0x01];
+const PEER_MAC: [u8; 6] = [0x02, 0, 0, 0, 0, 0x02];
+
+/// Initial sequence numbers of the client and server side of a stream's first connection
+const CLIENT_ISN: u32 = 0x1000_0000;
+const SERVER_ISN: u32 = 0x2000_0000;
+/// How far past where the last connection of a stream ended the next one starts, so that sequence
+/// numbers don't go backwards when a client reconnects.
+const ISN_GAP: u32 = 0x0010_0000;
+
+/// Largest payload put into a single segment, keeping the IP length fields in range.
+const MAX_SEGMENT_LEN: usize = 65_000;
+
+/// The logs of a connection together with the addresses to put into the headers. A client that
+/// reconnected has all its connections in one stream, each starting at its connect log.
+pub struct Stream<'a> {
+    pub local: SocketAddr,
+    pub peer: SocketAddr,
+    /// Whether the local side opened the connection, which decides who sends the SYN.
+    pub local_is_client: bool,
+    pub logs: &'a LogStore,
+}
+
+/// Writes the traffic of all `streams` as a single capture, ordered by time.
+pub fn write(mut writer: impl Write, streams: &[Stream]) -> io::Result<()> {
+    let mut frames = Vec::new();
+    for stream in streams {
+        Synthesizer::new(stream, &mut frames).run();
+    }
+    // Stable, so frames logged at the same time stay in order
+    frames.sort_by_key(|f| f.timestamp);
+
+    let mut section_header = Vec::new();
+    section_header.extend(BYTE_ORDER_MAGIC.to_le_bytes());
+    section_header.extend(1u16.to_le_bytes());
+    section_header.extend(0u16.to_le_bytes());
+    // Unknown section length
+    section_header.extend((-1i64).to_le_bytes());
+    push_option(&mut section_header, OPT_SHB_USERAPPL, b"Palm");
+    push_option(&mut section_header, OPT_END, &[]);
+    write_block(&mut writer, SECTION_HEADER_BLOCK, &section_header)?;
+
+    let mut interface = Vec::new();
+    interface.extend(LINKTYPE_ETHERNET.to_le_bytes());
+    interface.extend(0u16.to_le_bytes());
+    // No snapshot length limit
+    interface.extend(0u32.to_le_bytes());
+    push_option(&mut interface, OPT_IF_NAME, b"palm");
+    push_option(&mut interface, OPT_END, &[]);
+    write_block(&mut writer, INTERFACE_DESCRIPTION_BLOCK, &interface)?;
+
+    for frame in frames {
+        // Timestamps are in microseconds, the default resolution
+        let timestamp = frame.timestamp as u64;
+        let mut packet = Vec::with_capacity(20 + frame.data.len());
+        packet.extend(0u32.to_le_bytes());
+        packet.extend(((timestamp >> 32) as u32).to_le_bytes());
+        packet.extend((timestamp as u32).to_le_bytes());
+        packet.extend((frame.data.len() as u32).to_le_bytes());
+        packet.extend((frame.data.len() as u32).to_le_bytes());
+        packet.extend(&frame.data);
+        pad(&mut packet);
+        write_block(&mut writer, ENHANCED_PACKET_BLOCK, &packet)?;
+    }
+
+    writer.flush()
+}
+
+fn write_block(writer: &mut impl Write, block_type: u32, body: &[u8]) -> io::Result<()> {
+    let total_len = (12 + body.len().next_multiple_of(4)) as u32;
+    writer.write_all(&block_type.to_le_bytes())?;
+    writer.write_all(&total_len.to_le_bytes())?;
+    writer.write_all(body)?;
+    writer.write_all(&[0; 3][..body.len().next_multiple_of(4) - body.len()])?;
+    writer.write_all(&total_len.to_le_bytes())
+}
+
+fn push_option(body: &mut Vec<u8>, code: u16, value: &[u8]) {
+    body.extend(code.to_le_bytes());
+    body.extend((value.len() as u16).to_le_bytes());
+    body.extend(value);
+    pad(body);
+}
+
+fn pad(body: &mut Vec<u8>) {
+    body.resize(body.len().next_multiple_of(4), 0);
+}
+
+struct Frame {
+    /// Microseconds since the Unix epoch
+    timestamp: i64,
+    data: Vec<u8>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    Outgoing,
+    Incoming,
+}
+
+/// Turns the logs of a stream into frames, tracking the TCP state of both sides.
+struct Synthesizer<'a> {
+    stream: &'a Stream<'a>,
+    frames: &'a mut Vec<Frame>,
+    timestamp: i64,
+    open: bool,
+    local_seq: u32,
+    peer_seq: u32,
+    /// Initial sequence numbers of the next connection
+    local_isn: u32,
+    peer_isn: u32,
+    ip_id: u16,
+}
+
+impl<'a> Synthesizer<'a> {
+    fn new(stream: &'a Stream<'a>, frames: &'a mut Vec<Frame>) -> Self {
+        let (local_isn, peer_isn) = if stream.local_is_client {
+            (CLIENT_ISN, SERVER_ISN)
+        } else {
+            (SERVER_ISN, CLIENT_ISN)
+        };
+        Self {
+            stream,
+            frames,
+            timestamp: 0,
+            open: false,
+            local_seq: 0,
+            peer_seq: 0,
+            local_isn,
+            peer_isn,
+            ip_id: 0,
+        }
+    }
+
+    fn run(mut self) {
+        for log in self.stream.logs {
+            // Logs of different tasks can be slightly out of order, which mustn't reorder the frames
+            self.timestamp = self.timestamp.max(log.timestamp.timestamp_micros());
+            match &log.data {
+                LogData::ClientConnect(_) => self.open(),
+                LogData::ClientDisconnect(_) => self.close(),
+                LogData::SentPacket(packet) => self.data(Direction::Outgoing, packet),
+                LogData::ReceivedPacket(packet) => self.data(Direction::Incoming, packet),
+                _ => {}
+            }
+        }
+    }
+
+    fn open(&mut self) {
+        if self.open {
+            return;
+        }
+        self.open = true;
+
+        let (client, server) = if self.stream.local_is_client {
+            (Direction::Outgoing, Direction::Incoming)
+        } else {
+            (Direction::Incoming, Direction::Outgoing)
+        };
+        let isn = |direction| match direction {
+            Direction::Outgoing => self.local_isn,
+            Direction::Incoming => self.peer_isn,
+        };
+        let (client_isn, server_isn) = (isn(client), isn(server));
+        let (client_next, server_next) = (client_isn.wrapping_add(1), server_isn.wrapping_add(1));
+        self.segment(client, client_isn, 0, TCP_SYN, &[]);
+        self.segment(server, server_isn, client_next, TCP_SYN | TCP_ACK, &[]);
+        self.segment(client, client_next, server_next, TCP_ACK, &[]);
+
+        self.local_seq = self.local_isn.wrapping_add(1);
+        self.peer_seq = self.peer_isn.wrapping_add(1);
+    }
+
+    fn close(&mut self) {
+        if !self.open {
+            return;
+        }
+        self.open = false;
+
+        // Which side closed first isn't logged, so the local side always does
+        let (local_seq, peer_seq) = (self.local_seq, self.peer_seq);
+        self.local_isn = local_seq.wrapping_add(ISN_GAP);
+        self.peer_isn = peer_seq.wrapping_add(ISN_GAP);
+        self.segment(
+            Direction::Outgoing,
+            local_seq,
+            peer_seq,
+            TCP_FIN | TCP_ACK,
+            &[],
+        );
+        self.segment(
+            Direction::Incoming,
+            peer_seq,
+            local_seq.wrapping_add(1),
+            TCP_FIN | TCP_ACK,
+            &[],
+        );
+        self.segment(
+            Direction::Outgoing,
+            local_seq.wrapping_add(1),
+            peer_seq.wrapping_add(1),
+            TCP_ACK,
+            &[],
+        );
+    }
+
+    fn data(&mut self, direction: Direction, packet: &DataPacket) {
+        // Traffic without a logged connect still gets a handshake so the stream can be followed
+        self.open();
+
+        let mut start = 0;
+        let len = packet.data.len();
+        let fragments = if packet.fragments.is_empty() {
+            std::slice::from_ref(&len)
+        } else {
+            &packet.fragments[..]
+        };
+        for &end in fragments {
+            for payload in packet.data[start..end].chunks(MAX_SEGMENT_LEN) {
+                let (seq, ack) = match direction {
+                    Direction::Outgoing => (&mut self.local_seq, self.peer_seq),
+                    Direction::Incoming => (&mut self.peer_seq, self.local_seq),
+                };
+                let this_seq = *seq;
+                *seq = seq.wrapping_add(payload.len() as u32);
+                self.segment(direction, this_seq, ack, TCP_PSH | TCP_ACK, payload);
+            }
+            start = end;
+        }
+    }
+
+    fn segment(&mut self, direction: Direction, seq: u32, ack: u32, flags: u8, payload: &[u8]) {
+        let (src, dst, src_mac, dst_mac) = match direction {
+            Direction::Outgoing => (self.stream.local, self.stream.peer, LOCAL_MAC, PEER_MAC),
+            Direction::Incoming => (self.stream.peer, self.stream.local, PEER_MAC, LOCAL_MAC),
+        };
+
+        let mut tcp = Vec::with_capacity(20 + payload.len());
+        tcp.extend(src.port().to_be_bytes());
+        tcp.extend(dst.port().to_be_bytes());
+        tcp.extend(seq.to_be_bytes());
+        tcp.extend(ack.to_be_bytes());
+        // Header length of 5 words, no options
+        tcp.push(5 << 4);
+        tcp.push(flags);
+        tcp.extend(u16::MAX.to_be_bytes());
+        // Checksum, filled in below
+        tcp.extend([0, 0]);
+        // Urgent pointer
+        tcp.extend([0, 0]);
+        tcp.extend(payload);
+
+        let mut frame = Vec::with_capacity(54 + tcp.len());
+        frame.extend(dst_mac);
+        frame.extend(src_mac);
+
+        match (src.ip(), dst.ip()) {
+            (IpAddr::V4(src_ip), IpAddr::V4(dst_ip)) => {
+                let mut pseudo_header = Vec::with_capacity(12);
+                pseudo_header.extend(src_ip.octets());
+                pseudo_header.extend(dst_ip.octets());
+                pseudo_header.extend([0, IP_PROTOCOL_TCP]);
+                pseudo_header.extend((tcp.len() as u16).to_be_bytes());
+                let sum = checksum(&pseudo_header, &tcp);
+                tcp[16..18].copy_from_slice(&sum.to_be_bytes());
+
+                let mut ip = Vec::with_capacity(20);
+                // Version 4, header length of 5 words
+                ip.push(0x45);
+                ip.push(0);
+                ip.extend(((20 + tcp.len()) as u16).to_be_bytes());
+                ip.extend(self.ip_id.to_be_bytes());
+                // Don't fragment
+                ip.extend(0x4000u16.to_be_bytes());
+                ip.push(64);
+                ip.push(IP_PROTOCOL_TCP);
+                ip.extend([0, 0]);
+                ip.extend(src_ip.octets());
+                ip.extend(dst_ip.octets());
+                let sum = checksum(&ip, &[]);
+                ip[10..12].copy_from_slice(&sum.to_be_bytes());
+                self.ip_id = self.ip_id.wrapping_add(1);
+
+                frame.extend(ETHERTYPE_IPV4.to_be_bytes());
+                frame.extend(ip);
+            }
+            (src_ip, dst_ip) => {
+                let (src_ip, dst_ip) = (to_ipv6(src_ip), to_ipv6(dst_ip));
+                let mut pseudo_header = Vec::with_capacity(40);
+                pseudo_header.extend(src_ip.octets());
+                pseudo_header.extend(dst_ip.octets());
+                pseudo_header.extend((tcp.len() as u32).to_be_bytes());
+                pseudo_header.extend([0, 0, 0, IP_PROTOCOL_TCP]);
+                let sum = checksum(&pseudo_header, &tcp);
+                tcp[16..18].copy_from_slice(&sum.to_be_bytes());
+
+                frame.extend(ETHERTYPE_IPV6.to_be_bytes());
+                // Version 6, no traffic class or flow label
+                frame.extend([0x60, 0, 0, 0]);
+                frame.extend((tcp.len() as u16).to_be_bytes());
+                frame.push(IP_PROTOCOL_TCP);
+                frame.push(64);
+                frame.extend(src_ip.octets());
+                frame.extend(dst_ip.octets());
+            }
+        }
+        frame.extend(tcp);
+
+        self.frames.push(Frame {
+            timestamp: self.timestamp,
+            data: frame,
+        });
+    }
+}
+
+fn to_ipv6(ip: IpAddr) -> Ipv6Addr {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    }
+}
+
+/// The internet checksum of `header` followed by `data`, `header` must have an even length.
+fn checksum(header: &[u8], data: &[u8]) -> u16 {
+    let mut sum = header
+        .chunks(2)
+        .chain(data.chunks(2))
+        .map(|word| u32::from(word[0]) << 8 | u32::from(*word.get(1).unwrap_or(&0)))
+        .fold(0u32, |sum, word| {
+            let sum = sum + word;
+            (sum & 0xFFFF) + (sum >> 16)
+        });
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Local, TimeDelta};
+
+    use super::*;
+    use crate::{
+        backend::Log,
+        capture::{self, Direction},
+    };
+
+    fn logs(logs: impl IntoIterator<Item = Log>) -> LogStore {
+        let start = DateTime::from_timestamp(1_700_000_000, 0)
+            .unwrap()
+            .with_timezone(&Local);
+        let mut store = LogStore::default();
+        for (i, log) in logs.into_iter().enumerate() {
+            store.push(log.at(start + TimeDelta::milliseconds(i as i64)));
+        }
+        store
+    }
+
+    fn packet(data: &[u8]) -> DataPacket {
+        DataPacket::new("peer".into(), data.to_vec())
+    }
+
+    fn messages(flow: &capture::Flow) -> Vec<(Direction, &[u8])> {
+        flow.messages
+            .iter()
+            .map(|m| (m.direction, &m.data[..]))
+            .collect()
+    }
+
+    #[test]
+    fn written_traffic_reads_back() {
+        let logs = logs([
+            Log::connect("peer".into()),
+            Log::sent(DataPacket::fragmented(
+                "peer".into(),
+                b"hello".to_vec(),
+                vec![2, 5],
+            )),
+            Log::received(packet(b"world")),
+            Log::sent(packet(b"!")),
+            Log::disconnect("peer".into()),
+        ]);
+        let stream = Stream {
+            local: "10.0.0.1:50000".parse().unwrap(),
+            peer: "10.0.0.2:80".parse().unwrap(),
+            local_is_client: true,
+            logs: &logs,
+        };
+        let mut file = Vec::new();
+        write(&mut file, &[stream]).unwrap();
+
+        let flows = capture::parse(&file).unwrap();
+        assert_eq!(flows.len(), 1);
+        let flow = &flows[0];
+        assert_eq!(flow.client, "10.0.0.1:50000".parse().unwrap());
+        assert_eq!(flow.server, "10.0.0.2:80".parse().unwrap());
+        assert_eq!(
+            messages(flow),
+            [
+                (Direction::ClientToServer, &b"hello"[..]),
+                (Direction::ServerToClient, b"world"),
+                (Direction::ClientToServer, b"!"),
+            ]
+        );
+        assert_eq!(flow.start, logs.get(0).unwrap().timestamp);
+        assert_eq!(flow.end, Some(logs.get(4).unwrap().timestamp));
+    }
+
+    #[test]
+    fn server_side_over_ipv6_reads_back() {
+        let logs = logs([
+            Log::received(packet(b"request")),
+            Log::sent(packet(&[7; MAX_SEGMENT_LEN * 2 + 10])),
+        ]);
+        let stream = Stream {
+            local: "[::1]:8080".parse().unwrap(),
+            peer: "[::1]:50000".parse().unwrap(),
+            local_is_client: false,
+            logs: &logs,
+        };
+        let mut file = Vec::new();
+        write(&mut file, &[stream]).unwrap();
+
+        let flows = capture::parse(&file).unwrap();
+        assert_eq!(flows.len(), 1);
+        let flow = &flows[0];
+        // There was no connect, so the handshake is made up when the first data arrives
+        assert_eq!(flow.client, "[::1]:50000".parse().unwrap());
+        assert_eq!(flow.server, "[::1]:8080".parse().unwrap());
+        assert_eq!(flow.end, None);
+        assert_eq!(
+            messages(flow),
+            [
+                (Direction::ClientToServer, &b"request"[..]),
+                (
+                    Direction::ServerToClient,
+                    &[7; MAX_SEGMENT_LEN * 2 + 10][..]
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn streams_are_interleaved_by_time() {
+        let first = logs([Log::connect("a".into()), Log::sent(packet(b"a"))]);
+        let second = logs([Log::connect("b".into()), Log::sent(packet(b"b"))]);
+        let stream = |local: &str, logs| Stream {
+            local: local.parse().unwrap(),
+            peer: "10.0.0.2:80".parse().unwrap(),
+            local_is_client: true,
+            logs,
+        };
+        let mut file = Vec::new();
+        write(
+            &mut file,
+            &[
+                stream("10.0.0.1:1000", &first),
+                stream("10.0.0.1:2000", &second),
+            ],
+        )
+        .unwrap();
+
+        let flows = capture::parse(&file).unwrap();
+        let clients: Vec<_> = flows.iter().map(|flow| flow.client.port()).collect();
+        assert_eq!(clients, [1000, 2000]);
+        assert_eq!(flows[1].messages[0].data, b"b");
+    }
+
+    #[test]
+    fn reconnects_start_new_connections_with_later_sequence_numbers() {
+        let logs = logs([
+            Log::connect("peer".into()),
+            Log::sent(packet(b"first")),
+            Log::received(packet(b"reply")),
+            Log::disconnect("peer".into()),
+            Log::connect("peer".into()),
+            Log::sent(packet(b"second")),
+            Log::disconnect("peer".into()),
+        ]);
+        let stream = Stream {
+            local: "10.0.0.1:50000".parse().unwrap(),
+            peer: "10.0.0.2:80".parse().unwrap(),
+            local_is_client: true,
+            logs: &logs,
+        };
+        let mut frames = Vec::new();
+        Synthesizer::new(&stream, &mut frames).run();
+
+        // Sequence numbers of the client's segments, after the Ethernet and IPv4 headers
+        let client_seqs: Vec<u32> = frames
+            .iter()
+            .map(|frame| &frame.data[34..])
+            .filter(|tcp| tcp[..2] == 50000u16.to_be_bytes())
+            .map(|tcp| u32::from_be_bytes(tcp[4..8].try_into().unwrap()))
+            .collect();
+        assert!(
+            client_seqs.windows(2).all(|pair| pair[0] <= pair[1]),
+            "{client_seqs:x?}"
+        );
+
+        let mut file = Vec::new();
+        write(&mut file, &[stream]).unwrap();
+        let flows = capture::parse(&file).unwrap();
+        assert_eq!(flows.len(), 2);
+        assert_eq!(
+            messages(&flows[0]),
+            [
+                (Direction::ClientToServer, &b"first"[..]),
+                (Direction::ServerToClient, b"reply"),
+            ]
+        );
+        assert_eq!(
+            messages(&flows[1]),
+            [(Direction::ClientToServer, &b"second"[..])]
+        );
+        assert_eq!(flows[1].start, logs.get(4).unwrap().timestamp);
+    }
+
+    #[test]
+    fn checksum_of_checksummed_data_is_zero() {
+        let mut header = vec![0x45, 0, 0, 20, 0, 1, 0x40, 0, 64, 6, 0, 0];
+        header.extend([10, 0, 0, 1, 10, 0, 0, 2]);
+        let sum = checksum(&header, &[]);
+        header[10..12].copy_from_slice(&sum.to_be_bytes());
+        assert_eq!(checksum(&header, &[]), 0);
+        // An odd byte at the end is padded with a zero
+        assert_eq!(checksum(&[], &[0x12]), checksum(&[0x12, 0], &[]));
+    }
+}
@@ -0,0 +1,909 @@
+use std::{fmt::Write as _, path::Path, time::Duration};
+
+use anyhow::{anyhow, bail, Context};
+use regex::bytes::Regex;
+use serde::Deserialize;
+use tokio::{
+    runtime::Handle,
+    sync::{
+        broadcast::{self, error::RecvError},
+        watch,
+    },
+    task::JoinHandle,
+    time::{sleep, timeout_at, Instant},
+};
+
+use crate::{
+    backend::{ConnectionHandle, Log, LogData, ServerHandle},
+    util::{hex_encode_formatted, parse_hex},
+};
+
+pub(crate) const DEFAULT_TIMEOUT_MS: u64 = 5000;
+
+/// A sequence of steps to run against a connection, loaded from a TOML file like:
+///
+/// ```toml
+/// name = "Handshake"
+/// connect = "127.0.0.1:54321" # or `listen = 54321` to wait for a client instead
+/// timeout_ms = 2000           # default timeout of expect steps
+///
+/// [[steps]]
+/// send = "01 02 03"           # or `send_text = "HELLO\r\n"`
+///
+/// [[steps]]
+/// expect = "0A 0B"            # or `expect_text`, `expect_regex` or `expect_length`
+/// timeout_ms = 500
+///
+/// [[steps]]
+/// sleep_ms = 100
+/// ```
+#[derive(Clone, Debug)]
+pub struct Scenario {
+    pub name: String,
+    pub target: Option<Target>,
+    pub steps: Vec<Step>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Target {
+    /// Connect to the given address as a client
+    Connect(String),
+    /// Listen on the given port and run against the first client
+    Listen(u16),
+}
+
+#[derive(Clone, Debug)]
+pub struct Step {
+    pub name: Option<String>,
+    pub action: Action,
+}
+
+#[derive(Clone, Debug)]
+pub enum Action {
+    Send(Vec<u8>),
+    Expect { matcher: Matcher, timeout: Duration },
+    Sleep(Duration),
+}
+
+#[derive(Clone, Debug)]
+pub enum Matcher {
+    /// The next bytes received must be exactly these
+    Exact(Vec<u8>),
+    /// Received data must match the regex, everything up to the end of the match is consumed
+    Regex(Regex),
+    /// The given number of bytes must be received
+    Length(usize),
+}
+
+impl Scenario {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let mut scenario = Self::from_toml(&text)?;
+        if scenario.name.is_empty() {
+            scenario.name = path
+                .file_stem()
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_default();
+        }
+        Ok(scenario)
+    }
+
+    pub fn from_toml(text: &str) -> anyhow::Result<Self> {
+        let raw: RawScenario = toml::from_str(text)?;
+        let default_timeout = Duration::from_millis(raw.timeout_ms.unwrap_or(DEFAULT_TIMEOUT_MS));
+
+        let target = match (raw.connect, raw.listen) {
+            (Some(_), Some(_)) => bail!("A scenario can't both connect and listen"),
+            (Some(address), None) => Some(Target::Connect(address)),
+            (None, Some(port)) => Some(Target::Listen(port)),
+            (None, None) => None,
+        };
+
+        let steps = raw
+            .steps
+            .into_iter()
+            .enumerate()
+            .map(|(i, step)| {
+                step.into_step(default_timeout)
+                    .with_context(|| format!("Invalid step {}", i + 1))
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Self {
+            name: raw.name.unwrap_or_default(),
+            target,
+            steps,
+        })
+    }
+}
+
+impl std::fmt::Display for Step {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(name) = &self.name {
+            return write!(f, "{name}");
+        }
+
+        match &self.action {
+            Action::Send(data) => write!(f, "Send {}", hex_encode_formatted(data)),
+            Action::Expect { matcher, .. } => match matcher {
+                Matcher::Exact(data) => write!(f, "Expect {}", hex_encode_formatted(data)),
+                Matcher::Regex(regex) => write!(f, "Expect /{}/", regex.as_str()),
+                Matcher::Length(len) => write!(f, "Expect {len} bytes"),
+            },
+            Action::Sleep(duration) => write!(f, "Sleep {}ms", duration.as_millis()),
+        }
+    }
+}
+
+impl std::fmt::Display for Target {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Connect(address) => write!(f, "Connect to {address}"),
+            Self::Listen(port) => write!(f, "Listen on {port}"),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawScenario {
+    name: Option<String>,
+    connect: Option<String>,
+    listen: Option<u16>,
+    timeout_ms: Option<u64>,
+    #[serde(default)]
+    steps: Vec<RawStep>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawStep {
+    name: Option<String>,
+    send: Option<String>,
+    send_text: Option<String>,
+    expect: Option<String>,
+    expect_text: Option<String>,
+    expect_regex: Option<String>,
+    expect_length: Option<usize>,
+    sleep_ms: Option<u64>,
+    timeout_ms: Option<u64>,
+}
+
+impl RawStep {
+    fn into_step(self, default_timeout: Duration) -> anyhow::Result<Step> {
+        let timeout = self
+            .timeout_ms
+            .map(Duration::from_millis)
+            .unwrap_or(default_timeout);
+        let expect = |matcher| Action::Expect { matcher, timeout };
+
+        let mut actions = Vec::new();
+        if let Some(hex) = self.send {
+            actions.push(Action::Send(parse_hex(&hex)?));
+        }
+        if let Some(text) = self.send_text {
+            actions.push(Action::Send(text.into_bytes()));
+        }
+        if let Some(hex) = self.expect {
+            actions.push(expect(Matcher::Exact(parse_hex(&hex)?)));
+        }
+        if let Some(text) = self.expect_text {
+            actions.push(expect(Matcher::Exact(text.into_bytes())));
+        }
+        if let Some(regex) = self.expect_regex {
+            actions.push(expect(Matcher::Regex(Regex::new(&regex)?)));
+        }
+        if let Some(len) = self.expect_length {
+            actions.push(expect(Matcher::Length(len)));
+        }
+        if let Some(ms) = self.sleep_ms {
+            actions.push(Action::Sleep(Duration::from_millis(ms)));
+        }
+
+        if actions.len() != 1 {
+            bail!("A step must have exactly one send, expect or sleep action");
+        }
+
+        Ok(Step {
+            name: self.name,
+            action: actions.pop().unwrap(),
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Outcome {
+    Passed,
+    Failed(String),
+    /// Not run because an earlier step failed
+    Skipped,
+}
+
+#[derive(Clone, Debug)]
+pub struct StepResult {
+    pub description: String,
+    pub outcome: Outcome,
+    pub elapsed: Duration,
+    pub expected: Option<Vec<u8>>,
+    pub actual: Option<Vec<u8>>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Report {
+    pub name: String,
+    /// Why the scenario couldn't be started, e.g. because connecting failed
+    pub error: Option<String>,
+    pub results: Vec<StepResult>,
+    pub elapsed: Duration,
+    pub finished: bool,
+}
+
+impl Report {
+    pub fn passed(&self) -> bool {
+        self.error.is_none()
+            && self
+                .results
+                .iter()
+                .all(|r| matches!(r.outcome, Outcome::Passed))
+    }
+
+    pub fn failures(&self) -> usize {
+        self.results
+            .iter()
+            .filter(|r| matches!(r.outcome, Outcome::Failed(_)))
+            .count()
+            + self.error.is_some() as usize
+    }
+
+    pub fn skipped(&self) -> usize {
+        self.results
+            .iter()
+            .filter(|r| matches!(r.outcome, Outcome::Skipped))
+            .count()
+    }
+
+    pub fn summary(&self) -> String {
+        if let Some(error) = &self.error {
+            return format!("{}: {error}", self.name);
+        }
+
+        format!(
+            "{}: {} {}, {} of {} steps passed, {} failed, {} skipped in {:.3}s",
+            self.name,
+            if self.finished { "finished" } else { "running" },
+            if self.passed() {
+                "successfully"
+            } else {
+                "with failures"
+            },
+            self.results.len() - self.failures() - self.skipped(),
+            self.results.len(),
+            self.failures(),
+            self.skipped(),
+            self.elapsed.as_secs_f64()
+        )
+    }
+
+    /// Formats the report as a JUnit XML test suite with one test case per step.
+    pub fn to_junit_xml(&self) -> String {
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        let _ = writeln!(
+            xml,
+            "<testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" skipped=\"{}\" time=\"{:.3}\">",
+            xml_escape(&self.name),
+            self.results.len() + self.error.is_some() as usize,
+            self.failures(),
+            self.skipped(),
+            self.elapsed.as_secs_f64()
+        );
+
+        if let Some(error) = &self.error {
+            let _ = writeln!(
+                xml,
+                "  <testcase name=\"Setup\" classname=\"{}\" time=\"0.000\">\n    <failure message=\"{}\"/>\n  </testcase>",
+                xml_escape(&self.name),
+                xml_escape(error)
+            );
+        }
+
+        for (i, result) in self.results.iter().enumerate() {
+            let _ = write!(
+                xml,
+                "  <testcase name=\"{}: {}\" classname=\"{}\" time=\"{:.3}\"",
+                i + 1,
+                xml_escape(&result.description),
+                xml_escape(&self.name),
+                result.elapsed.as_secs_f64()
+            );
+            match &result.outcome {
+                Outcome::Passed => xml.push_str("/>\n"),
+                Outcome::Skipped => xml.push_str(">\n    <skipped/>\n  </testcase>\n"),
+                Outcome::Failed(message) => {
+                    let mut details = String::new();
+                    if let Some(expected) = &result.expected {
+                        let _ = writeln!(details, "Expected: {}", hex_encode_formatted(expected));
+                    }
+                    if let Some(actual) = &result.actual {
+                        let _ = writeln!(details, "Actual:   {}", hex_encode_formatted(actual));
+                    }
+                    let _ = write!(
+                        xml,
+                        ">\n    <failure message=\"{}\">{}</failure>\n  </testcase>\n",
+                        xml_escape(message),
+                        xml_escape(&details)
+                    );
+                }
+            }
+        }
+
+        xml.push_str("</testsuite>\n");
+        xml
+    }
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// A scenario running on the tokio runtime.
+pub struct ScenarioRun {
+    report: watch::Receiver<Report>,
+    handle: JoinHandle<()>,
+}
+
+impl ScenarioRun {
+    /// Returns the report as of now, which includes the results of all steps run so far.
+    pub fn report(&self) -> Report {
+        self.report.borrow().clone()
+    }
+
+    pub fn is_running(&self) -> bool {
+        !self.handle.is_finished()
+    }
+
+    pub fn abort(&self) {
+        self.handle.abort();
+    }
+
+    /// Waits for the scenario to finish and returns the final report.
+    pub async fn finish(self) -> Report {
+        let _ = self.handle.await;
+        self.report.borrow().clone()
+    }
+}
+
+/// Runs `scenario` on a client connection, waiting for it to be established first if necessary.
+/// `events` must have been subscribed to before the connection was started.
+pub fn run_client(
+    scenario: Scenario,
+    conn: ConnectionHandle,
+    mut events: broadcast::Receiver<Log>,
+    rt: &Handle,
+) -> ScenarioRun {
+    spawn(scenario, rt, move || async move {
+        if conn.net_state() != crate::backend::NetState::Active {
+            loop {
+                match events.recv().await {
+                    Ok(log) => match log.data {
+                        LogData::ClientConnect(_) => break,
+                        LogData::ConnectError(error) => bail!("Failed to connect: {error}"),
+                        LogData::ConnectTimedOut => bail!("Failed to connect: Timed Out"),
+                        _ => {}
+                    },
+                    Err(RecvError::Lagged(missed)) => {
+                        bail!("Missed {missed} events while connecting")
+                    }
+                    Err(RecvError::Closed) => bail!("Connection was dropped"),
+                }
+            }
+        }
+        Ok((conn, events))
+    })
+}
+
+/// Runs `scenario` on the first client to connect to `server`.
+/// `events` must have been subscribed to before the server was started.
+pub fn run_server(
+    scenario: Scenario,
+    server: ServerHandle,
+    mut events: broadcast::Receiver<Log>,
+    rt: &Handle,
+) -> ScenarioRun {
+    spawn(scenario, rt, move || async move {
+        let address = loop {
+            match events.recv().await {
+                Ok(log) => match log.data {
+                    LogData::ClientConnect(address) => break address,
+                    LogData::ServerStartError(error) => bail!("Failed to start server: {error}"),
+                    LogData::ServerStopped => bail!("Server stopped before a client connected"),
+                    _ => {}
+                },
+                Err(RecvError::Lagged(missed)) => {
+                    bail!("Missed {missed} events while waiting for a client")
+                }
+                Err(RecvError::Closed) => bail!("Server was dropped"),
+            }
+        };
+
+        let conn = server
+            .connection(&address)
+            .ok_or_else(|| anyhow!("Client disconnected before the scenario started"))?;
+        Ok((conn, events))
+    })
+}
+
+fn spawn<F>(
+    scenario: Scenario,
+    rt: &Handle,
+    connect: impl FnOnce() -> F + Send + 'static,
+) -> ScenarioRun
+where
+    F: std::future::Future<Output = anyhow::Result<(ConnectionHandle, broadcast::Receiver<Log>)>>
+        + Send,
+{
+    let (report_tx, report_rx) = watch::channel(Report {
+        name: scenario.name.clone(),
+        ..Default::default()
+    });
+
+    let handle = rt.spawn(async move {
+        match connect().await {
+            Ok((conn, events)) => run_steps(&scenario, conn, events, &report_tx).await,
+            Err(error) => report_tx.send_modify(|r| r.error = Some(error.to_string())),
+        }
+        report_tx.send_modify(|r| r.finished = true);
+    });
+
+    ScenarioRun {
+        report: report_rx,
+        handle,
+    }
+}
+
+async fn run_steps(
+    scenario: &Scenario,
+    conn: ConnectionHandle,
+    events: broadcast::Receiver<Log>,
+    report_tx: &watch::Sender<Report>,
+) {
+    let start = Instant::now();
+    let mut inbox = Inbox {
+        address: conn.address().to_string(),
+        events,
+        buffer: Vec::new(),
+        closed: false,
+        missed: 0,
+    };
+    let mut failed = false;
+
+    for step in &scenario.steps {
+        let step_start = Instant::now();
+        let mut result = StepResult {
+            description: step.to_string(),
+            outcome: Outcome::Passed,
+            elapsed: Duration::ZERO,
+            expected: None,
+            actual: None,
+        };
+
+        if failed {
+            result.outcome = Outcome::Skipped;
+        } else {
+            match &step.action {
+                Action::Send(data) => {
+                    if let Err(error) = conn.send_data(data.clone()) {
+                        result.outcome = Outcome::Failed(error.to_string());
+                    }
+                }
+                Action::Expect { matcher, timeout } => {
+                    inbox.expect(matcher, *timeout, &mut result).await;
+                }
+                Action::Sleep(duration) => sleep(*duration).await,
+            }
+            failed = matches!(result.outcome, Outcome::Failed(_));
+        }
+
+        result.elapsed = step_start.elapsed();
+        report_tx.send_modify(|r| {
+            r.results.push(result);
+            r.elapsed = start.elapsed();
+        });
+    }
+}
+
+/// Data received on the connection under test that no step has consumed yet.
+struct Inbox {
+    address: String,
+    events: broadcast::Receiver<Log>,
+    buffer: Vec<u8>,
+    closed: bool,
+    /// Events that came faster than they were taken in, after which the data can't be trusted
+    missed: u64,
+}
+
+impl Inbox {
+    async fn expect(&mut self, matcher: &Matcher, timeout: Duration, result: &mut StepResult) {
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            let matched = match matcher {
+                Matcher::Exact(data) => {
+                    result.expected = Some(data.clone());
+                    (self.buffer.len() >= data.len()).then_some(data.len())
+                }
+                Matcher::Regex(regex) => regex.find(&self.buffer).map(|m| m.end()),
+                Matcher::Length(len) => (self.buffer.len() >= *len).then_some(*len),
+            };
+
+            if let Some(end) = matched {
+                let actual: Vec<u8> = self.buffer.drain(..end).collect();
+                if matches!(matcher, Matcher::Exact(data) if *data != actual) {
+                    result.outcome = Outcome::Failed("Received data doesn't match".into());
+                }
+                result.actual = Some(actual);
+                return;
+            }
+
+            if !self.fill(deadline).await {
+                result.outcome = Outcome::Failed(if self.missed > 0 {
+                    format!("Missed {} events", self.missed)
+                } else if self.closed {
+                    "Connection closed".into()
+                } else {
+                    format!("Timed out after {}ms", timeout.as_millis())
+                });
+                result.actual = Some(std::mem::take(&mut self.buffer));
+                return;
+            }
+        }
+    }
+
+    /// Waits for more data until `deadline`.
+    /// Returns `false` if none arrived in time, the connection was closed or events were missed.
+    async fn fill(&mut self, deadline: Instant) -> bool {
+        while !self.closed {
+            match timeout_at(deadline, self.events.recv()).await {
+                Ok(Ok(log)) => match log.data {
+                    LogData::ReceivedPacket(packet) if packet.address == self.address => {
+                        self.buffer.extend(packet.data);
+                        return true;
+                    }
+                    LogData::ClientDisconnect(address) if address == self.address => {
+                        self.closed = true
+                    }
+                    _ => {}
+                },
+                Ok(Err(RecvError::Lagged(missed))) => {
+                    self.missed = missed;
+                    return false;
+                }
+                Ok(Err(RecvError::Closed)) => self.closed = true,
+                Err(_) => return false,
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::DataPacket;
+
+    const ADDRESS: &str = "127.0.0.1:4000";
+
+    fn error(text: &str) -> String {
+        format!("{:#}", Scenario::from_toml(text).unwrap_err())
+    }
+
+    fn received(address: &str, data: &[u8]) -> Log {
+        Log::received(DataPacket::new(address.into(), data.to_vec()))
+    }
+
+    fn inbox(events: broadcast::Receiver<Log>) -> Inbox {
+        Inbox {
+            address: ADDRESS.into(),
+            events,
+            buffer: Vec::new(),
+            closed: false,
+            missed: 0,
+        }
+    }
+
+    fn result() -> StepResult {
+        StepResult {
+            description: String::new(),
+            outcome: Outcome::Passed,
+            elapsed: Duration::ZERO,
+            expected: None,
+            actual: None,
+        }
+    }
+
+    /// The failure message of expecting `matcher` after `logs` arrived, `None` if it passed.
+    async fn expect(logs: Vec<Log>, matcher: Matcher) -> (Option<String>, StepResult) {
+        let (events_tx, events) = broadcast::channel(16);
+        for log in logs {
+            events_tx.send(log).unwrap();
+        }
+        let mut inbox = inbox(events);
+        let mut result = result();
+        inbox
+            .expect(&matcher, Duration::from_millis(20), &mut result)
+            .await;
+        let failure = match &result.outcome {
+            Outcome::Failed(message) => Some(message.clone()),
+            _ => None,
+        };
+        (failure, result)
+    }
+
+    #[test]
+    fn scenarios_parse_from_toml() {
+        let scenario = Scenario::from_toml(
+            r#"
+            name = "Handshake"
+            connect = "127.0.0.1:54321"
+            timeout_ms = 2000
+
+            [[steps]]
+            send = "01 02 03"
+
+            [[steps]]
+            name = "Greeting"
+            send_text = "HELLO\r\n"
+
+            [[steps]]
+            expect = "0A 0B"
+            timeout_ms = 500
+
+            [[steps]]
+            expect_text = "OK"
+
+            [[steps]]
+            expect_regex = "\\d+"
+
+            [[steps]]
+            expect_length = 4
+
+            [[steps]]
+            sleep_ms = 100
+            "#,
+        )
+        .unwrap();
+        assert_eq!(scenario.name, "Handshake");
+        assert_eq!(
+            scenario.target,
+            Some(Target::Connect("127.0.0.1:54321".into()))
+        );
+
+        let descriptions: Vec<String> = scenario.steps.iter().map(Step::to_string).collect();
+        assert_eq!(
+            descriptions,
+            [
+                "Send 01 02 03",
+                "Greeting",
+                "Expect 0A 0B",
+                "Expect 4F 4B",
+                "Expect /\\d+/",
+                "Expect 4 bytes",
+                "Sleep 100ms",
+            ]
+        );
+        let timeouts: Vec<u128> = scenario
+            .steps
+            .iter()
+            .filter_map(|step| match step.action {
+                Action::Expect { timeout, .. } => Some(timeout.as_millis()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(timeouts, [500, 2000, 2000, 2000]);
+    }
+
+    #[test]
+    fn scenarios_default_to_no_target_and_the_default_timeout() {
+        let scenario = Scenario::from_toml("[[steps]]\nexpect_length = 1").unwrap();
+        assert_eq!(scenario.name, "");
+        assert_eq!(scenario.target, None);
+        assert!(matches!(
+            scenario.steps[0].action,
+            Action::Expect { timeout, .. } if timeout == Duration::from_millis(DEFAULT_TIMEOUT_MS)
+        ));
+        assert_eq!(
+            Scenario::from_toml("listen = 54321").unwrap().target,
+            Some(Target::Listen(54321))
+        );
+    }
+
+    #[test]
+    fn steps_need_exactly_one_action() {
+        let only_one = "Invalid step 2: A step must have exactly one send, expect or sleep action";
+        assert_eq!(
+            error("[[steps]]\nsleep_ms = 1\n[[steps]]\nsend = \"01\"\nexpect = \"02\""),
+            only_one
+        );
+        assert_eq!(
+            error("[[steps]]\nsleep_ms = 1\n[[steps]]\nname = \"Nothing\""),
+            only_one
+        );
+        assert_eq!(
+            error("[[steps]]\nsleep_ms = 1\n[[steps]]\nexpect_text = \"a\"\nexpect_length = 1"),
+            only_one
+        );
+    }
+
+    #[test]
+    fn invalid_scenarios_fail() {
+        assert_eq!(
+            error("connect = \"127.0.0.1:1\"\nlisten = 1"),
+            "A scenario can't both connect and listen"
+        );
+        assert!(error("[[steps]]\nsend = \"0G\"").starts_with("Invalid step 1: "));
+        assert!(error("[[steps]]\nexpect_regex = \"(\"").starts_with("Invalid step 1: "));
+        assert!(Scenario::from_toml("[[steps]]\nreceive = \"01\"").is_err());
+    }
+
+    #[tokio::test]
+    async fn exact_matches_consume_just_the_expected_bytes() {
+        let logs = vec![received(ADDRESS, b"hello world")];
+        let (failure, result) = expect(logs, Matcher::Exact(b"hello".to_vec())).await;
+        assert_eq!(failure, None);
+        assert_eq!(result.expected.as_deref(), Some(&b"hello"[..]));
+        assert_eq!(result.actual.as_deref(), Some(&b"hello"[..]));
+
+        let logs = vec![received(ADDRESS, b"help!")];
+        let (failure, result) = expect(logs, Matcher::Exact(b"hello".to_vec())).await;
+        assert_eq!(failure.as_deref(), Some("Received data doesn't match"));
+        assert_eq!(result.actual.as_deref(), Some(&b"help!"[..]));
+    }
+
+    #[tokio::test]
+    async fn regex_and_length_matches() {
+        let logs = vec![received(ADDRESS, b"id=42;rest")];
+        let (failure, result) = expect(logs, Matcher::Regex(Regex::new(r"\d+").unwrap())).await;
+        assert_eq!(failure, None);
+        assert_eq!(result.actual.as_deref(), Some(&b"id=42"[..]));
+
+        let logs = vec![received(ADDRESS, b"abcdef")];
+        let (failure, result) = expect(logs, Matcher::Length(4)).await;
+        assert_eq!(failure, None);
+        assert_eq!(result.actual.as_deref(), Some(&b"abcd"[..]));
+    }
+
+    #[tokio::test]
+    async fn expect_waits_for_data_across_receives() {
+        // Including traffic of other connections of a server, which doesn't count
+        let logs = vec![
+            received(ADDRESS, b"he"),
+            received("127.0.0.1:5000", b"xx"),
+            Log::script("unrelated".into()),
+            received(ADDRESS, b"l"),
+            received(ADDRESS, b"lo"),
+        ];
+        let (failure, result) = expect(logs, Matcher::Exact(b"hello".to_vec())).await;
+        assert_eq!(failure, None);
+        assert_eq!(result.actual.as_deref(), Some(&b"hello"[..]));
+    }
+
+    #[tokio::test]
+    async fn expect_fails_after_the_timeout_with_what_was_received() {
+        let logs = vec![received(ADDRESS, b"hel")];
+        let (failure, result) = expect(logs, Matcher::Exact(b"hello".to_vec())).await;
+        assert_eq!(failure.as_deref(), Some("Timed out after 20ms"));
+        assert_eq!(result.actual.as_deref(), Some(&b"hel"[..]));
+    }
+
+    #[tokio::test]
+    async fn expect_fails_once_the_connection_closes() {
+        let logs = vec![received(ADDRESS, b"a"), Log::disconnect(ADDRESS.into())];
+        let (failure, result) = expect(logs, Matcher::Length(2)).await;
+        assert_eq!(failure.as_deref(), Some("Connection closed"));
+        assert_eq!(result.actual.as_deref(), Some(&b"a"[..]));
+    }
+
+    #[tokio::test]
+    async fn expect_fails_when_events_were_missed() {
+        let (events_tx, events) = broadcast::channel(2);
+        for data in [b"a", b"b", b"c"] {
+            events_tx.send(received(ADDRESS, data)).unwrap();
+        }
+        let mut result = result();
+        inbox(events)
+            .expect(&Matcher::Length(3), Duration::from_secs(5), &mut result)
+            .await;
+        assert!(matches!(result.outcome, Outcome::Failed(message) if message == "Missed 1 events"));
+    }
+
+    fn report() -> Report {
+        let step = |description: &str, outcome| StepResult {
+            description: description.into(),
+            outcome,
+            elapsed: Duration::from_millis(5),
+            expected: None,
+            actual: None,
+        };
+        Report {
+            name: "Login & <more>".into(),
+            error: None,
+            results: vec![
+                step("Send 01", Outcome::Passed),
+                StepResult {
+                    expected: Some(vec![1, 2]),
+                    actual: Some(vec![3]),
+                    ..step(
+                        "Expect \"01 02\"",
+                        Outcome::Failed("Wanted <01> & 'then' 02".into()),
+                    )
+                },
+                step("Sleep 10ms", Outcome::Skipped),
+            ],
+            elapsed: Duration::from_millis(1500),
+            finished: true,
+        }
+    }
+
+    #[test]
+    fn summaries_count_the_outcomes() {
+        let mut report = report();
+        assert_eq!(
+            report.summary(),
+            "Login & <more>: finished with failures, 1 of 3 steps passed, 1 failed, 1 skipped in 1.500s"
+        );
+
+        report.results.truncate(1);
+        report.finished = false;
+        assert!(report.passed());
+        assert_eq!(
+            report.summary(),
+            "Login & <more>: running successfully, 1 of 1 steps passed, 0 failed, 0 skipped in 1.500s"
+        );
+
+        report.error = Some("Failed to connect: refused".into());
+        assert!(!report.passed());
+        assert_eq!(report.failures(), 1);
+        assert_eq!(
+            report.summary(),
+            "Login & <more>: Failed to connect: refused"
+        );
+    }
+
+    #[test]
+    fn junit_reports_escape_names_and_messages() {
+        assert_eq!(
+            report().to_junit_xml(),
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <testsuite name=\"Login &amp; &lt;more&gt;\" tests=\"3\" failures=\"1\" skipped=\"1\" time=\"1.500\">\n\
+             \x20 <testcase name=\"1: Send 01\" classname=\"Login &amp; &lt;more&gt;\" time=\"0.005\"/>\n\
+             \x20 <testcase name=\"2: Expect &quot;01 02&quot;\" classname=\"Login &amp; &lt;more&gt;\" time=\"0.005\">\n\
+             \x20   <failure message=\"Wanted &lt;01&gt; &amp; &apos;then&apos; 02\">Expected: 01 02\n\
+             Actual:   03\n\
+             </failure>\n\
+             \x20 </testcase>\n\
+             \x20 <testcase name=\"3: Sleep 10ms\" classname=\"Login &amp; &lt;more&gt;\" time=\"0.005\">\n\
+             \x20   <skipped/>\n\
+             \x20 </testcase>\n\
+             </testsuite>\n"
+        );
+    }
+
+    #[test]
+    fn junit_reports_list_setup_errors_as_a_failed_test() {
+        let report = Report {
+            name: "Handshake".into(),
+            error: Some("Failed to connect: \"refused\"".into()),
+            ..Default::default()
+        };
+        let xml = report.to_junit_xml();
+        assert!(xml.contains("tests=\"1\" failures=\"1\" skipped=\"0\""));
+        assert!(xml.contains(
+            "  <testcase name=\"Setup\" classname=\"Handshake\" time=\"0.000\">\n    \
+             <failure message=\"Failed to connect: &quot;refused&quot;\"/>\n  </testcase>\n"
+        ));
+    }
+}
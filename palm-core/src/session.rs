@@ -0,0 +1,351 @@
+//! Recorded sessions of a client or server, saved as Palm session files: a JSON header line
+//! followed by the logs in the [`logfile`] JSON Lines format. A session's connections can be
+//! turned into [`Flow`]s to replay one side of them against a new target.
+
+use std::{
+    io::{BufRead, BufReader, BufWriter, Read, Write},
+    net::SocketAddr,
+    path::Path,
+};
+
+use anyhow::{bail, Context};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    backend::{Log, LogData},
+    capture::{Direction, Flow, Message, Protocol, Side},
+    logfile::{self, Format},
+};
+
+pub const EXTENSION: &str = "palm";
+
+const VERSION: u32 = 1;
+
+#[derive(Clone, Debug)]
+pub struct Session {
+    /// The side Palm played while recording
+    pub side: Side,
+    /// The address connected to or the port listened on
+    pub target: String,
+    pub connections: Vec<RecordedConnection>,
+    pub logs: Vec<Log>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RecordedConnection {
+    /// The address the connection's logs refer to the peer by
+    pub address: String,
+    pub local: SocketAddr,
+    pub peer: SocketAddr,
+    /// When the connection was made, or the recording started if it was already open. Logs of the
+    /// connection's address from then on belong to it until the address connects again.
+    #[serde(default, with = "rfc3339")]
+    pub since: Option<DateTime<Local>>,
+}
+
+/// Timestamps as RFC 3339, the same as in the logs.
+mod rfc3339 {
+    use chrono::{DateTime, Local};
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        time: &Option<DateTime<Local>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match time {
+            Some(time) => serializer.serialize_some(&time.to_rfc3339()),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<DateTime<Local>>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|time| {
+                DateTime::parse_from_rfc3339(&time)
+                    .map(|time| time.with_timezone(&Local))
+                    .map_err(D::Error::custom)
+            })
+            .transpose()
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+    palm_session: u32,
+    side: Side,
+    target: String,
+    connections: Vec<RecordedConnection>,
+}
+
+impl Session {
+    pub fn write(&self, mut writer: impl Write) -> anyhow::Result<()> {
+        let header = Header {
+            palm_session: VERSION,
+            side: self.side,
+            target: self.target.clone(),
+            connections: self.connections.clone(),
+        };
+        serde_json::to_writer(&mut writer, &header)?;
+        writer.write_all(b"\n")?;
+        logfile::write(writer, &self.logs, Format::JsonLines)
+    }
+
+    pub fn read(reader: impl Read) -> anyhow::Result<Self> {
+        let mut reader = BufReader::new(reader);
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let header: Header = serde_json::from_str(&line).context("Not a Palm session file")?;
+        if header.palm_session != VERSION {
+            bail!("Unsupported session version {}", header.palm_session);
+        }
+
+        Ok(Self {
+            side: header.side,
+            target: header.target,
+            connections: header.connections,
+            logs: logfile::read(reader, Format::JsonLines)?,
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        self.write(BufWriter::new(std::fs::File::create(path)?))
+    }
+
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        Self::read(std::fs::File::open(path)?)
+    }
+
+    /// The traffic of each recorded connection as a flow, with the recording side's payloads
+    /// going from client to server if it was the client and the other way around otherwise.
+    pub fn flows(&self) -> Vec<Flow> {
+        self.connections
+            .iter()
+            .map(|conn| {
+                let (client, server, sent) = match self.side {
+                    Side::Client => (conn.local, conn.peer, Direction::ClientToServer),
+                    Side::Server => (conn.peer, conn.local, Direction::ServerToClient),
+                };
+                let received = match sent {
+                    Direction::ClientToServer => Direction::ServerToClient,
+                    Direction::ServerToClient => Direction::ClientToServer,
+                };
+
+                // A client that reconnected has a connection per connect, all to the same address
+                let until = self
+                    .connections
+                    .iter()
+                    .filter(|other| other.address == conn.address)
+                    .filter_map(|other| other.since)
+                    .filter(|&since| Some(since) > conn.since)
+                    .min();
+                let during = |log: &&Log| {
+                    conn.since.is_none_or(|since| log.timestamp >= since)
+                        && until.is_none_or(|until| log.timestamp < until)
+                };
+
+                let mut start = None;
+                let mut end = None;
+                let mut messages = Vec::new();
+                for log in self.logs.iter().filter(during) {
+                    let (direction, packet) = match &log.data {
+                        LogData::ClientConnect(address) if *address == conn.address => {
+                            start.get_or_insert(log.timestamp);
+                            continue;
+                        }
+                        LogData::ClientDisconnect(address) if *address == conn.address => {
+                            end = Some(log.timestamp);
+                            continue;
+                        }
+                        LogData::SentPacket(packet) => (sent, packet),
+                        LogData::ReceivedPacket(packet) => (received, packet),
+                        _ => continue,
+                    };
+                    if packet.address == conn.address {
+                        messages.push(Message {
+                            timestamp: log.timestamp,
+                            direction,
+                            data: packet.data.clone(),
+                        });
+                    }
+                }
+
+                Flow {
+                    protocol: Protocol::Tcp,
+                    client,
+                    server,
+                    start: start
+                        .or_else(|| messages.first().map(|m| m.timestamp))
+                        .unwrap_or_else(Local::now),
+                    end,
+                    messages,
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeDelta;
+
+    use super::*;
+    use crate::{backend::DataPacket, logfile::Record};
+
+    const PEER: &str = "10.0.0.2:80";
+
+    fn at(ms: i64) -> DateTime<Local> {
+        DateTime::from_timestamp(1_700_000_000, 0)
+            .unwrap()
+            .with_timezone(&Local)
+            + TimeDelta::milliseconds(ms)
+    }
+
+    fn packet(address: &str, data: &[u8]) -> DataPacket {
+        DataPacket::new(address.into(), data.to_vec())
+    }
+
+    fn connection(
+        address: &str,
+        local: &str,
+        peer: &str,
+        since: Option<i64>,
+    ) -> RecordedConnection {
+        RecordedConnection {
+            address: address.into(),
+            local: local.parse().unwrap(),
+            peer: peer.parse().unwrap(),
+            since: since.map(at),
+        }
+    }
+
+    fn messages(flow: &Flow) -> Vec<(Direction, &[u8])> {
+        flow.messages
+            .iter()
+            .map(|m| (m.direction, &m.data[..]))
+            .collect()
+    }
+
+    /// A client that connected, disconnected and connected again to the same server.
+    fn reconnected() -> Session {
+        Session {
+            side: Side::Client,
+            target: PEER.into(),
+            connections: vec![
+                connection(PEER, "10.0.0.1:50000", PEER, Some(0)),
+                connection(PEER, "10.0.0.1:50001", PEER, Some(30)),
+            ],
+            logs: vec![
+                Log::connect(PEER.into()).at(at(0)),
+                Log::sent(packet(PEER, b"first")).at(at(10)),
+                Log::received(packet(PEER, b"reply")).at(at(15)),
+                Log::disconnect(PEER.into()).at(at(20)),
+                Log::connect(PEER.into()).at(at(30)),
+                Log::sent(packet(PEER, b"second")).at(at(40)),
+                Log::script("not traffic".into()).at(at(45)),
+                Log::disconnect(PEER.into()).at(at(50)),
+            ],
+        }
+    }
+
+    #[test]
+    fn sessions_read_back() {
+        let mut session = reconnected();
+        session.connections[0].since = None;
+        let mut file = Vec::new();
+        session.write(&mut file).unwrap();
+        let read = Session::read(&file[..]).unwrap();
+
+        assert_eq!(read.side, session.side);
+        assert_eq!(read.target, session.target);
+        assert_eq!(
+            serde_json::to_value(&read.connections).unwrap(),
+            serde_json::to_value(&session.connections).unwrap()
+        );
+        assert_eq!(read.connections[1].since, Some(at(30)));
+        let records = |logs: &[Log]| -> Vec<_> {
+            logs.iter()
+                .map(|log| serde_json::to_value(Record::from(log)).unwrap())
+                .collect()
+        };
+        assert_eq!(records(&read.logs), records(&session.logs));
+    }
+
+    #[test]
+    fn other_files_fail_to_read() {
+        let error = |file: &str| format!("{:#}", Session::read(file.as_bytes()).unwrap_err());
+        assert!(error("{\"timestamp\":\"2025-01-01T00:00:00Z\"}\n")
+            .starts_with("Not a Palm session file"));
+        assert_eq!(
+            error(r#"{"palm_session":2,"side":"client","target":"","connections":[]}"#),
+            "Unsupported session version 2"
+        );
+    }
+
+    #[test]
+    fn reconnects_become_separate_flows() {
+        let flows = reconnected().flows();
+        assert_eq!(flows.len(), 2);
+
+        let first = &flows[0];
+        assert_eq!(first.client, "10.0.0.1:50000".parse().unwrap());
+        assert_eq!(first.server, PEER.parse().unwrap());
+        assert_eq!(
+            messages(first),
+            [
+                (Direction::ClientToServer, &b"first"[..]),
+                (Direction::ServerToClient, b"reply"),
+            ]
+        );
+        assert_eq!((first.start, first.end), (at(0), Some(at(20))));
+
+        let second = &flows[1];
+        assert_eq!(second.client, "10.0.0.1:50001".parse().unwrap());
+        assert_eq!(
+            messages(second),
+            [(Direction::ClientToServer, &b"second"[..])]
+        );
+        assert_eq!((second.start, second.end), (at(30), Some(at(50))));
+    }
+
+    #[test]
+    fn server_flows_only_have_their_clients_traffic() {
+        let (a, b) = ("127.0.0.1:50000", "127.0.0.1:50001");
+        let session = Session {
+            side: Side::Server,
+            target: "8080".into(),
+            connections: vec![
+                // Already connected when the recording started
+                connection(a, "127.0.0.1:8080", a, None),
+                connection(b, "127.0.0.1:8080", b, Some(5)),
+            ],
+            logs: vec![
+                Log::received(packet(a, b"from a")).at(at(0)),
+                Log::connect(b.into()).at(at(5)),
+                Log::received(packet(b, b"from b")).at(at(10)),
+                Log::sent(packet(a, b"to a")).at(at(15)),
+            ],
+        };
+        let flows = session.flows();
+
+        assert_eq!(flows[0].client, a.parse().unwrap());
+        assert_eq!(flows[0].server, "127.0.0.1:8080".parse().unwrap());
+        assert_eq!(
+            messages(&flows[0]),
+            [
+                (Direction::ClientToServer, &b"from a"[..]),
+                (Direction::ServerToClient, b"to a"),
+            ]
+        );
+        // Without a connect, the flow starts with its first message
+        assert_eq!((flows[0].start, flows[0].end), (at(0), None));
+
+        assert_eq!(
+            messages(&flows[1]),
+            [(Direction::ClientToServer, &b"from b"[..])]
+        );
+        assert_eq!(flows[1].start, at(5));
+    }
+}
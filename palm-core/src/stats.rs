@@ -0,0 +1,388 @@
+//! Traffic statistics of connections and servers, counted by the connections' tasks as data is
+//! read and written rather than derived from logs, so they stay accurate when logs are dropped.
+
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// How many seconds of throughput are kept for graphs.
+pub const HISTORY_SECONDS: usize = 120;
+
+/// Bytes moved in each direction, per second when used for throughput.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Throughput {
+    pub sent: u64,
+    pub received: u64,
+}
+
+/// Times between sending data and receiving the next data after it.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RoundTrips {
+    pub count: u32,
+    pub last: Duration,
+    pub min: Duration,
+    pub max: Duration,
+    total: Duration,
+}
+
+impl RoundTrips {
+    fn add(&mut self, rtt: Duration) {
+        self.min = if self.count == 0 {
+            rtt
+        } else {
+            self.min.min(rtt)
+        };
+        self.max = self.max.max(rtt);
+        self.last = rtt;
+        self.total += rtt;
+        self.count += 1;
+    }
+
+    pub fn average(&self) -> Option<Duration> {
+        self.total.checked_div(self.count)
+    }
+}
+
+/// A snapshot of the traffic of a connection, or of all connections of a server.
+#[derive(Clone, Debug, Default)]
+pub struct Stats {
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub packets_sent: u64,
+    pub packets_received: u64,
+    /// When the connection was established or the server started
+    pub started: Option<Instant>,
+    /// When the connection was closed or the server stopped, `None` while it's still running
+    pub stopped: Option<Instant>,
+    /// Bytes per second for up to the last [`HISTORY_SECONDS`], oldest first. The last second is
+    /// still in progress while running.
+    pub history: VecDeque<Throughput>,
+    /// The most bytes moved in any one second
+    pub peak: Throughput,
+    pub round_trips: RoundTrips,
+    /// How many seconds after starting the history ends
+    history_end: u64,
+    /// When data was last sent without anything received since, to time the round trip
+    awaiting_reply: Option<Instant>,
+}
+
+impl Stats {
+    pub fn duration(&self) -> Option<Duration> {
+        let started = self.started?;
+        Some(self.stopped.unwrap_or_else(Instant::now) - started)
+    }
+
+    /// Bytes moved during the last complete second.
+    pub fn current(&self) -> Throughput {
+        let complete = match self.stopped {
+            Some(_) => self.history.len(),
+            None => self.history.len().saturating_sub(1),
+        };
+        complete
+            .checked_sub(1)
+            .and_then(|i| self.history.get(i))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn average_sent_size(&self) -> Option<u64> {
+        self.bytes_sent.checked_div(self.packets_sent)
+    }
+
+    pub fn average_received_size(&self) -> Option<u64> {
+        self.bytes_received.checked_div(self.packets_received)
+    }
+
+    fn start(&mut self, now: Instant) {
+        *self = Self {
+            started: Some(now),
+            ..Self::default()
+        };
+        self.advance(now);
+    }
+
+    fn stop(&mut self, now: Instant) {
+        self.advance(now);
+        self.stopped = Some(now);
+    }
+
+    /// Adds empty seconds to the history until its last one is the one `now` is in.
+    fn advance(&mut self, now: Instant) {
+        let (Some(started), None) = (self.started, self.stopped) else {
+            return;
+        };
+        let end = now.saturating_duration_since(started).as_secs() + 1;
+        if end - self.history_end.min(end) > HISTORY_SECONDS as u64 {
+            self.history.clear();
+            self.history_end = end - HISTORY_SECONDS as u64;
+        }
+        while self.history_end < end {
+            if self.history.len() == HISTORY_SECONDS {
+                self.history.pop_front();
+            }
+            self.history.push_back(Throughput::default());
+            self.history_end += 1;
+        }
+    }
+
+    fn add(&mut self, now: Instant, sent: u64, received: u64) {
+        self.advance(now);
+        if let Some(second) = self.history.back_mut() {
+            second.sent += sent;
+            second.received += received;
+            self.peak.sent = self.peak.sent.max(second.sent);
+            self.peak.received = self.peak.received.max(second.received);
+        }
+    }
+
+    fn sent(&mut self, now: Instant, bytes: u64) {
+        self.add(now, bytes, 0);
+        self.bytes_sent += bytes;
+        self.packets_sent += 1;
+    }
+
+    fn received(&mut self, now: Instant, bytes: u64) {
+        self.add(now, 0, bytes);
+        self.bytes_received += bytes;
+        self.packets_received += 1;
+    }
+
+    /// Starts timing a round trip, unless data sent earlier is still awaiting a reply.
+    fn await_reply(&mut self, now: Instant) {
+        self.awaiting_reply.get_or_insert(now);
+    }
+
+    /// Ends the round trip being timed, if any, and returns how long it took.
+    fn reply(&mut self, now: Instant) -> Option<Duration> {
+        let rtt = self.awaiting_reply.take().map(|sent| now - sent)?;
+        self.round_trips.add(rtt);
+        Some(rtt)
+    }
+}
+
+/// Where a connection counts its traffic, shared with its tasks. Connections of a server also
+/// count into the server's stats.
+#[derive(Clone, Default)]
+pub(crate) struct StatsRecorder {
+    stats: Arc<Mutex<Stats>>,
+    server: Option<Arc<Mutex<Stats>>>,
+}
+
+impl StatsRecorder {
+    /// A recorder that also counts into the stats of `server`.
+    pub fn for_server_connection(server: &Self) -> Self {
+        Self {
+            stats: Arc::default(),
+            server: Some(server.stats.clone()),
+        }
+    }
+
+    /// Starts counting anew.
+    pub fn start(&self) {
+        self.stats.lock().unwrap().start(Instant::now());
+    }
+
+    pub fn stop(&self) {
+        self.stats.lock().unwrap().stop(Instant::now());
+    }
+
+    pub fn sent(&self, bytes: usize) {
+        let now = Instant::now();
+        let mut stats = self.stats.lock().unwrap();
+        stats.sent(now, bytes as u64);
+        stats.await_reply(now);
+        drop(stats);
+
+        if let Some(server) = &self.server {
+            server.lock().unwrap().sent(now, bytes as u64);
+        }
+    }
+
+    pub fn received(&self, bytes: usize) {
+        let now = Instant::now();
+        let mut stats = self.stats.lock().unwrap();
+        stats.received(now, bytes as u64);
+        let rtt = stats.reply(now);
+        drop(stats);
+
+        if let Some(server) = &self.server {
+            let mut server = server.lock().unwrap();
+            server.received(now, bytes as u64);
+            if let Some(rtt) = rtt {
+                server.round_trips.add(rtt);
+            }
+        }
+    }
+
+    /// The stats as of now, with the history reaching up to the current second.
+    pub fn snapshot(&self) -> Stats {
+        let mut stats = self.stats.lock().unwrap().clone();
+        stats.advance(Instant::now());
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    fn started(now: Instant) -> Stats {
+        let mut stats = Stats::default();
+        stats.start(now);
+        stats
+    }
+
+    fn throughput(sent: u64, received: u64) -> Throughput {
+        Throughput { sent, received }
+    }
+
+    #[test]
+    fn traffic_is_counted_per_second() {
+        let t0 = Instant::now();
+        let mut stats = started(t0);
+        assert_eq!(stats.history, [Throughput::default()]);
+
+        stats.sent(t0 + ms(500), 10);
+        stats.sent(t0 + ms(1200), 5);
+        stats.received(t0 + ms(1900), 3);
+        assert_eq!(stats.history, [throughput(10, 0), throughput(5, 3)]);
+        // The second still in progress doesn't count yet
+        assert_eq!(stats.current(), throughput(10, 0));
+
+        // Seconds without traffic are empty
+        stats.received(t0 + ms(3100), 1);
+        assert_eq!(
+            stats.history,
+            [
+                throughput(10, 0),
+                throughput(5, 3),
+                Throughput::default(),
+                throughput(0, 1),
+            ]
+        );
+        assert_eq!((stats.bytes_sent, stats.packets_sent), (15, 2));
+        assert_eq!((stats.bytes_received, stats.packets_received), (4, 2));
+
+        // Once stopped, the last second is complete
+        stats.stop(t0 + ms(3500));
+        assert_eq!(stats.current(), throughput(0, 1));
+        assert_eq!(stats.duration(), Some(ms(3500)));
+        // And nothing is added anymore
+        stats.advance(t0 + ms(10_000));
+        assert_eq!(stats.history.len(), 4);
+    }
+
+    #[test]
+    fn history_keeps_the_last_seconds() {
+        let t0 = Instant::now();
+        let mut stats = started(t0);
+        for second in 0..HISTORY_SECONDS as u64 + 10 {
+            stats.sent(t0 + Duration::from_secs(second), second);
+        }
+        assert_eq!(stats.history.len(), HISTORY_SECONDS);
+        assert_eq!(stats.history.front(), Some(&throughput(10, 0)));
+        assert_eq!(
+            stats.history.back(),
+            Some(&throughput(HISTORY_SECONDS as u64 + 9, 0))
+        );
+    }
+
+    #[test]
+    fn gaps_longer_than_the_history_clear_it() {
+        let t0 = Instant::now();
+        let mut stats = started(t0);
+        stats.sent(t0, 100);
+        stats.received(t0 + Duration::from_secs(500), 7);
+
+        assert_eq!(stats.history.len(), HISTORY_SECONDS);
+        assert!(stats
+            .history
+            .iter()
+            .take(HISTORY_SECONDS - 1)
+            .all(|second| *second == Throughput::default()));
+        assert_eq!(stats.history.back(), Some(&throughput(0, 7)));
+
+        // The next second lines up with the time since starting
+        stats.received(t0 + Duration::from_secs(501), 2);
+        assert_eq!(stats.history.len(), HISTORY_SECONDS);
+        assert_eq!(stats.history.back(), Some(&throughput(0, 2)));
+    }
+
+    #[test]
+    fn peak_is_the_busiest_second_of_each_direction() {
+        let t0 = Instant::now();
+        let mut stats = started(t0);
+        stats.sent(t0, 10);
+        stats.sent(t0 + ms(900), 10);
+        stats.received(t0 + ms(1000), 5);
+        stats.sent(t0 + ms(1500), 15);
+        stats.received(t0 + Duration::from_secs(5), 8);
+        assert_eq!(stats.peak, throughput(20, 8));
+    }
+
+    #[test]
+    fn round_trips_are_timed_from_a_send_to_the_next_receive() {
+        let t0 = Instant::now();
+        let mut stats = started(t0);
+        assert_eq!(stats.reply(t0), None);
+
+        stats.await_reply(t0);
+        // Sending more before a reply doesn't restart the timing
+        stats.await_reply(t0 + ms(10));
+        assert_eq!(stats.reply(t0 + ms(30)), Some(ms(30)));
+        // Only the first receive after sending is a reply
+        assert_eq!(stats.reply(t0 + ms(40)), None);
+
+        stats.await_reply(t0 + ms(50));
+        assert_eq!(stats.reply(t0 + ms(60)), Some(ms(10)));
+
+        let round_trips = stats.round_trips;
+        assert_eq!(round_trips.count, 2);
+        assert_eq!(
+            (round_trips.min, round_trips.max, round_trips.last),
+            (ms(10), ms(30), ms(10))
+        );
+        assert_eq!(round_trips.average(), Some(ms(20)));
+    }
+
+    #[test]
+    fn averages_need_packets() {
+        let t0 = Instant::now();
+        let mut stats = started(t0);
+        assert_eq!(stats.average_sent_size(), None);
+        assert_eq!(stats.average_received_size(), None);
+        assert_eq!(stats.round_trips.average(), None);
+
+        stats.sent(t0, 10);
+        stats.sent(t0, 21);
+        assert_eq!(stats.average_sent_size(), Some(15));
+        assert_eq!(stats.average_received_size(), None);
+    }
+
+    #[test]
+    fn connections_count_into_their_server() {
+        let server = StatsRecorder::default();
+        server.start();
+        let conn = StatsRecorder::for_server_connection(&server);
+        conn.start();
+        conn.sent(4);
+        conn.received(6);
+        // Other connections start out on their own
+        let other = StatsRecorder::for_server_connection(&server);
+        other.start();
+        other.received(1);
+
+        let (conn, server) = (conn.snapshot(), server.snapshot());
+        assert_eq!((conn.bytes_sent, conn.bytes_received), (4, 6));
+        assert_eq!((server.bytes_sent, server.bytes_received), (4, 7));
+        assert_eq!(server.packets_received, 2);
+        assert_eq!(conn.round_trips.count, 1);
+        assert_eq!(server.round_trips.count, 1);
+    }
+}
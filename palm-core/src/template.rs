@@ -0,0 +1,227 @@
+//! Templates naming the bit fields at the start of packets, for protocols that pack flags into
+//! bits.
+
+use std::{ops::Range, path::Path};
+
+use anyhow::{bail, Context};
+use serde::Deserialize;
+
+/// Most bits a field can span, so that its value fits in a `u64`.
+const MAX_FIELD_BITS: usize = 64;
+
+/// Names of ranges of bits, loaded from a TOML file like:
+///
+/// ```toml
+/// name = "Control Header"
+///
+/// [[fields]]
+/// name = "version"
+/// bits = 3        # fields follow one another from the most significant bit of the first byte
+///
+/// [[fields]]
+/// name = "ack"
+/// bits = 1
+///
+/// [[fields]]
+/// name = "length"
+/// at = 16         # or start at a given bit
+/// bits = 16
+/// ```
+#[derive(Clone, Debug)]
+pub struct Template {
+    pub name: String,
+    pub fields: Vec<Field>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Field {
+    pub name: String,
+    /// Bits it spans, counted from the most significant bit of the first byte
+    pub bits: Range<usize>,
+}
+
+impl Template {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let mut template = Self::from_toml(&text)?;
+        if template.name.is_empty() {
+            template.name = path
+                .file_stem()
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_default();
+        }
+        Ok(template)
+    }
+
+    pub fn from_toml(text: &str) -> anyhow::Result<Self> {
+        let raw: RawTemplate = toml::from_str(text)?;
+        let mut next = 0;
+        let fields = raw
+            .fields
+            .into_iter()
+            .map(|field| {
+                if field.bits == 0 || field.bits > MAX_FIELD_BITS {
+                    bail!(
+                        "Field {:?} must have 1 to {MAX_FIELD_BITS} bits",
+                        field.name
+                    );
+                }
+                let start = field.at.unwrap_or(next);
+                let Some(end) = start.checked_add(field.bits) else {
+                    bail!("Field {:?} ends past the last bit", field.name);
+                };
+                next = end;
+                Ok(Field {
+                    name: field.name,
+                    bits: start..next,
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Self {
+            name: raw.name.unwrap_or_default(),
+            fields,
+        })
+    }
+
+    /// The first field with bit `bit`, and its index.
+    pub fn field_at(&self, bit: usize) -> Option<(usize, &Field)> {
+        self.fields
+            .iter()
+            .enumerate()
+            .find(|(_, field)| field.bits.contains(&bit))
+    }
+}
+
+impl Field {
+    /// The value of the field's bits in `data`, most significant first. `None` if `data` ends
+    /// before the field does.
+    pub fn value(&self, data: &[u8]) -> Option<u64> {
+        if self.bytes().end > data.len() {
+            return None;
+        }
+        Some(
+            self.bits
+                .clone()
+                .fold(0, |value, bit| value << 1 | u64::from(bit_of(data, bit))),
+        )
+    }
+
+    /// The bytes the field has bits in.
+    pub fn bytes(&self) -> Range<usize> {
+        self.bits.start / 8..self.bits.end.div_ceil(8)
+    }
+}
+
+/// Bit `bit` of `data`, counted from the most significant bit of the first byte.
+pub fn bit_of(data: &[u8], bit: usize) -> bool {
+    data[bit / 8] & (0x80 >> (bit % 8)) != 0
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawTemplate {
+    name: Option<String>,
+    #[serde(default)]
+    fields: Vec<RawField>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawField {
+    name: String,
+    bits: usize,
+    at: Option<usize>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(name: &str, bits: Range<usize>) -> Field {
+        Field {
+            name: name.to_string(),
+            bits,
+        }
+    }
+
+    #[test]
+    fn fields_follow_one_another_unless_placed() {
+        let template = Template::from_toml(
+            r#"
+            name = "Control Header"
+
+            [[fields]]
+            name = "version"
+            bits = 3
+
+            [[fields]]
+            name = "ack"
+            bits = 1
+
+            [[fields]]
+            name = "length"
+            at = 16
+            bits = 16
+
+            [[fields]]
+            name = "after"
+            bits = 64
+            "#,
+        )
+        .unwrap();
+        assert_eq!(template.name, "Control Header");
+        assert_eq!(
+            template.fields,
+            [
+                field("version", 0..3),
+                field("ack", 3..4),
+                field("length", 16..32),
+                field("after", 32..96),
+            ]
+        );
+        assert_eq!(template.field_at(3).map(|(i, _)| i), Some(1));
+        assert_eq!(template.field_at(8), None);
+    }
+
+    #[test]
+    fn an_empty_template_has_no_name_or_fields() {
+        let template = Template::from_toml("").unwrap();
+        assert_eq!(template.name, "");
+        assert!(template.fields.is_empty());
+    }
+
+    #[test]
+    fn invalid_templates_fail() {
+        let error = |text| format!("{:#}", Template::from_toml(text).unwrap_err());
+        assert_eq!(
+            error("[[fields]]\nname = \"flag\"\nbits = 0"),
+            "Field \"flag\" must have 1 to 64 bits"
+        );
+        assert_eq!(
+            error("[[fields]]\nname = \"wide\"\nbits = 65"),
+            "Field \"wide\" must have 1 to 64 bits"
+        );
+        assert_eq!(
+            error(&format!(
+                "[[fields]]\nname = \"last\"\nat = {}\nbits = 2",
+                usize::MAX
+            )),
+            "Field \"last\" ends past the last bit"
+        );
+        assert!(Template::from_toml("[[fields]]\nname = \"flag\"\nbit = 1").is_err());
+        assert!(Template::from_toml("[[fields]]\nbits = 1").is_err());
+    }
+
+    #[test]
+    fn values_are_read_most_significant_bit_first() {
+        let data = [0b1011_0110, 0b0100_0000, 0xFF];
+        assert_eq!(field("version", 0..3).value(&data), Some(0b101));
+        assert_eq!(field("across", 6..10).value(&data), Some(0b1001));
+        assert_eq!(field("byte", 16..24).value(&data), Some(0xFF));
+        assert_eq!(field("past", 20..28).value(&data), None);
+        assert_eq!(field("all", 0..64).value(&[0xFF; 8]), Some(u64::MAX));
+        assert_eq!(field("across", 6..10).bytes(), 0..2);
+    }
+}
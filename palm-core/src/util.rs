@@ -0,0 +1,85 @@
+use serde::{Deserialize, Serialize};
+
+/// How bytes are written as hex, or as bits: in groups of some bytes with a space between groups.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HexFormat {
+    /// Bytes per group
+    pub group: usize,
+    pub uppercase: bool,
+    /// Whether bytes are written as their 8 bits rather than 2 hex digits
+    pub binary: bool,
+}
+
+impl Default for HexFormat {
+    fn default() -> Self {
+        Self {
+            group: 1,
+            uppercase: true,
+            binary: false,
+        }
+    }
+}
+
+impl HexFormat {
+    pub fn encode<T: AsRef<[u8]>>(&self, data: T) -> String {
+        let data = data.as_ref();
+        let mut text = String::with_capacity(self.width(data.len()));
+        for (i, byte) in data.iter().enumerate() {
+            if i != 0 && i % self.group.max(1) == 0 {
+                text.push(' ');
+            }
+            text.push_str(&self.byte(*byte));
+        }
+        text
+    }
+
+    pub fn byte(&self, byte: u8) -> String {
+        if self.binary {
+            format!("{byte:08b}")
+        } else if self.uppercase {
+            format!("{byte:02X}")
+        } else {
+            format!("{byte:02x}")
+        }
+    }
+
+    /// The base bytes are written in, 2 or 16.
+    pub fn radix(&self) -> u32 {
+        if self.binary {
+            2
+        } else {
+            16
+        }
+    }
+
+    /// How many digits a byte takes.
+    pub fn digits(&self) -> usize {
+        if self.binary {
+            8
+        } else {
+            2
+        }
+    }
+
+    /// How many characters `len` bytes take.
+    pub fn width(&self, len: usize) -> usize {
+        (len * self.digits() + len.div_ceil(self.group.max(1))).saturating_sub(1)
+    }
+
+    /// The character the `index`th byte of a line starts at.
+    pub fn offset(&self, index: usize) -> usize {
+        index * self.digits() + index / self.group.max(1)
+    }
+}
+
+/// Encodes `data` as uppercase hex with a space between bytes.
+pub fn hex_encode_formatted<T: AsRef<[u8]>>(data: T) -> String {
+    HexFormat::default().encode(data)
+}
+
+/// Decodes hex digits, ignoring any whitespace between them.
+pub fn parse_hex(text: &str) -> anyhow::Result<Vec<u8>> {
+    let cleaned: String = text.chars().filter(|c| !c.is_whitespace()).collect();
+    Ok(hex::decode(cleaned)?)
+}
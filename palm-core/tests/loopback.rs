@@ -0,0 +1,161 @@
+//! A client and a server talking to each other on localhost.
+
+use std::time::Duration;
+
+use palm_core::backend::{Connection, Fragmentation, Log, LogData, NetState, SendOptions, Server};
+use tokio::{sync::broadcast, time::timeout};
+
+/// Waits for the first event `f` picks something out of.
+async fn wait_for<T>(
+    events: &mut broadcast::Receiver<Log>,
+    mut f: impl FnMut(LogData) -> Option<T>,
+) -> T {
+    timeout(Duration::from_secs(5), async {
+        loop {
+            if let Some(found) = f(events.recv().await.unwrap().data) {
+                return found;
+            }
+        }
+    })
+    .await
+    .expect("Timed out waiting for an event")
+}
+
+/// Waits until `len` bytes were received, however they were split up on the way.
+async fn received(events: &mut broadcast::Receiver<Log>, len: usize) -> Vec<u8> {
+    let mut data = Vec::new();
+    while data.len() < len {
+        data.extend(
+            wait_for(events, |log| match log {
+                LogData::ReceivedPacket(packet) => Some(packet.data),
+                _ => None,
+            })
+            .await,
+        );
+    }
+    data
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn client_and_server_exchange_data() {
+    let server = Server::listen(0).await.unwrap();
+    let port = server.port().unwrap();
+    let mut server_events = server.subscribe();
+
+    let mut client = Connection::connect(format!("127.0.0.1:{port}"))
+        .await
+        .unwrap();
+    let mut client_events = client.subscribe();
+    assert_eq!(client.net_state(), NetState::Active);
+
+    let address = wait_for(&mut server_events, |log| match log {
+        LogData::ClientConnect(address) => Some(address),
+        _ => None,
+    })
+    .await;
+    let endpoints = client.endpoints().unwrap();
+    assert_eq!(address, endpoints.local.to_string());
+    assert_eq!(endpoints.peer.port(), port);
+
+    client.send_data(b"ping".to_vec()).unwrap();
+    assert_eq!(received(&mut server_events, 4).await, b"ping");
+
+    let conn = server.handle().connection(&address).unwrap();
+    conn.send_data(b"pong".to_vec()).unwrap();
+    assert_eq!(received(&mut client_events, 4).await, b"pong");
+
+    client.shutdown();
+    let disconnected = wait_for(&mut server_events, |log| match log {
+        LogData::ClientDisconnect(address) => Some(address),
+        _ => None,
+    })
+    .await;
+    assert_eq!(disconnected, address);
+
+    // Everything that happened is also kept in the logs once polled
+    let kinds: Vec<_> = client
+        .poll_logs()
+        .filter_map(|log| match &log.data {
+            LogData::SentPacket(packet) => Some(("sent", packet.data.clone())),
+            LogData::ReceivedPacket(packet) => Some(("received", packet.data.clone())),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        kinds,
+        [("sent", b"ping".to_vec()), ("received", b"pong".to_vec())]
+    );
+    server.poll_connection_logs();
+    server.with_connection(&address, |conn| {
+        let conn = conn.unwrap();
+        assert!(conn
+            .logs()
+            .iter()
+            .any(|log| matches!(&log.data, LogData::ReceivedPacket(p) if p.data == b"ping")));
+    });
+
+    server.shutdown();
+    wait_for(&mut server_events, |log| {
+        matches!(log, LogData::ServerStopped).then_some(())
+    })
+    .await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn server_broadcasts_to_every_connection() {
+    let server = Server::listen(0).await.unwrap();
+    let address = format!("127.0.0.1:{}", server.port().unwrap());
+    let mut server_events = server.subscribe();
+
+    let first = Connection::connect(address.clone()).await.unwrap();
+    let second = Connection::connect(address).await.unwrap();
+    let (mut first_events, mut second_events) = (first.subscribe(), second.subscribe());
+    for _ in 0..2 {
+        wait_for(&mut server_events, |log| {
+            matches!(log, LogData::ClientConnect(_)).then_some(())
+        })
+        .await;
+    }
+
+    server.handle().broadcast(b"hello".to_vec()).unwrap();
+    assert_eq!(received(&mut first_events, 5).await, b"hello");
+    assert_eq!(received(&mut second_events, 5).await, b"hello");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn connecting_to_a_closed_port_fails() {
+    // Bind a port and free it again so that nothing listens on it
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    drop(listener);
+
+    assert!(Connection::connect(format!("127.0.0.1:{port}"))
+        .await
+        .is_err());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn shutting_down_mid_packet_only_counts_what_was_sent() {
+    let server = Server::listen(0).await.unwrap();
+    let mut server_events = server.subscribe();
+    let client = Connection::connect(format!("127.0.0.1:{}", server.port().unwrap()))
+        .await
+        .unwrap();
+    let mut client_events = client.subscribe();
+
+    let options = SendOptions {
+        fragmentation: Fragmentation::FixedSize(2),
+        delay: Duration::from_secs(60),
+    };
+    client.send_data_with(b"abcd".to_vec(), &options).unwrap();
+    assert_eq!(received(&mut server_events, 2).await, b"ab");
+
+    client.shutdown();
+    let error = wait_for(&mut client_events, |log| match log {
+        LogData::FatalWriteError(error) => Some(error.to_string()),
+        _ => None,
+    })
+    .await;
+    assert_eq!(error, "Shut down after sending 2 of 4 bytes");
+    assert_eq!(client.stats().bytes_sent, 2);
+}
@@ -0,0 +1,458 @@
+use std::{
+    io::{BufRead, Read, Write},
+    path::PathBuf,
+    sync::mpsc,
+    thread::sleep,
+    time::Duration,
+};
+
+use anyhow::Context;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use palm_core::{
+    backend::{Connection, ConnectionHandle, Log, LogData, NetState, Server},
+    logfile,
+    scenario::{self, Outcome, Scenario, Target},
+    util::{hex_encode_formatted, parse_hex},
+};
+use tokio::runtime::Runtime;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Test TCP clients and servers. Launches the GUI if no command is given.
+#[derive(Parser)]
+#[command(version)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Connect to a server, send the given payloads and print all traffic
+    Connect {
+        /// Address to connect to, e.g. 127.0.0.1:54321
+        address: String,
+        #[command(flatten)]
+        payload: PayloadArgs,
+        /// Disconnect this many milliseconds after all payloads were sent,
+        /// instead of waiting for the server to close the connection
+        #[arg(long, value_name = "MS")]
+        wait: Option<u64>,
+        #[arg(long, value_enum, default_value_t)]
+        format: OutputFormat,
+    },
+    /// Listen for clients, send the given payloads to each of them and print all traffic
+    Listen {
+        port: u16,
+        #[command(flatten)]
+        payload: PayloadArgs,
+        /// Stop after the first client disconnects
+        #[arg(long)]
+        once: bool,
+        #[arg(long, value_enum, default_value_t)]
+        format: OutputFormat,
+    },
+    /// Run a scenario file, exiting with a non-zero code if any step fails
+    Run {
+        scenario: PathBuf,
+        /// Connect to this address instead of the scenario's target
+        #[arg(long, conflicts_with = "listen")]
+        connect: Option<String>,
+        /// Listen on this port instead of using the scenario's target
+        #[arg(long)]
+        listen: Option<u16>,
+        /// Write the report as JUnit XML to this file
+        #[arg(long, value_name = "FILE")]
+        junit: Option<PathBuf>,
+        #[arg(long, value_enum, default_value_t)]
+        format: OutputFormat,
+    },
+}
+
+#[derive(Args)]
+pub struct PayloadArgs {
+    /// Hex encoded payload to send, can be repeated
+    #[arg(long, value_name = "HEX")]
+    send: Vec<String>,
+    /// Text payload to send, can be repeated
+    #[arg(long, value_name = "TEXT")]
+    send_text: Vec<String>,
+    /// File to send the contents of, `-` streams stdin
+    #[arg(long, value_name = "FILE")]
+    input: Option<PathBuf>,
+    /// Treat the input as hex digits rather than raw bytes
+    #[arg(long, requires = "input")]
+    hex_input: bool,
+}
+
+#[derive(Clone, Copy, Default, ValueEnum)]
+pub enum OutputFormat {
+    /// Packets as hex bytes
+    #[default]
+    Hex,
+    /// Packets as text, escaping anything that isn't printable
+    Text,
+    /// One JSON object per log, as in exported JSON Lines logs
+    Json,
+}
+
+/// Runs `command`, returning the process' exit code.
+pub fn run(command: Command) -> i32 {
+    let result = match command {
+        Command::Connect {
+            address,
+            payload,
+            wait,
+            format,
+        } => connect(address, payload, wait, format),
+        Command::Listen {
+            port,
+            payload,
+            once,
+            format,
+        } => listen(port, payload, once, format),
+        Command::Run {
+            scenario,
+            connect,
+            listen,
+            junit,
+            format,
+        } => run_scenario(scenario, connect, listen, junit, format),
+    };
+
+    match result {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(error) => {
+            print_error(error);
+            2
+        }
+    }
+}
+
+fn print_error(error: anyhow::Error) {
+    eprintln!("Error: {error:#}");
+}
+
+fn connect(
+    address: String,
+    payload: PayloadArgs,
+    wait: Option<u64>,
+    format: OutputFormat,
+) -> anyhow::Result<bool> {
+    let payloads = payload.read()?;
+    let rt = Runtime::new()?;
+    let mut conn = Connection::new();
+    conn.start_client(address, rt.handle())?;
+
+    let mut payloads = Some(payloads);
+    let (errors_tx, errors) = mpsc::channel();
+    let mut failed = false;
+    let succeeded = 'outer: loop {
+        for log in conn.poll_logs() {
+            print_log(log, format);
+            match log.data {
+                LogData::ConnectError(_) | LogData::ConnectTimedOut => break 'outer false,
+                LogData::FatalReadError(_) | LogData::FatalWriteError(_) => failed = true,
+                LogData::ClientDisconnect(_) => break 'outer !failed,
+                _ => {}
+            }
+        }
+        if let Ok(error) = errors.try_recv() {
+            print_error(error);
+            break false;
+        }
+
+        if conn.net_state() == NetState::Active && payloads.is_some() {
+            let payloads = payloads.take().unwrap();
+            let handle = conn.handle();
+            let errors_tx = errors_tx.clone();
+            rt.spawn_blocking(move || {
+                let sent = payloads
+                    .send_packets(&handle)
+                    .and_then(|_| payloads.stream_stdin(|data| handle.send_data(data)));
+                match sent {
+                    Ok(()) => {
+                        if let Some(wait) = wait {
+                            sleep(Duration::from_millis(wait));
+                            handle.shutdown();
+                        }
+                    }
+                    Err(error) => {
+                        let _ = errors_tx.send(error);
+                    }
+                }
+            });
+        }
+
+        sleep(POLL_INTERVAL);
+    };
+
+    // Don't wait on a sender that's still blocked reading stdin
+    rt.shutdown_background();
+    Ok(succeeded)
+}
+
+fn listen(
+    port: u16,
+    payload: PayloadArgs,
+    once: bool,
+    format: OutputFormat,
+) -> anyhow::Result<bool> {
+    let payloads = payload.read()?;
+    let rt = Runtime::new()?;
+    let mut server = Server::new();
+    let mut events = server.subscribe();
+    server.start(port, rt.handle())?;
+    let handle = server.handle();
+
+    let (errors_tx, errors) = mpsc::channel();
+    let stdin_payloads = payloads.clone();
+    let stdin_handle = handle.clone();
+    let stdin_errors_tx = errors_tx.clone();
+    rt.spawn_blocking(move || {
+        if let Err(error) = stdin_payloads.stream_stdin(|data| stdin_handle.broadcast(data)) {
+            let _ = stdin_errors_tx.send(error);
+        }
+    });
+
+    let mut failed = false;
+    let succeeded = 'outer: loop {
+        let _ = server.poll_logs();
+        server.poll_connection_logs();
+
+        while let Ok(log) = events.try_recv() {
+            print_log(&log, format);
+            match &log.data {
+                LogData::ServerStartError(_) => break 'outer false,
+                LogData::ServerStopped => break 'outer !failed,
+                LogData::FatalReadError(_) | LogData::FatalWriteError(_) => failed = true,
+                LogData::ClientConnect(address) => {
+                    if let Some(conn) = handle.connection(address) {
+                        let payloads = payloads.clone();
+                        let errors_tx = errors_tx.clone();
+                        rt.spawn_blocking(move || {
+                            if let Err(error) = payloads.send_packets(&conn) {
+                                let _ = errors_tx.send(error);
+                            }
+                        });
+                    }
+                }
+                LogData::ClientDisconnect(_) if once => server.shutdown(),
+                _ => {}
+            }
+        }
+        if let Ok(error) = errors.try_recv() {
+            print_error(error);
+            break false;
+        }
+
+        sleep(POLL_INTERVAL);
+    };
+
+    rt.shutdown_background();
+    Ok(succeeded)
+}
+
+fn run_scenario(
+    path: PathBuf,
+    connect: Option<String>,
+    listen: Option<u16>,
+    junit: Option<PathBuf>,
+    format: OutputFormat,
+) -> anyhow::Result<bool> {
+    let mut scenario = Scenario::load(&path)?;
+    if let Some(address) = connect {
+        scenario.target = Some(Target::Connect(address));
+    } else if let Some(port) = listen {
+        scenario.target = Some(Target::Listen(port));
+    }
+
+    let rt = Runtime::new()?;
+    let mut events;
+    let (mut client, mut server) = (None, None);
+    let run = match scenario.target.clone() {
+        Some(Target::Connect(address)) => {
+            let mut conn = Connection::new();
+            let scenario_events = conn.subscribe();
+            events = conn.subscribe();
+            conn.start_client(address, rt.handle())?;
+            let run = scenario::run_client(scenario, conn.handle(), scenario_events, rt.handle());
+            client = Some(conn);
+            run
+        }
+        Some(Target::Listen(port)) => {
+            let mut srv = Server::new();
+            let scenario_events = srv.subscribe();
+            events = srv.subscribe();
+            srv.start(port, rt.handle())?;
+            let run = scenario::run_server(scenario, srv.handle(), scenario_events, rt.handle());
+            server = Some(srv);
+            run
+        }
+        None => anyhow::bail!("The scenario has no target, pass --connect or --listen"),
+    };
+
+    let mut printed_results = 0;
+    loop {
+        let running = run.is_running();
+        if let Some(conn) = &mut client {
+            let _ = conn.poll_logs();
+        }
+        if let Some(srv) = &mut server {
+            let _ = srv.poll_logs();
+            srv.poll_connection_logs();
+        }
+        while let Ok(log) = events.try_recv() {
+            print_log(&log, format);
+        }
+
+        let report = run.report();
+        for (i, result) in report.results.iter().enumerate().skip(printed_results) {
+            let outcome = match &result.outcome {
+                Outcome::Passed => "PASS".to_string(),
+                Outcome::Failed(message) => format!("FAIL ({message})"),
+                Outcome::Skipped => "SKIP".to_string(),
+            };
+            eprintln!(
+                "Step {}: {} ... {outcome} [{:.1}ms]",
+                i + 1,
+                result.description,
+                result.elapsed.as_secs_f64() * 1000.
+            );
+            if let Outcome::Failed(_) = result.outcome {
+                if let Some(expected) = &result.expected {
+                    eprintln!("  Expected: {}", hex_encode_formatted(expected));
+                }
+                if let Some(actual) = &result.actual {
+                    eprintln!("  Actual:   {}", hex_encode_formatted(actual));
+                }
+            }
+        }
+        printed_results = report.results.len();
+
+        if !running {
+            eprintln!("{}", report.summary());
+            if let Some(junit) = junit {
+                std::fs::write(&junit, report.to_junit_xml())
+                    .with_context(|| format!("Failed to write {}", junit.display()))?;
+            }
+            return Ok(report.passed());
+        }
+
+        sleep(POLL_INTERVAL);
+    }
+}
+
+impl PayloadArgs {
+    fn read(self) -> anyhow::Result<Payloads> {
+        let mut packets = self
+            .send
+            .iter()
+            .map(|hex| parse_hex(hex).with_context(|| format!("Invalid hex payload {hex:?}")))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        packets.extend(self.send_text.into_iter().map(String::into_bytes));
+
+        let mut stream_stdin = false;
+        match self.input {
+            Some(path) if path.as_os_str() == "-" => stream_stdin = true,
+            Some(path) => {
+                let data = std::fs::read(&path)
+                    .with_context(|| format!("Failed to read {}", path.display()))?;
+                packets.push(if self.hex_input {
+                    parse_hex(&String::from_utf8_lossy(&data))?
+                } else {
+                    data
+                });
+            }
+            None => {}
+        }
+
+        Ok(Payloads {
+            packets,
+            stream_stdin,
+            hex_input: self.hex_input,
+        })
+    }
+}
+
+#[derive(Clone)]
+struct Payloads {
+    packets: Vec<Vec<u8>>,
+    stream_stdin: bool,
+    hex_input: bool,
+}
+
+impl Payloads {
+    fn send_packets(&self, conn: &ConnectionHandle) -> anyhow::Result<()> {
+        for packet in &self.packets {
+            conn.send_data(packet.clone())?;
+        }
+        Ok(())
+    }
+
+    /// Passes stdin to `send` until it's exhausted, if it's being streamed.
+    /// Lines are sent one by one when the input is hex, raw input is sent as it's read.
+    fn stream_stdin(&self, send: impl Fn(Vec<u8>) -> anyhow::Result<()>) -> anyhow::Result<()> {
+        if !self.stream_stdin {
+            return Ok(());
+        }
+
+        let mut stdin = std::io::stdin().lock();
+        if self.hex_input {
+            let mut line = String::new();
+            while stdin.read_line(&mut line)? != 0 {
+                send(parse_hex(&line)?)?;
+                line.clear();
+            }
+        } else {
+            let mut buffer = [0u8; 2048];
+            loop {
+                match stdin.read(&mut buffer)? {
+                    0 => break,
+                    read => send(buffer[..read].to_vec())?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn print_log(log: &Log, format: OutputFormat) {
+    let timestamp = log.timestamp.format("%H:%M:%S%.3f");
+    let line = match format {
+        OutputFormat::Json => serde_json::to_string(&logfile::Record::from(log)).unwrap(),
+        _ => format!("{timestamp} {}", describe(&log.data, format)),
+    };
+
+    let mut stdout = std::io::stdout().lock();
+    let _ = writeln!(stdout, "{line}");
+    let _ = stdout.flush();
+}
+
+fn describe(data: &LogData, format: OutputFormat) -> String {
+    let render = |bytes: &[u8]| match format {
+        OutputFormat::Text => escape_text(bytes),
+        _ => hex_encode_formatted(bytes),
+    };
+
+    match data {
+        LogData::ClientConnect(address) => format!("{address} Connected"),
+        LogData::ClientDisconnect(address) => format!("{address} Disconnected"),
+        LogData::ServerStarted => "Server Started".into(),
+        LogData::ServerStopped => "Server Stopped".into(),
+        LogData::ReceivedPacket(packet) => format!("{} > {}", packet.address, render(&packet.data)),
+        LogData::SentPacket(packet) => format!("You < {}", render(&packet.data)),
+        LogData::ConnectError(error) => format!("Failed to Connect: {error}"),
+        LogData::ConnectTimedOut => "Failed to Connect: Timed Out".into(),
+        LogData::ServerStartError(error) => format!("Failed to Start Server: {error}"),
+        LogData::FatalReadError(error) => format!("Fatal Read Error: {error}"),
+        LogData::FatalWriteError(error) => format!("Fatal Write Error: {error}"),
+        LogData::Script(message) => format!("Script: {message}"),
+        LogData::ScriptError(error) => format!("Script Error: {error}"),
+    }
+}
+
+fn escape_text(bytes: &[u8]) -> String {
+    bytes.escape_ascii().to_string()
+}
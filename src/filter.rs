@@ -0,0 +1,584 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    ops::Range,
+};
+
+use chrono::{NaiveTime, TimeDelta};
+use eframe::egui::{
+    self, vec2, Align, Button, ComboBox, Id, Key, Rect, ScrollArea, Sense, TextEdit,
+    TopBottomPanel, UiBuilder,
+};
+use regex::bytes::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+
+use palm_core::{
+    backend::{Log, LogData},
+    logstore::LogStore,
+    template::Template,
+    util::parse_hex,
+};
+
+use crate::{
+    gui::{log_ui, LogLayout},
+    hexedit::{copy_as_ui, fields_text, save_dialog},
+};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SearchMode {
+    #[default]
+    Hex,
+    Text,
+    Regex,
+}
+
+impl SearchMode {
+    fn label(self) -> &'static str {
+        match self {
+            Self::Hex => "Hex",
+            Self::Text => "Text",
+            Self::Regex => "Regex",
+        }
+    }
+}
+
+/// Something done with a log from its row's context menu.
+pub enum RowAction {
+    /// Compare a packet log with another one
+    Compare(Log),
+    /// Show a packet log in the data inspector
+    Inspect(Log),
+}
+
+/// How log timestamps are shown.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TimeFormat {
+    #[default]
+    Seconds,
+    Millis,
+    Micros,
+    /// Time since the connection or server the log belongs to started
+    SinceStart,
+    /// Time since the log shown above
+    SincePrevious,
+}
+
+impl TimeFormat {
+    const ALL: [Self; 5] = [
+        Self::Seconds,
+        Self::Millis,
+        Self::Micros,
+        Self::SinceStart,
+        Self::SincePrevious,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            Self::Seconds => "Time of Day",
+            Self::Millis => "Time of Day (ms)",
+            Self::Micros => "Time of Day (µs)",
+            Self::SinceStart => "Since Connection Start",
+            Self::SincePrevious => "Since Previous Log",
+        }
+    }
+
+    /// How many characters timestamps take.
+    pub fn width(self) -> usize {
+        match self {
+            Self::Seconds => 8,
+            Self::Millis => 12,
+            Self::Micros => 15,
+            Self::SinceStart | Self::SincePrevious => 11,
+        }
+    }
+}
+
+pub fn time_format_ui(ui: &mut egui::Ui, time_format: &mut TimeFormat) {
+    for format in TimeFormat::ALL {
+        ui.selectable_value(time_format, format, format.label());
+    }
+}
+
+/// Matches of the search inside a packet, in bytes.
+#[derive(Clone, Copy, Default)]
+pub struct Highlights<'a> {
+    pub ranges: &'a [Range<usize>],
+    /// Index of the match navigated to, if it's in this packet
+    pub current: Option<usize>,
+}
+
+/// Everything that decides which logs are shown and what's highlighted in them.
+#[derive(Clone, PartialEq)]
+struct Criteria {
+    sent: bool,
+    received: bool,
+    connections: bool,
+    errors: bool,
+    script: bool,
+    peer: String,
+    from: String,
+    to: String,
+
+    query: String,
+    mode: SearchMode,
+    ignore_case: bool,
+}
+
+/// A log that passes the filter.
+struct Entry {
+    /// Index of the log in its store
+    index: usize,
+    matches: Vec<Range<usize>>,
+    /// Index of the connect or server start log in [`Cache::starts`] this log is timed from
+    start: Option<usize>,
+}
+
+/// The logs of a store that pass the filter, updated with new logs as they arrive.
+#[derive(Default)]
+struct Cache {
+    /// What the cache was built from: the store, the criteria and whether the filter applied
+    key: Option<(u64, Criteria, bool, bool)>,
+    /// Index of the next log to check
+    scanned: usize,
+    entries: VecDeque<Entry>,
+    matches: usize,
+
+    /// Copies of the connect and server start logs, kept even if the store drops them
+    starts: Vec<Log>,
+    /// The latest start of each peer address
+    connection_starts: HashMap<String, usize>,
+    latest_start: Option<usize>,
+}
+
+/// A filter bar for a log view: hides logs by kind, peer and time and searches packet data.
+/// Also shows the logs themselves, laying out only the rows that are scrolled into view.
+pub struct LogFilter {
+    pub open: bool,
+    pub time_format: TimeFormat,
+    id: String,
+
+    criteria: Criteria,
+    /// `None` if there's no query
+    search: Result<Option<Regex>, String>,
+    cache: Cache,
+
+    /// Index of the match navigated to among all matches in the visible logs
+    current: usize,
+    scroll_to_current: bool,
+}
+
+impl LogFilter {
+    pub fn new(id: impl Into<String>) -> Self {
+        Self {
+            open: false,
+            time_format: TimeFormat::default(),
+            id: id.into(),
+            criteria: Criteria {
+                sent: true,
+                received: true,
+                connections: true,
+                errors: true,
+                script: true,
+                peer: String::new(),
+                from: String::new(),
+                to: String::new(),
+                query: String::new(),
+                mode: SearchMode::default(),
+                ignore_case: false,
+            },
+            search: Ok(None),
+            cache: Cache::default(),
+            current: 0,
+            scroll_to_current: false,
+        }
+    }
+
+    pub fn opened(mut self) -> Self {
+        self.open = true;
+        self
+    }
+
+    /// Shows the filter bar if it's open and the logs that pass the filter. Returns what was picked
+    /// from a row's context menu.
+    pub fn show(
+        &mut self,
+        ui: &mut egui::Ui,
+        logs: &LogStore,
+        show_addresses: bool,
+        template: Option<&Template>,
+    ) -> Option<RowAction> {
+        self.update(logs, show_addresses);
+
+        if self.open {
+            TopBottomPanel::top(format!("log-filter:{}", self.id)).show_inside(ui, |ui| {
+                self.bar_ui(ui, show_addresses);
+            });
+        }
+        if logs.start() > 0 {
+            ui.weak(format!(
+                "{} older logs were dropped, only the last {} are kept",
+                logs.start(),
+                logs.capacity()
+            ));
+        }
+
+        let layout = LogLayout::new(ui, template, self.time_format);
+        let scroll_to_current = std::mem::take(&mut self.scroll_to_current);
+        let mut picked = None;
+        let mut time_format = self.time_format;
+        ScrollArea::vertical()
+            .id_salt(format!("log-scroll:{}", self.id))
+            .auto_shrink(false)
+            .show_viewport(ui, |ui, viewport| {
+                let spacing = ui.spacing().item_spacing.y;
+                let origin = ui.max_rect().min;
+                let width = ui.available_width();
+
+                let mut y = 0.;
+                let mut first_match = 0;
+                let mut previous = None;
+                for entry in &self.cache.entries {
+                    let log = logs.get(entry.index).unwrap();
+                    let height = layout.height(log);
+                    let rect = Rect::from_min_size(origin + vec2(0., y), vec2(width, height));
+                    let current = (first_match..first_match + entry.matches.len())
+                        .contains(&self.current)
+                        .then(|| self.current - first_match);
+
+                    if scroll_to_current && current.is_some() {
+                        ui.scroll_to_rect(rect, Some(Align::Center));
+                    }
+                    if y + height >= viewport.min.y && y <= viewport.max.y {
+                        let highlights = Highlights {
+                            ranges: &entry.matches,
+                            current,
+                        };
+                        let time = self.time_text(log, entry, previous);
+                        ui.scope_builder(UiBuilder::new().max_rect(rect), |ui| {
+                            log_ui(ui, log, &time, show_addresses, highlights, &layout);
+                        });
+                        let id = Id::new(("log-row", &self.id, entry.index));
+                        let mut response = ui.interact(rect, id, Sense::click());
+                        let fields = template.zip(packet_data(log)).and_then(|(template, data)| {
+                            fields_text(template, data, 0..usize::MAX)
+                        });
+                        if let Some(fields) = fields {
+                            response = response.on_hover_text(fields);
+                        }
+                        response.context_menu(|ui| {
+                            if packet_data(log).is_some() {
+                                if ui.button("⇄ Compare").clicked() {
+                                    picked = Some(RowAction::Compare(log.clone()));
+                                }
+                                if ui.button("🔬 Inspect").clicked() {
+                                    picked = Some(RowAction::Inspect(log.clone()));
+                                }
+                                copy_as_ui(ui, packet_data(log).unwrap());
+                                if ui.button("💾 Save Packet As…").clicked() {
+                                    save_dialog(packet_data(log).unwrap(), "packet.bin");
+                                }
+                            }
+                            ui.menu_button("🕓 Timestamps", |ui| {
+                                time_format_ui(ui, &mut time_format);
+                            });
+                        });
+                    }
+
+                    y += height + spacing;
+                    first_match += entry.matches.len();
+                    previous = Some(log);
+                }
+                ui.expand_to_include_rect(Rect::from_min_size(origin, vec2(width, y)));
+            });
+        self.time_format = time_format;
+        picked
+    }
+
+    fn time_text(&self, log: &Log, entry: &Entry, previous: Option<&Log>) -> String {
+        let width = self.time_format.width();
+        let since = |earlier: Option<&Log>| match earlier {
+            Some(earlier) => format!("{:>width$}", format_delta(log.since(earlier))),
+            None => format!("{:>width$}", "-"),
+        };
+        match self.time_format {
+            TimeFormat::Seconds => log.timestamp.format("%H:%M:%S").to_string(),
+            TimeFormat::Millis => log.timestamp.format("%H:%M:%S%.3f").to_string(),
+            TimeFormat::Micros => log.timestamp.format("%H:%M:%S%.6f").to_string(),
+            TimeFormat::SinceStart => since(entry.start.map(|i| &self.cache.starts[i])),
+            TimeFormat::SincePrevious => since(Some(previous.unwrap_or(log))),
+        }
+    }
+
+    /// Brings the cache up to date with `logs`, rebuilding it if the filter changed.
+    fn update(&mut self, logs: &LogStore, show_addresses: bool) {
+        let key = (logs.id(), self.criteria.clone(), self.open, show_addresses);
+        let cache = &mut self.cache;
+        if cache.key.as_ref() != Some(&key) {
+            if cache.key.as_ref().map(|k| &k.1) != Some(&key.1) {
+                self.search = compile(&self.criteria);
+            }
+            *cache = Cache {
+                key: Some(key),
+                scanned: logs.start(),
+                ..Default::default()
+            };
+            self.current = 0;
+        }
+
+        // Forget logs the store dropped
+        while let Some(entry) = cache.entries.front() {
+            if entry.index >= logs.start() {
+                break;
+            }
+            cache.matches -= entry.matches.len();
+            self.current = self.current.saturating_sub(entry.matches.len());
+            cache.entries.pop_front();
+        }
+
+        let search = self.search.as_ref().ok().and_then(Option::as_ref);
+        let from = parse_time(&self.criteria.from);
+        let to = parse_time(&self.criteria.to);
+        let start = cache.scanned.max(logs.start());
+        for (index, log) in (start..).zip(logs.range(start..logs.end())) {
+            let log_start = cache.track_start(log);
+            if self.open && !passes(&self.criteria, log, show_addresses, from, to) {
+                continue;
+            }
+            let matches: Vec<_> = match (search.filter(|_| self.open), packet_data(log)) {
+                (Some(regex), Some(data)) => regex
+                    .find_iter(data)
+                    .filter(|m| !m.is_empty())
+                    .map(|m| m.range())
+                    .collect(),
+                _ => Vec::new(),
+            };
+            cache.matches += matches.len();
+            cache.entries.push_back(Entry {
+                index,
+                matches,
+                start: log_start,
+            });
+        }
+        cache.scanned = logs.end();
+
+        if self.current >= cache.matches {
+            self.current = 0;
+        }
+    }
+
+    fn bar_ui(&mut self, ui: &mut egui::Ui, show_addresses: bool) {
+        let total = self.cache.matches;
+        let criteria = &mut self.criteria;
+        let mut navigate = None;
+
+        ui.horizontal(|ui| {
+            ui.label("Search");
+            let query = ui.add(
+                TextEdit::singleline(&mut criteria.query)
+                    .desired_width(220.)
+                    .hint_text(match criteria.mode {
+                        SearchMode::Hex => "DE AD BE EF",
+                        SearchMode::Text => "HTTP/1.1",
+                        SearchMode::Regex => r"\d+ OK",
+                    }),
+            );
+            if query.lost_focus() && ui.input(|i| i.key_pressed(Key::Enter)) {
+                navigate = Some(ui.input(|i| i.modifiers.shift));
+                query.request_focus();
+            }
+            ComboBox::from_id_salt(format!("log-filter-mode:{}", self.id))
+                .selected_text(criteria.mode.label())
+                .show_ui(ui, |ui| {
+                    for mode in [SearchMode::Hex, SearchMode::Text, SearchMode::Regex] {
+                        ui.selectable_value(&mut criteria.mode, mode, mode.label());
+                    }
+                });
+            if criteria.mode != SearchMode::Hex {
+                ui.checkbox(&mut criteria.ignore_case, "Ignore Case");
+            }
+
+            if ui.add_enabled(total > 0, Button::new("⏶")).clicked() {
+                navigate = Some(true);
+            }
+            if ui.add_enabled(total > 0, Button::new("⏷")).clicked() {
+                navigate = Some(false);
+            }
+            match &self.search {
+                Err(error) => {
+                    ui.colored_label(ui.visuals().error_fg_color, error);
+                }
+                Ok(Some(_)) if total == 0 => {
+                    ui.weak("No matches");
+                }
+                Ok(Some(_)) => {
+                    ui.label(format!("{}/{total}", self.current + 1));
+                }
+                Ok(None) => {}
+            }
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("Show");
+            ui.toggle_value(&mut criteria.sent, "Sent");
+            ui.toggle_value(&mut criteria.received, "Received");
+            ui.toggle_value(&mut criteria.connections, "Connections");
+            ui.toggle_value(&mut criteria.errors, "Errors");
+            ui.toggle_value(&mut criteria.script, "Script");
+
+            if show_addresses {
+                ui.separator();
+                ui.label("Peer");
+                ui.add(
+                    TextEdit::singleline(&mut criteria.peer)
+                        .desired_width(140.)
+                        .hint_text("127.0.0.1:54321"),
+                );
+            }
+
+            ui.separator();
+            ui.label("From");
+            time_edit(ui, &mut criteria.from);
+            ui.label("To");
+            time_edit(ui, &mut criteria.to);
+        });
+
+        if let Some(backwards) = navigate {
+            self.navigate(backwards);
+        }
+    }
+
+    fn navigate(&mut self, backwards: bool) {
+        let total = self.cache.matches;
+        if total == 0 {
+            return;
+        }
+        self.current = if backwards {
+            (self.current + total - 1) % total
+        } else {
+            (self.current + 1) % total
+        };
+        self.scroll_to_current = true;
+    }
+}
+
+fn compile(criteria: &Criteria) -> Result<Option<Regex>, String> {
+    if criteria.query.trim().is_empty() {
+        return Ok(None);
+    }
+
+    let pattern = match criteria.mode {
+        SearchMode::Hex => {
+            let bytes = parse_hex(&criteria.query).map_err(|_| "Invalid hex".to_string())?;
+            let escaped: String = bytes.iter().map(|b| format!(r"\x{b:02X}")).collect();
+            format!("(?-u){escaped}")
+        }
+        SearchMode::Text => regex::escape(&criteria.query),
+        SearchMode::Regex => criteria.query.clone(),
+    };
+    RegexBuilder::new(&pattern)
+        .case_insensitive(criteria.ignore_case && criteria.mode != SearchMode::Hex)
+        .build()
+        .map(Some)
+        .map_err(|e| e.to_string())
+}
+
+fn passes(
+    criteria: &Criteria,
+    log: &Log,
+    show_addresses: bool,
+    from: Option<NaiveTime>,
+    to: Option<NaiveTime>,
+) -> bool {
+    let (kind, peer) = match &log.data {
+        LogData::SentPacket(packet) => (criteria.sent, Some(&packet.address)),
+        LogData::ReceivedPacket(packet) => (criteria.received, Some(&packet.address)),
+        LogData::ClientConnect(address) | LogData::ClientDisconnect(address) => {
+            (criteria.connections, Some(address))
+        }
+        LogData::ServerStarted | LogData::ServerStopped => (criteria.connections, None),
+        LogData::ConnectError(_)
+        | LogData::ConnectTimedOut
+        | LogData::ServerStartError(_)
+        | LogData::FatalReadError(_)
+        | LogData::FatalWriteError(_)
+        | LogData::ScriptError(_) => (criteria.errors, None),
+        LogData::Script(_) => (criteria.script, None),
+    };
+    if !kind {
+        return false;
+    }
+
+    let peer_filter = criteria.peer.trim();
+    if show_addresses
+        && !peer_filter.is_empty()
+        && !peer.is_some_and(|peer| peer.contains(peer_filter))
+    {
+        return false;
+    }
+
+    let time = log.timestamp.time();
+    !(from.is_some_and(|from| time < from) || to.is_some_and(|to| time > to))
+}
+
+fn packet_data(log: &Log) -> Option<&[u8]> {
+    match &log.data {
+        LogData::SentPacket(packet) | LogData::ReceivedPacket(packet) => Some(&packet.data),
+        _ => None,
+    }
+}
+
+impl Cache {
+    /// Remembers `log` if it starts a connection or server and returns the start it's timed from.
+    fn track_start(&mut self, log: &Log) -> Option<usize> {
+        match &log.data {
+            LogData::ServerStarted => {
+                self.starts.push(log.clone());
+                self.latest_start = Some(self.starts.len() - 1);
+            }
+            LogData::ClientConnect(address) => {
+                self.starts.push(log.clone());
+                self.latest_start = Some(self.starts.len() - 1);
+                self.connection_starts
+                    .insert(address.clone(), self.starts.len() - 1);
+            }
+            _ => {}
+        }
+        let address = match &log.data {
+            LogData::ClientConnect(address) | LogData::ClientDisconnect(address) => Some(address),
+            LogData::SentPacket(packet) | LogData::ReceivedPacket(packet) => Some(&packet.address),
+            _ => None,
+        };
+        address
+            .and_then(|address| self.connection_starts.get(address).copied())
+            .or(self.latest_start)
+    }
+}
+
+/// Formats a duration as signed seconds with microseconds, e.g. `+1.250000`.
+fn format_delta(delta: TimeDelta) -> String {
+    let sign = if delta < TimeDelta::zero() { '-' } else { '+' };
+    let delta = delta.abs();
+    format!(
+        "{sign}{}.{:06}",
+        delta.num_seconds(),
+        delta.subsec_nanos() / 1000
+    )
+}
+
+/// Parses times like `13:05`, `13:05:42` or `13:05:42.250`.
+fn parse_time(text: &str) -> Option<NaiveTime> {
+    let text = text.trim();
+    NaiveTime::parse_from_str(text, "%H:%M:%S%.f")
+        .or_else(|_| NaiveTime::parse_from_str(text, "%H:%M"))
+        .ok()
+}
+
+fn time_edit(ui: &mut egui::Ui, text: &mut String) {
+    let valid = text.trim().is_empty() || parse_time(text).is_some();
+    let mut edit = TextEdit::singleline(text)
+        .desired_width(84.)
+        .hint_text("HH:MM:SS");
+    if !valid {
+        edit = edit.text_color(ui.visuals().error_fg_color);
+    }
+    ui.add(edit);
+}
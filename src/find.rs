@@ -0,0 +1,594 @@
+//! Finding and replacing bytes in a draft by hex pattern, text or integer value.
+
+use std::ops::Range;
+
+use eframe::egui::{self, Button, ComboBox, Id, Key, TextEdit};
+use regex::bytes::{Regex, RegexBuilder};
+
+use palm_core::inspect::{self, Endian, Kind};
+
+use crate::hexedit::{self, History};
+
+/// Most matches found, so that common patterns in large drafts don't take up too much memory.
+/// Replacing all of them replaces this many at a time.
+const MAX_MATCHES: usize = 100_000;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FindMode {
+    #[default]
+    Hex,
+    Text,
+    Integer,
+}
+
+impl FindMode {
+    fn label(self) -> &'static str {
+        match self {
+            Self::Hex => "Hex",
+            Self::Text => "Text",
+            Self::Integer => "Integer",
+        }
+    }
+}
+
+/// What a search is compiled from.
+#[derive(Clone, PartialEq, Eq)]
+struct Query {
+    text: String,
+    mode: FindMode,
+    ignore_case: bool,
+    /// Bytes of integers
+    width: usize,
+    endian: Endian,
+}
+
+/// A byte of a hex pattern and which of its bits are fixed, `?` nibbles aren't.
+#[derive(Clone, Copy)]
+struct PatternByte {
+    value: u8,
+    mask: u8,
+}
+
+pub struct FindBar {
+    pub open: bool,
+    /// Whether replacing is shown too
+    pub replacing: bool,
+    id: String,
+    query: Query,
+    replacement: String,
+    replace_error: Option<String>,
+    search: Result<Option<Regex>, String>,
+    compiled: Option<Query>,
+    /// Revision of the buffer the matches were found in
+    searched: Option<u64>,
+    matches: Vec<Range<usize>>,
+    current: usize,
+    focus_query: bool,
+}
+
+impl FindBar {
+    pub fn new(id: impl Into<String>) -> Self {
+        Self {
+            open: false,
+            replacing: false,
+            id: id.into(),
+            query: Query {
+                text: String::new(),
+                mode: FindMode::default(),
+                ignore_case: false,
+                width: 4,
+                endian: Endian::default(),
+            },
+            replacement: String::new(),
+            replace_error: None,
+            search: Ok(None),
+            compiled: None,
+            searched: None,
+            matches: Vec::new(),
+            current: 0,
+            focus_query: false,
+        }
+    }
+
+    /// Opens the bar with the query focused, and with replacing shown if `replacing`.
+    pub fn open(&mut self, replacing: bool) {
+        self.open = true;
+        self.replacing = replacing;
+        self.focus_query = true;
+    }
+
+    /// Where the query matched, as of the last time the bar was shown.
+    pub fn matches(&self) -> &[Range<usize>] {
+        if self.open {
+            &self.matches
+        } else {
+            &[]
+        }
+    }
+
+    /// Shows the bar and finds the query in `buffer`. Found matches are selected in the editor
+    /// with `editor` id, and replacing them rewrites `buffer`.
+    pub fn ui(
+        &mut self,
+        ui: &mut egui::Ui,
+        editor: Id,
+        buffer: &mut Vec<u8>,
+        history: &mut History,
+    ) {
+        let changed = self.compiled.as_ref() != Some(&self.query);
+        if changed {
+            self.search = compile(&self.query);
+            self.compiled = Some(self.query.clone());
+        }
+        if changed || self.searched != Some(history.revision()) {
+            self.find(buffer, history);
+        }
+        if changed && !self.matches.is_empty() {
+            // Start from the cursor rather than the top
+            let cursor = hexedit::selection(ui.ctx(), editor).start;
+            self.current = self.next_match(cursor);
+            self.select(ui.ctx(), editor);
+        }
+
+        let total = self.matches.len();
+        let mut navigate = None;
+        ui.horizontal(|ui| {
+            ui.label("Find");
+            let query = ui.add(
+                TextEdit::singleline(&mut self.query.text)
+                    .desired_width(220.)
+                    .hint_text(match self.query.mode {
+                        FindMode::Hex => "DE AD ?? EF",
+                        FindMode::Text => "HTTP/1.1",
+                        FindMode::Integer => "1234",
+                    }),
+            );
+            if std::mem::take(&mut self.focus_query) {
+                query.request_focus();
+            }
+            if query.lost_focus() {
+                if ui.input(|i| i.key_pressed(Key::Enter)) {
+                    navigate = Some(ui.input(|i| i.modifiers.shift));
+                    query.request_focus();
+                } else if ui.input(|i| i.key_pressed(Key::Escape)) {
+                    self.open = false;
+                }
+            }
+            ComboBox::from_id_salt(format!("find-mode:{}", self.id))
+                .selected_text(self.query.mode.label())
+                .show_ui(ui, |ui| {
+                    for mode in [FindMode::Hex, FindMode::Text, FindMode::Integer] {
+                        ui.selectable_value(&mut self.query.mode, mode, mode.label());
+                    }
+                });
+            match self.query.mode {
+                FindMode::Hex => {}
+                FindMode::Text => {
+                    ui.checkbox(&mut self.query.ignore_case, "Ignore Case");
+                }
+                FindMode::Integer => {
+                    ComboBox::from_id_salt(format!("find-width:{}", self.id))
+                        .selected_text(format!("{}-bit", self.query.width * 8))
+                        .show_ui(ui, |ui| {
+                            for width in [1, 2, 4, 8] {
+                                ui.selectable_value(
+                                    &mut self.query.width,
+                                    width,
+                                    format!("{}-bit", width * 8),
+                                );
+                            }
+                        });
+                    ui.selectable_value(&mut self.query.endian, Endian::Little, "LE")
+                        .on_hover_text("Little Endian");
+                    ui.selectable_value(&mut self.query.endian, Endian::Big, "BE")
+                        .on_hover_text("Big Endian");
+                }
+            }
+
+            if ui.add_enabled(total > 0, Button::new("⏶")).clicked() {
+                navigate = Some(true);
+            }
+            if ui.add_enabled(total > 0, Button::new("⏷")).clicked() {
+                navigate = Some(false);
+            }
+            match &self.search {
+                Err(error) => {
+                    ui.colored_label(ui.visuals().error_fg_color, error);
+                }
+                Ok(Some(_)) if total == 0 => {
+                    ui.weak("No matches");
+                }
+                Ok(Some(_)) if total == MAX_MATCHES => {
+                    ui.label(format!("{}/{total}+", self.current + 1));
+                }
+                Ok(Some(_)) => {
+                    ui.label(format!("{}/{total}", self.current + 1));
+                }
+                Ok(None) => {}
+            }
+
+            ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                if ui.small_button("✕").on_hover_text("Close").clicked() {
+                    self.open = false;
+                }
+                ui.toggle_value(&mut self.replacing, "Replace");
+            });
+        });
+
+        if let Some(backwards) = navigate {
+            self.navigate(backwards);
+            self.select(ui.ctx(), editor);
+        }
+
+        if !self.replacing {
+            return;
+        }
+        let mut replace_all = false;
+        let mut replace = false;
+        ui.horizontal(|ui| {
+            ui.label("With");
+            let replacement = ui.add(
+                TextEdit::singleline(&mut self.replacement)
+                    .desired_width(220.)
+                    .hint_text(match self.query.mode {
+                        FindMode::Hex => "DE AD ?? EF",
+                        FindMode::Text => "HTTP/2",
+                        FindMode::Integer => "5678",
+                    }),
+            );
+            if replacement.lost_focus() && ui.input(|i| i.key_pressed(Key::Enter)) {
+                replace = true;
+                replacement.request_focus();
+            }
+            replace |= ui.add_enabled(total > 0, Button::new("Replace")).clicked();
+            replace_all = ui
+                .add_enabled(total > 0, Button::new("Replace All"))
+                .clicked();
+            if let Some(error) = &self.replace_error {
+                ui.colored_label(ui.visuals().error_fg_color, error);
+            }
+        });
+        if !replace && !replace_all {
+            return;
+        }
+
+        let pattern = match parse_replacement(&self.query, &self.replacement) {
+            Ok(pattern) => pattern,
+            Err(error) => {
+                self.replace_error = Some(error);
+                return;
+            }
+        };
+        self.replace_error = None;
+        if replace_all {
+            self.replace_all(ui.ctx(), editor, buffer, history, &pattern);
+        } else if let Some(range) = self.matches.get(self.current).cloned() {
+            let data = replaced(&pattern, &buffer[range.clone()]);
+            let end = range.start + data.len();
+            history.splice(buffer, range.clone(), data, false);
+            hexedit::select(ui.ctx(), editor, range.start..end);
+            self.find(buffer, history);
+            self.current = self.next_match(end);
+        }
+    }
+
+    fn replace_all(
+        &mut self,
+        ctx: &egui::Context,
+        editor: Id,
+        buffer: &mut Vec<u8>,
+        history: &mut History,
+        pattern: &[PatternByte],
+    ) {
+        let (Some(first), Some(last)) = (self.matches.first(), self.matches.last()) else {
+            return;
+        };
+        let span = first.start..last.end;
+
+        // Replace everything in one edit so that it's undone at once
+        let mut data = Vec::with_capacity(span.len());
+        let mut end = span.start;
+        for range in &self.matches {
+            data.extend_from_slice(&buffer[end..range.start]);
+            data.extend(replaced(pattern, &buffer[range.clone()]));
+            end = range.end;
+        }
+        let selected = span.start..span.start + data.len();
+        history.splice(buffer, span, data, false);
+        hexedit::select(ctx, editor, selected);
+        self.find(buffer, history);
+        self.current = 0;
+    }
+
+    fn find(&mut self, buffer: &[u8], history: &History) {
+        self.matches = match &self.search {
+            Ok(Some(regex)) => regex
+                .find_iter(buffer)
+                .filter(|m| !m.is_empty())
+                .map(|m| m.range())
+                .take(MAX_MATCHES)
+                .collect(),
+            _ => Vec::new(),
+        };
+        self.searched = Some(history.revision());
+        if self.current >= self.matches.len() {
+            self.current = 0;
+        }
+    }
+
+    /// Index of the first match at or after `position`, wrapping around to the first one.
+    fn next_match(&self, position: usize) -> usize {
+        let next = self.matches.partition_point(|m| m.start < position);
+        if next == self.matches.len() {
+            0
+        } else {
+            next
+        }
+    }
+
+    fn navigate(&mut self, backwards: bool) {
+        let total = self.matches.len();
+        if total == 0 {
+            return;
+        }
+        self.current = if backwards {
+            (self.current + total - 1) % total
+        } else {
+            (self.current + 1) % total
+        };
+    }
+
+    fn select(&self, ctx: &egui::Context, editor: Id) {
+        if let Some(range) = self.matches.get(self.current) {
+            hexedit::select(ctx, editor, range.clone());
+        }
+    }
+}
+
+fn compile(query: &Query) -> Result<Option<Regex>, String> {
+    if query.text.trim().is_empty() {
+        return Ok(None);
+    }
+
+    let pattern = match query.mode {
+        FindMode::Hex => {
+            let escaped: String = parse_pattern(&query.text)?
+                .iter()
+                .map(|byte| match byte.mask {
+                    0xff => format!(r"\x{:02X}", byte.value),
+                    0xf0 => format!(r"[\x{:02X}-\x{:02X}]", byte.value, byte.value | 0x0f),
+                    0x0f => {
+                        let bytes: String = (0..16)
+                            .map(|high| format!(r"\x{:02X}", high << 4 | byte.value))
+                            .collect();
+                        format!("[{bytes}]")
+                    }
+                    _ => ".".to_string(),
+                })
+                .collect();
+            format!("(?s-u){escaped}")
+        }
+        FindMode::Text => regex::escape(&query.text),
+        FindMode::Integer => {
+            let escaped: String = integer_bytes(query, &query.text)?
+                .iter()
+                .map(|b| format!(r"\x{b:02X}"))
+                .collect();
+            format!("(?-u){escaped}")
+        }
+    };
+    RegexBuilder::new(&pattern)
+        .case_insensitive(query.ignore_case && query.mode == FindMode::Text)
+        .build()
+        .map(Some)
+        .map_err(|e| e.to_string())
+}
+
+/// Parses hex where `?` stands for any nibble.
+fn parse_pattern(text: &str) -> Result<Vec<PatternByte>, String> {
+    let nibbles: Vec<char> = text.chars().filter(|c| !c.is_whitespace()).collect();
+    if !nibbles.len().is_multiple_of(2) {
+        return Err("Hex needs two digits per byte".to_string());
+    }
+    let nibble = |c: char| match c {
+        '?' => Ok((0, 0)),
+        c => c
+            .to_digit(16)
+            .map(|digit| (digit as u8, 0xf))
+            .ok_or_else(|| format!("Invalid hex digit {c:?}")),
+    };
+    nibbles
+        .chunks(2)
+        .map(|pair| {
+            let (high, high_mask) = nibble(pair[0])?;
+            let (low, low_mask) = nibble(pair[1])?;
+            Ok(PatternByte {
+                value: high << 4 | low,
+                mask: high_mask << 4 | low_mask,
+            })
+        })
+        .collect()
+}
+
+fn integer_bytes(query: &Query, text: &str) -> Result<Vec<u8>, String> {
+    let signed = text.trim_start().starts_with('-');
+    let kind = match (query.width, signed) {
+        (1, false) => Kind::U8,
+        (1, true) => Kind::I8,
+        (2, false) => Kind::U16,
+        (2, true) => Kind::I16,
+        (4, false) => Kind::U32,
+        (4, true) => Kind::I32,
+        (8, false) => Kind::U64,
+        _ => Kind::I64,
+    };
+    inspect::write(kind, text, query.endian).map_err(|e| format!("{e:#}"))
+}
+
+/// What matches are replaced with. `?` nibbles of hex replacements keep the matched nibble.
+fn parse_replacement(query: &Query, text: &str) -> Result<Vec<PatternByte>, String> {
+    let literal = |bytes: Vec<u8>| {
+        bytes
+            .into_iter()
+            .map(|value| PatternByte { value, mask: 0xff })
+            .collect()
+    };
+    match query.mode {
+        FindMode::Hex => parse_pattern(text),
+        FindMode::Text => Ok(literal(text.as_bytes().to_vec())),
+        FindMode::Integer => integer_bytes(query, text).map(literal),
+    }
+}
+
+fn replaced(pattern: &[PatternByte], matched: &[u8]) -> Vec<u8> {
+    pattern
+        .iter()
+        .enumerate()
+        .map(|(i, byte)| byte.value | matched.get(i).copied().unwrap_or(0) & !byte.mask)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(text: &str, mode: FindMode) -> Query {
+        Query {
+            text: text.into(),
+            mode,
+            ignore_case: false,
+            width: 4,
+            endian: Endian::Little,
+        }
+    }
+
+    fn matches(query: &Query, buffer: &[u8]) -> Vec<(usize, usize)> {
+        let regex = compile(query).unwrap().unwrap();
+        regex
+            .find_iter(buffer)
+            .map(|m| (m.start(), m.end()))
+            .collect()
+    }
+
+    #[test]
+    fn hex_patterns_have_wildcard_nibbles() {
+        let pattern = parse_pattern("4? ?1 ??").unwrap();
+        let masks: Vec<(u8, u8)> = pattern.iter().map(|b| (b.value, b.mask)).collect();
+        assert_eq!(masks, [(0x40, 0xf0), (0x01, 0x0f), (0x00, 0x00)]);
+
+        let wildcards = query("4??1", FindMode::Hex);
+        assert_eq!(
+            matches(
+                &wildcards,
+                &[0x4F, 0xA1, 0x5F, 0xA1, 0x40, 0x11, 0x40, 0x12]
+            ),
+            [(0, 2), (4, 6)]
+        );
+        // Wildcards match any byte, including newlines and bytes that aren't UTF-8
+        assert_eq!(
+            matches(&query("0A ??", FindMode::Hex), &[0x0A, 0x0A, 0xFF]),
+            [(0, 2)]
+        );
+    }
+
+    #[test]
+    fn hex_needs_whole_bytes_of_hex_digits() {
+        assert_eq!(
+            parse_pattern("01 2").err().as_deref(),
+            Some("Hex needs two digits per byte")
+        );
+        assert_eq!(
+            parse_pattern("0G").err().as_deref(),
+            Some("Invalid hex digit 'G'")
+        );
+        assert!(compile(&query("ABC", FindMode::Hex)).is_err());
+        assert!(compile(&query("  ", FindMode::Hex)).unwrap().is_none());
+    }
+
+    #[test]
+    fn wildcard_replacements_keep_the_matched_nibbles() {
+        let pattern = parse_replacement(&query("", FindMode::Hex), "?F A? ??").unwrap();
+        assert_eq!(replaced(&pattern, &[0x12, 0x34, 0x56]), [0x1F, 0xA4, 0x56]);
+        // Replacements longer than the match have nothing to keep
+        assert_eq!(replaced(&pattern, &[0x12]), [0x1F, 0xA0, 0x00]);
+
+        let literal = parse_replacement(&query("", FindMode::Text), "?").unwrap();
+        assert_eq!(replaced(&literal, &[0x12]), b"?");
+    }
+
+    #[test]
+    fn integers_are_found_by_their_bytes() {
+        let cases: [(usize, Endian, &str, &[u8]); 10] = [
+            (1, Endian::Little, "200", &[200]),
+            (1, Endian::Big, "-1", &[0xFF]),
+            (2, Endian::Little, "0x1234", &[0x34, 0x12]),
+            (2, Endian::Big, "0x1234", &[0x12, 0x34]),
+            (2, Endian::Big, "-2", &[0xFF, 0xFE]),
+            (4, Endian::Little, "1", &[1, 0, 0, 0]),
+            (4, Endian::Big, "-256", &[0xFF, 0xFF, 0xFF, 0x00]),
+            (8, Endian::Little, "1", &[1, 0, 0, 0, 0, 0, 0, 0]),
+            (8, Endian::Big, "-1", &[0xFF; 8]),
+            (8, Endian::Big, "18446744073709551615", &[0xFF; 8]),
+        ];
+        for (width, endian, text, bytes) in cases {
+            let query = Query {
+                width,
+                endian,
+                ..query(text, FindMode::Integer)
+            };
+            assert_eq!(
+                integer_bytes(&query, text).unwrap(),
+                bytes,
+                "{width} {text}"
+            );
+            assert_eq!(
+                matches(&query, &[&[0x7F], bytes].concat()),
+                [(1, 1 + width)]
+            );
+        }
+
+        let query = |text: &str, width| Query {
+            width,
+            ..query(text, FindMode::Integer)
+        };
+        assert!(integer_bytes(&query("256", 1), "256").is_err());
+        assert!(integer_bytes(&query("-129", 1), "-129").is_err());
+        assert!(compile(&query("ten", 4)).is_err());
+    }
+
+    #[test]
+    fn text_is_found_literally() {
+        let buffer = b"a.b A.B axb";
+        assert_eq!(matches(&query("a.b", FindMode::Text), buffer), [(0, 3)]);
+        let ignore_case = Query {
+            ignore_case: true,
+            ..query("a.b", FindMode::Text)
+        };
+        assert_eq!(matches(&ignore_case, buffer), [(0, 3), (4, 7)]);
+    }
+
+    #[test]
+    fn replacing_all_is_undone_at_once() {
+        let ctx = egui::Context::default();
+        let editor = Id::new("editor");
+        let mut buffer = b"a-b-c-d".to_vec();
+        let mut history = History::default();
+
+        let mut bar = FindBar::new("find");
+        bar.query = query("-", FindMode::Text);
+        bar.search = compile(&bar.query);
+        bar.find(&buffer, &history);
+        assert_eq!(bar.matches.len(), 3);
+
+        let pattern = parse_replacement(&bar.query, "+=").unwrap();
+        bar.replace_all(&ctx, editor, &mut buffer, &mut history, &pattern);
+        assert_eq!(buffer, b"a+=b+=c+=d");
+        assert!(bar.matches.is_empty());
+        // Everything replaced is selected
+        assert_eq!(hexedit::selection(&ctx, editor), 1..9);
+
+        assert!(history.undo(&mut buffer).is_some());
+        assert_eq!(buffer, b"a-b-c-d");
+        assert_eq!(history.undo(&mut buffer), None);
+    }
+}
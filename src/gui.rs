@@ -1,16 +1,47 @@
-use std::{ops::Not, sync::Arc};
+use std::{
+    fs::File,
+    io::BufWriter,
+    ops::Not,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::bail;
+use chrono::Local;
 
 use eframe::egui::{
-    self, Align, Button, CentralPanel, Label, Layout, ScrollArea, Stroke, TextEdit,
-    TopBottomPanel,
+    self, text::LayoutJob, Align, Align2, Button, CentralPanel, Color32, ComboBox, DragValue, Grid,
+    Label, Layout, ScrollArea, SidePanel, Stroke, TextEdit, TextFormat, TopBottomPanel,
 };
 use egui_tiles::{Behavior, Tile, TileId};
-use tokio::runtime::Runtime;
+use palm_core::{
+    backend::{Connection, Endpoints, Fragmentation, Log, LogData, NetState, SendOptions, Server},
+    capture::{Flow, Protocol, ReplayOptions, Replies, Side},
+    diff::{self, Change},
+    logfile,
+    logstore::{self, LogStore},
+    pcapng,
+    scenario::{self, Outcome, Scenario, ScenarioRun, StepResult, Target},
+    session::{self, RecordedConnection, Session},
+    stats::{self, Stats},
+    template::Template,
+    util::HexFormat,
+};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    runtime::Runtime,
+    sync::broadcast::{self, error::RecvError},
+    task::JoinHandle,
+};
 
 use crate::{
-    backend::{Connection, Log, LogData, Mode, NetState, Server},
-    hexedit::HexEditor,
-    util::hex_encode_formatted,
+    filter::{time_format_ui, Highlights, LogFilter, RowAction, TimeFormat},
+    find::FindBar,
+    hexedit::{append_byte, HexEditor, HexLayout, History},
+    inspector::Inspector,
+    script::{self, ScriptTask},
+    workspace::TabState,
 };
 
 pub struct ClientUI {
@@ -21,6 +52,12 @@ pub struct ClientUI {
     backend: Connection,
 }
 
+impl Default for ClientUI {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl ClientUI {
     pub fn new() -> Self {
         Self {
@@ -30,15 +67,16 @@ impl ClientUI {
         }
     }
 
-    pub fn start(&mut self, rt: &Runtime) {
+    pub fn start(&mut self, rt: &Runtime) -> anyhow::Result<()> {
         let address = self.address.clone();
-        self.backend.start_client(address, rt)
+        self.backend.start_client(address, rt.handle())
     }
 
     pub fn send_data(&mut self) -> anyhow::Result<()> {
-        let data: Vec<u8> = self.connection_ui.draft_data.drain(..).collect();
+        let data = self.connection_ui.take_draft();
 
-        self.backend.send_data(data)
+        self.backend
+            .send_data_with(data, &self.connection_ui.send_options.options())
     }
 
     pub fn backend(&self) -> &Connection {
@@ -49,6 +87,8 @@ impl ClientUI {
 pub struct ConnectionUI {
     address: String,
     pub draft_data: Vec<u8>,
+    pub draft_history: History,
+    pub send_options: SendOptionsUI,
 }
 
 impl ConnectionUI {
@@ -56,16 +96,23 @@ impl ConnectionUI {
         Self {
             address,
             draft_data: Vec::new(),
+            draft_history: History::default(),
+            send_options: SendOptionsUI::default(),
         }
     }
 
-    pub fn send_data(&mut self, parent: &mut ServerUI) -> anyhow::Result<()> {
-        let data = self.draft_data.drain(..).collect();
-        self.with_backend_mut(parent, |b| b.send_data(data))
+    /// Empties the draft for sending it, which can be undone to get it back.
+    pub fn take_draft(&mut self) -> Vec<u8> {
+        let data = self.draft_data.clone();
+        self.draft_history
+            .splice(&mut self.draft_data, 0..data.len(), Vec::new(), false);
+        data
     }
 
-    pub fn update_and_read_logs(&self, parent: &ServerUI) -> Vec<Log> {
-        self.with_backend_mut(parent, |b| b.update_and_read_logs())
+    pub fn send_data(&mut self, parent: &mut ServerUI) -> anyhow::Result<()> {
+        let data = self.take_draft();
+        let options = self.send_options.options();
+        self.with_backend_mut(parent, |b| b.send_data_with(data, &options))
     }
 
     pub fn net_state(&self, parent: &ServerUI) -> NetState {
@@ -102,6 +149,12 @@ pub struct ServerUI {
     focused_connection: Option<String>,
 }
 
+impl Default for ServerUI {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl ServerUI {
     pub fn new() -> Self {
         Self {
@@ -112,14 +165,12 @@ impl ServerUI {
         }
     }
 
-    pub fn start(&mut self, rt: &Runtime) {
-        self.backend.start(self.port.parse::<u16>().unwrap(), rt)
+    pub fn start(&mut self, rt: &Runtime) -> anyhow::Result<()> {
+        self.backend.start(self.port.parse::<u16>()?, rt.handle())
     }
 
-    pub fn update_read_and_process_logs(&mut self) -> Vec<Log> {
-        let (server_logs, prior_len) = self.backend.update_and_read_logs();
-
-        for new_log in &server_logs[prior_len..] {
+    pub fn update_and_process_logs(&mut self) {
+        for new_log in self.backend.poll_logs() {
             match &new_log.data {
                 LogData::ClientConnect(address)
                     if !self.connection_uis.iter().any(|c| c.address == *address) =>
@@ -130,22 +181,29 @@ impl ServerUI {
             }
         }
 
-        if let Some(conn_addr) = &mut self.focused_connection {
-            self.backend.update_and_read_logs_for(&conn_addr)
+        // Connections that aren't focused are still polled so that their logs don't pile up in the channel
+        self.backend.poll_connection_logs();
+    }
+
+    /// Calls `f` with the logs of the focused connection or the server.
+    pub fn with_focused_logs<T>(&self, f: impl FnOnce(&LogStore) -> T) -> T {
+        if let Some(conn_addr) = &self.focused_connection {
+            self.backend
+                .with_connection(conn_addr, |conn| f(conn.unwrap().logs()))
         } else {
-            server_logs
+            f(self.backend.logs())
         }
     }
 
     pub fn send_focused_connection_data(&mut self) -> anyhow::Result<()> {
         // Could probably be made more concise
-        if let Some(data) = self
+        if let Some((data, options)) = self
             .focused_connection_ui_mut()
-            .and_then(|c| Some(c.draft_data.drain(..).collect::<Vec<u8>>()))
+            .map(|c| (c.take_draft(), c.send_options.options()))
         {
             self.with_focused_connection_mut(|conn| {
                 if let Some(conn) = conn {
-                    conn.send_data(data)
+                    conn.send_data_with(data, &options)
                 } else {
                     Ok(())
                 }
@@ -169,12 +227,12 @@ impl ServerUI {
 
             self.connection_uis.remove(index);
 
-            if index >= self.connection_uis().len() && self.connection_uis().len() != 0 {
+            if index >= self.connection_uis().len() && !self.connection_uis().is_empty() {
                 index = self.connection_uis().len() - 1;
             }
 
             if self.focused_connection.as_deref() == Some(address) {
-                if self.connection_uis.len() == 0 {
+                if self.connection_uis.is_empty() {
                     self.set_focused_connection(None);
                 } else {
                     self.set_focused_connection(Some(self.connection_uis()[index].address.clone()));
@@ -209,21 +267,17 @@ impl ServerUI {
     }
 
     pub fn focused_connection_ui(&self) -> Option<&ConnectionUI> {
-        self.focused_connection.as_ref().and_then(|c| {
-            Some(
-                self.connection_ui_from_addr(c)
-                    .expect("Focused Connection UI is Invalid/Destroyed"),
-            )
+        self.focused_connection.as_ref().map(|c| {
+            self.connection_ui_from_addr(c)
+                .expect("Focused Connection UI is Invalid/Destroyed")
         })
     }
 
     pub fn focused_connection_ui_mut(&mut self) -> Option<&mut ConnectionUI> {
         // Unnecessary clone maybe? Probably not important
-        self.focused_connection.clone().and_then(|c| {
-            Some(
-                self.connection_ui_from_addr_mut(&c)
-                    .expect("Focused Connection UI is Invalid/Destroyed"),
-            )
+        self.focused_connection.clone().map(|c| {
+            self.connection_ui_from_addr_mut(&c)
+                .expect("Focused Connection UI is Invalid/Destroyed")
         })
     }
 
@@ -253,12 +307,54 @@ impl ServerUI {
     }
 }
 
+#[derive(Default, PartialEq, Eq, Copy, Clone, Serialize, Deserialize)]
+pub enum Mode {
+    #[default]
+    Client,
+    Server,
+}
+
+impl std::fmt::Display for Mode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::Client => "Client",
+                Self::Server => "Server",
+            }
+        )
+    }
+}
+
+fn focused_connection_ui_mut<'a>(
+    client: &'a mut Option<ClientUI>,
+    server: &'a mut Option<ServerUI>,
+) -> Option<&'a mut ConnectionUI> {
+    if let Some(client) = client {
+        Some(&mut client.connection_ui)
+    } else if let Some(server) = server {
+        server.focused_connection_ui_mut()
+    } else {
+        None
+    }
+}
+
 pub struct Tab {
     pub id: u32,
 
     mode: Mode,
     client: Option<ClientUI>,
     server: Option<ServerUI>,
+    pub script: ScriptUI,
+    pub scenario: ScenarioUI,
+    pub filter: LogFilter,
+    pub stats_open: bool,
+    pub inspector: Inspector,
+    pub find: FindBar,
+    /// How many logs the connection or server and each of its connections keep
+    pub log_capacity: usize,
+    recording: Option<Recording>,
 
     rt: Arc<Runtime>,
 }
@@ -271,43 +367,150 @@ impl Tab {
             mode: Mode::default(),
             client: Some(ClientUI::new()),
             server: None,
+            script: ScriptUI::default(),
+            scenario: ScenarioUI::default(),
+            filter: LogFilter::new(format!("tab:{id}")),
+            stats_open: false,
+            inspector: Inspector::new(format!("tab:{id}")),
+            find: FindBar::new(format!("tab:{id}")),
+            log_capacity: logstore::DEFAULT_CAPACITY,
+            recording: None,
         }
     }
 
-    pub fn start_client(&mut self) {
+    /// Makes a tab set up like the one `state` was saved from.
+    pub fn restore(rt: Arc<Runtime>, state: &TabState) -> Self {
+        let mut tab = Self::new(state.id, rt);
+        tab.set_mode(state.mode);
+        match state.mode {
+            Mode::Client => {
+                let client = tab.client_mut();
+                client.address = state.address.clone();
+                client.connection_ui.draft_data = hex::decode(&state.draft).unwrap_or_default();
+                client.connection_ui.send_options = state.send_options.clone();
+            }
+            Mode::Server => tab.server_mut().port = state.port.clone(),
+        }
+        tab.script.source = state.script.clone();
+        if let Some(path) = &state.scenario {
+            tab.scenario.load(path.clone());
+        }
+        if let Some(path) = &state.template {
+            tab.inspector.load_template(path.clone());
+        }
+        tab.log_capacity = state.log_capacity;
+        tab.filter.time_format = state.time_format;
+        tab
+    }
+
+    /// What's kept of the tab, without its draft if that's longer than `max_draft` bytes.
+    pub fn state(&self, max_draft: usize) -> TabState {
+        let client = self.client_safe();
+        TabState {
+            id: self.id,
+            mode: self.mode,
+            address: client.map(|c| c.address.clone()).unwrap_or_default(),
+            port: self
+                .server_safe()
+                .map(|s| s.port.clone())
+                .unwrap_or_default(),
+            draft: client
+                .map(|c| &c.connection_ui.draft_data)
+                .filter(|draft| draft.len() <= max_draft)
+                .map(hex::encode)
+                .unwrap_or_default(),
+            send_options: client
+                .map(|c| c.connection_ui.send_options.clone())
+                .unwrap_or_default(),
+            script: self.script.source.clone(),
+            scenario: self.scenario.path.clone(),
+            template: self.inspector.template_path().cloned(),
+            log_capacity: self.log_capacity,
+            time_format: self.filter.time_format,
+        }
+    }
+
+    pub fn start_client(&mut self) -> anyhow::Result<()> {
         if self.mode() != Mode::Client {
             panic!("Must be in client mode to start_client")
         }
 
         if let Some(client) = &mut self.client {
-            client.start(&self.rt);
+            client.start(&self.rt)
         } else {
             panic!("Client not initialized!");
         }
     }
 
-    pub fn start_server(&mut self) {
+    pub fn start_server(&mut self) -> anyhow::Result<()> {
         if self.mode() != Mode::Server {
             panic!("Must in server mode to start_server")
         }
 
         if let Some(server) = &mut self.server {
-            server.start(&self.rt);
+            server.start(&self.rt)
         } else {
             panic!("Server not initialized!");
         }
     }
 
-    pub fn draft_data_mut(&mut self) -> Option<&mut Vec<u8>> {
-        // Might solve ownership errors when using `and_then`
+    /// Id of the editor of the draft, to get and set its selection.
+    pub fn draft_editor_id(&self) -> egui::Id {
+        egui::Id::new(("tab-draft", self.id))
+    }
+
+    /// The draft of the client or the focused server connection, along with its history.
+    pub fn draft_mut(&mut self) -> Option<(&mut Vec<u8>, &mut History)> {
+        focused_connection_ui_mut(&mut self.client, &mut self.server)
+            .map(|c| (&mut c.draft_data, &mut c.draft_history))
+    }
+
+    /// Shows the inspector on the draft, or on the packet picked to inspect.
+    pub fn inspector_ui(&mut self, ui: &mut egui::Ui) {
+        let editor = self.draft_editor_id();
+        let Self {
+            client,
+            server,
+            inspector,
+            ..
+        } = self;
+        let draft = focused_connection_ui_mut(client, server)
+            .map(|c| (&mut c.draft_data, &mut c.draft_history));
+        inspector.ui(ui, editor, draft);
+    }
+
+    /// Shows the find bar on the draft, opening it with Ctrl+F or Ctrl+H while the draft is focused.
+    pub fn find_ui(&mut self, ui: &mut egui::Ui) {
+        let editor = self.draft_editor_id();
+        if ui.memory(|m| m.has_focus(editor)) {
+            if ui.input_mut(|i| i.consume_key(egui::Modifiers::COMMAND, egui::Key::F)) {
+                self.find.open(false);
+            }
+            if ui.input_mut(|i| i.consume_key(egui::Modifiers::COMMAND, egui::Key::H)) {
+                self.find.open(true);
+            }
+        }
+        if !self.find.open {
+            return;
+        }
+        let Self {
+            client,
+            server,
+            find,
+            ..
+        } = self;
+        if let Some(c) = focused_connection_ui_mut(client, server) {
+            find.ui(ui, editor, &mut c.draft_data, &mut c.draft_history);
+        }
+    }
+
+    pub fn send_options_mut(&mut self) -> Option<&mut SendOptionsUI> {
         if let Some(client) = &mut self.client {
-            Some(&mut client.connection_ui.draft_data)
+            Some(&mut client.connection_ui.send_options)
         } else if let Some(server) = &mut self.server {
-            if let Some(c) = server.focused_connection_ui_mut() {
-                Some(&mut c.draft_data)
-            } else {
-                None
-            }
+            server
+                .focused_connection_ui_mut()
+                .map(|c| &mut c.send_options)
         } else {
             None
         }
@@ -323,10 +526,35 @@ impl Tab {
         }
     }
 
-    pub fn update_and_read_logs(&mut self) -> Vec<Log> {
+    pub fn update_logs(&mut self) {
+        let capacity = self.log_capacity;
         match self.mode {
-            Mode::Client => self.client_mut().backend.update_and_read_logs(),
-            Mode::Server => self.server_mut().update_read_and_process_logs(),
+            Mode::Client => {
+                let backend = &mut self.client_mut().backend;
+                backend.set_log_capacity(capacity);
+                let _ = backend.poll_logs();
+            }
+            Mode::Server => {
+                let server = self.server_mut();
+                server.backend_mut().set_log_capacity(capacity);
+                server.update_and_process_logs();
+            }
+        }
+    }
+
+    /// Shows the logs of the tab's connection or of the focused server connection, returning the
+    /// packet log picked for comparison if any.
+    pub fn logs_ui(&mut self, ui: &mut egui::Ui) -> Option<RowAction> {
+        let filter = &mut self.filter;
+        let template = self.inspector.template();
+        if let Some(client) = &self.client {
+            filter.show(ui, client.backend().logs(), false, template)
+        } else if let Some(server) = &self.server {
+            server.with_focused_logs(|logs| {
+                filter.show(ui, logs, server.is_server_log_focused(), template)
+            })
+        } else {
+            None
         }
     }
 
@@ -362,6 +590,22 @@ impl Tab {
         self.server.as_mut()
     }
 
+    /// Traffic of the tab's connection, of the focused server connection or of the whole server.
+    pub fn stats(&self) -> Stats {
+        if let Some(client) = &self.client {
+            client.backend.stats()
+        } else if let Some(server) = &self.server {
+            match &server.focused_connection {
+                Some(conn_addr) => server
+                    .backend
+                    .with_connection(conn_addr, |conn| conn.unwrap().stats()),
+                None => server.backend.stats(),
+            }
+        } else {
+            Stats::default()
+        }
+    }
+
     pub fn net_state(&self) -> NetState {
         if let Some(client) = &self.client {
             client.backend.net_state()
@@ -372,6 +616,138 @@ impl Tab {
         }
     }
 
+    /// The logs of the tab's connection, or of its server and all of its connections in order.
+    pub fn all_logs(&self) -> Vec<Log> {
+        match self.mode {
+            Mode::Client => self.client().backend().logs().iter().cloned().collect(),
+            Mode::Server => {
+                let backend = self.server().backend();
+                let mut logs: Vec<Log> = backend.logs().iter().cloned().collect();
+                backend.with_connections(|connections| {
+                    for conn in connections {
+                        // The server logs connects and disconnects as well
+                        logs.extend(
+                            conn.logs()
+                                .iter()
+                                .filter(|log| {
+                                    !matches!(
+                                        log.data,
+                                        LogData::ClientConnect(_) | LogData::ClientDisconnect(_)
+                                    )
+                                })
+                                .cloned(),
+                        );
+                    }
+                });
+                logs.sort_by_key(|log| log.timestamp);
+                logs
+            }
+        }
+    }
+
+    /// Writes [`Self::all_logs`] to `path` as JSON Lines or CSV depending on its extension.
+    pub fn export_logs(&self, path: &Path) -> anyhow::Result<()> {
+        let file = BufWriter::new(File::create(path)?);
+        logfile::write(file, &self.all_logs(), logfile::Format::from_path(path))
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    pub fn start_recording(&mut self) {
+        let start = Local::now();
+        let open = |conn: &Connection| {
+            let address = conn.address()?;
+            let endpoints = conn.endpoints()?;
+            (conn.net_state() == NetState::Active).then(|| RecordedConnection {
+                address: address.to_string(),
+                local: endpoints.local,
+                peer: endpoints.peer,
+                since: Some(start),
+            })
+        };
+        self.recording = Some(match self.mode {
+            Mode::Client => {
+                let backend = self.client().backend();
+                let handle = backend.handle();
+                Recording::start(
+                    &self.rt,
+                    backend.subscribe(),
+                    open(backend).into_iter().collect(),
+                    move |_| handle.endpoints(),
+                )
+            }
+            Mode::Server => {
+                let backend = self.server().backend();
+                let handle = backend.handle();
+                Recording::start(
+                    &self.rt,
+                    backend.subscribe(),
+                    backend.with_connections(|conns| conns.iter().filter_map(open).collect()),
+                    move |address| handle.connection(address)?.endpoints(),
+                )
+            }
+        });
+    }
+
+    /// Stops recording and returns everything that happened on the tab since it started, along
+    /// with how many events came too fast to be recorded.
+    pub fn stop_recording(&mut self) -> Option<(Session, u64)> {
+        let recording = self.recording.take()?;
+        let recorded = std::mem::take(&mut *recording.recorded.lock().unwrap());
+        let (side, target) = match self.mode {
+            Mode::Client => (Side::Client, self.client().address.clone()),
+            Mode::Server => (Side::Server, self.server().port.clone()),
+        };
+
+        let session = Session {
+            side,
+            target,
+            connections: recorded.connections,
+            logs: recorded.logs,
+        };
+        Some((session, recorded.missed))
+    }
+
+    /// Writes the traffic of the tab's connection or all of its server's connections to `path`.
+    pub fn export_pcapng(&self, path: &Path) -> anyhow::Result<()> {
+        let file = BufWriter::new(File::create(path)?);
+        match self.mode {
+            Mode::Client => {
+                let backend = self.client().backend();
+                let Some(endpoints) = backend.endpoints() else {
+                    bail!("The tab was never connected");
+                };
+                // Only the last connection's ports are known, earlier ones of a client that
+                // reconnected are told apart by their handshakes
+                let stream = pcapng::Stream {
+                    local: endpoints.local,
+                    peer: endpoints.peer,
+                    local_is_client: true,
+                    logs: backend.logs(),
+                };
+                pcapng::write(file, &[stream])?;
+            }
+            Mode::Server => self.server().backend().with_connections(|connections| {
+                let streams: Vec<_> = connections
+                    .iter()
+                    .filter_map(|conn| {
+                        let endpoints = conn.endpoints()?;
+                        Some(pcapng::Stream {
+                            local: endpoints.local,
+                            peer: endpoints.peer,
+                            local_is_client: false,
+                            logs: conn.logs(),
+                        })
+                    })
+                    .collect();
+                pcapng::write(file, &streams)
+            })?,
+        }
+        Ok(())
+    }
+
     pub fn is_client(&self) -> bool {
         self.mode == Mode::Client
     }
@@ -384,8 +760,74 @@ impl Tab {
         self.mode
     }
 
+    pub fn run_script(&mut self) {
+        let source = self.script.source.clone();
+        let rt = self.rt.handle();
+        self.script.task = Some(match self.mode {
+            Mode::Client => script::run_client(source, self.client().backend().handle(), rt),
+            Mode::Server => script::run_server(source, self.server().backend().handle(), rt),
+        });
+    }
+
+    pub fn stop_script(&mut self) {
+        if let Some(task) = &self.script.task {
+            task.stop();
+        }
+    }
+
+    pub fn is_script_running(&self) -> bool {
+        matches!(&self.script.task, Some(task) if task.is_running())
+    }
+
+    /// Runs the loaded scenario, first connecting or starting the server as its target describes
+    /// if the tab isn't active yet.
+    pub fn run_scenario(&mut self) {
+        let Some(scenario) = self.scenario.scenario.clone() else {
+            return;
+        };
+
+        let inactive = self.net_state() == NetState::Inactive;
+        if inactive {
+            match &scenario.target {
+                Some(Target::Connect(address)) => {
+                    if self.mode != Mode::Client {
+                        self.set_mode(Mode::Client);
+                    }
+                    self.client_mut().address = address.clone();
+                }
+                Some(Target::Listen(port)) => {
+                    if self.mode != Mode::Server {
+                        self.set_mode(Mode::Server);
+                    }
+                    self.server_mut().port = port.to_string();
+                }
+                None => {}
+            }
+        }
+
+        let rt = self.rt.handle().clone();
+        self.scenario.run = Some(match self.mode {
+            Mode::Client => {
+                let events = self.client().backend().subscribe();
+                if inactive {
+                    self.start_client().unwrap();
+                }
+                scenario::run_client(scenario, self.client().backend().handle(), events, &rt)
+            }
+            Mode::Server => {
+                let events = self.server().backend().subscribe();
+                if inactive {
+                    self.start_server().unwrap();
+                }
+                scenario::run_server(scenario, self.server().backend().handle(), events, &rt)
+            }
+        });
+    }
+
     pub fn set_mode(&mut self, mode: Mode) {
         // FIXME: Disallow switching mode with active net state OR auto shutdown it instead
+        self.stop_script();
+        self.recording = None;
         self.mode = mode;
         match mode {
             Mode::Client => {
@@ -400,13 +842,1220 @@ impl Tab {
     }
 }
 
+/// A recording of a tab, taken from its events as they happen rather than from its logs, which only
+/// keep the latest.
+struct Recording {
+    recorded: Arc<Mutex<Recorded>>,
+    task: JoinHandle<()>,
+}
+
+#[derive(Default)]
+struct Recorded {
+    logs: Vec<Log>,
+    /// Connections open when recording started and every one made since
+    connections: Vec<RecordedConnection>,
+    /// Events that came faster than they could be recorded
+    missed: u64,
+}
+
+impl Recording {
+    /// Records `events`, looking up the endpoints of each connection they report with `endpoints`.
+    fn start(
+        rt: &Runtime,
+        mut events: broadcast::Receiver<Log>,
+        open: Vec<RecordedConnection>,
+        endpoints: impl Fn(&str) -> Option<Endpoints> + Send + 'static,
+    ) -> Self {
+        let recorded = Arc::new(Mutex::new(Recorded {
+            connections: open,
+            ..Default::default()
+        }));
+        let task = rt.spawn({
+            let recorded = recorded.clone();
+            async move {
+                loop {
+                    let log = match events.recv().await {
+                        Ok(log) => log,
+                        Err(RecvError::Lagged(missed)) => {
+                            recorded.lock().unwrap().missed += missed;
+                            continue;
+                        }
+                        Err(RecvError::Closed) => break,
+                    };
+                    let mut recorded = recorded.lock().unwrap();
+                    if let LogData::ClientConnect(address) = &log.data {
+                        if let Some(endpoints) = endpoints(address) {
+                            recorded.connections.push(RecordedConnection {
+                                address: address.clone(),
+                                local: endpoints.local,
+                                peer: endpoints.peer,
+                                since: Some(log.timestamp),
+                            });
+                        }
+                    }
+                    recorded.logs.push(log);
+                }
+            }
+        });
+        Self { recorded, task }
+    }
+}
+
+impl Drop for Recording {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// A script attached to a tab, see [`script`] for what it can do.
+#[derive(Default)]
+pub struct ScriptUI {
+    pub source: String,
+    pub open: bool,
+    task: Option<ScriptTask>,
+}
+
+#[derive(Default)]
+pub struct ScenarioUI {
+    pub open: bool,
+    pub path: Option<PathBuf>,
+    pub scenario: Option<Scenario>,
+    pub error: Option<String>,
+    run: Option<ScenarioRun>,
+}
+
+impl ScenarioUI {
+    pub fn load(&mut self, path: PathBuf) {
+        match Scenario::load(&path) {
+            Ok(scenario) => {
+                self.scenario = Some(scenario);
+                self.error = None;
+            }
+            Err(error) => {
+                self.scenario = None;
+                self.error = Some(format!("{error:#}"));
+            }
+        }
+        self.path = Some(path);
+    }
+
+    /// Replaces the loaded scenario with one that wasn't loaded from a file.
+    pub fn set(&mut self, scenario: Scenario) {
+        self.scenario = Some(scenario);
+        self.error = None;
+        self.path = None;
+    }
+
+    pub fn is_running(&self) -> bool {
+        matches!(&self.run, Some(run) if run.is_running())
+    }
+}
+
+fn scenario_ui(tab: &mut Tab, ui: &mut egui::Ui) {
+    ui.horizontal(|ui| {
+        ui.strong("Scenario");
+        if ui.button("Load…").clicked() {
+            if let Some(path) = rfd::FileDialog::new()
+                .add_filter("Scenario", &["toml"])
+                .pick_file()
+            {
+                tab.scenario.load(path);
+            }
+        }
+        if let Some(path) = tab.scenario.path.clone() {
+            if ui
+                .add_enabled(!tab.scenario.is_running(), Button::new("Reload"))
+                .clicked()
+            {
+                tab.scenario.load(path);
+            }
+        }
+        if tab.scenario.is_running() {
+            if ui.button("Stop").clicked() {
+                tab.scenario.run.as_ref().unwrap().abort();
+            }
+        } else if ui
+            .add_enabled(
+                tab.scenario.scenario.is_some() && tab.net_state() != NetState::Establishing,
+                Button::new("Run"),
+            )
+            .clicked()
+        {
+            tab.run_scenario();
+        }
+    });
+
+    if let Some(error) = &tab.scenario.error {
+        ui.colored_label(ui.visuals().error_fg_color, error);
+    }
+    let Some(scenario) = &tab.scenario.scenario else {
+        return;
+    };
+
+    ui.label(&scenario.name);
+    if let Some(target) = &scenario.target {
+        ui.weak(target.to_string());
+    }
+
+    let report = tab.scenario.run.as_ref().map(|r| r.report());
+    if let Some(report) = &report {
+        ui.separator();
+        ui.label(report.summary());
+        if report.finished && ui.button("Export JUnit XML…").clicked() {
+            if let Some(path) = rfd::FileDialog::new()
+                .add_filter("JUnit XML", &["xml"])
+                .set_file_name(format!("{}.xml", report.name))
+                .save_file()
+            {
+                if let Err(error) = std::fs::write(path, report.to_junit_xml()) {
+                    tab.scenario.error = Some(format!("Failed to export report: {error}"));
+                }
+            }
+        }
+    }
+
+    ui.separator();
+    ScrollArea::vertical().show(ui, |ui| {
+        Grid::new("scenario-steps").striped(true).show(ui, |ui| {
+            for (i, step) in scenario.steps.iter().enumerate() {
+                let result = report.as_ref().and_then(|r| r.results.get(i));
+                ui.monospace(format!("{}", i + 1));
+                ui.vertical(|ui| {
+                    ui.label(step.to_string());
+                    if let Some(StepResult {
+                        outcome: Outcome::Failed(message),
+                        expected,
+                        actual,
+                        ..
+                    }) = result
+                    {
+                        ui.colored_label(ui.visuals().error_fg_color, message);
+                        byte_diff_ui(ui, expected.as_deref(), actual.as_deref());
+                    }
+                });
+                match result.map(|r| &r.outcome) {
+                    Some(Outcome::Passed) => {
+                        ui.colored_label(Color32::GREEN, "Passed");
+                    }
+                    Some(Outcome::Failed(_)) => {
+                        ui.colored_label(ui.visuals().error_fg_color, "Failed");
+                    }
+                    Some(Outcome::Skipped) => {
+                        ui.weak("Skipped");
+                    }
+                    None => {
+                        ui.weak("Pending");
+                    }
+                }
+                ui.monospace(
+                    result
+                        .map(|r| format!("{:.1}ms", r.elapsed.as_secs_f64() * 1000.))
+                        .unwrap_or_default(),
+                );
+                ui.end_row();
+            }
+        });
+    });
+}
+
+/// Shows expected and actual bytes underneath each other, highlighting where they differ.
+fn byte_diff_ui(ui: &mut egui::Ui, expected: Option<&[u8]>, actual: Option<&[u8]>) {
+    let font = egui::TextStyle::Monospace.resolve(ui.style());
+    let (weak_color, text_color, error_color) = (
+        ui.visuals().weak_text_color(),
+        ui.visuals().text_color(),
+        ui.visuals().error_fg_color,
+    );
+    let row = |label: &str, data: &[u8], other: Option<&[u8]>| {
+        let mut job = LayoutJob::default();
+        job.append(label, 0., TextFormat::simple(font.clone(), weak_color));
+        for (i, byte) in data.iter().enumerate() {
+            let color = if other.is_none() || other.and_then(|o| o.get(i)) == Some(byte) {
+                text_color
+            } else {
+                error_color
+            };
+            job.append(
+                &format!("{byte:02X} "),
+                0.,
+                TextFormat::simple(font.clone(), color),
+            );
+        }
+        job
+    };
+
+    if let Some(expected) = expected {
+        ui.label(row("Expected ", expected, actual));
+    }
+    if let Some(actual) = actual {
+        ui.label(row("Actual   ", actual, expected));
+    }
+}
+
+fn stats_ui(ui: &mut egui::Ui, stats: &Stats) {
+    // Throughput changes every second even without any input
+    ui.ctx().request_repaint_after(Duration::from_millis(500));
+
+    let current = stats.current();
+    let average_size = |size: Option<u64>| size.map_or("-".to_string(), format_bytes);
+    Grid::new("stats")
+        .num_columns(3)
+        .striped(true)
+        .show(ui, |ui| {
+            ui.label("");
+            ui.strong("Sent");
+            ui.strong("Received");
+            ui.end_row();
+            ui.label("Bytes");
+            ui.label(format_bytes(stats.bytes_sent));
+            ui.label(format_bytes(stats.bytes_received));
+            ui.end_row();
+            ui.label("Packets");
+            ui.label(stats.packets_sent.to_string());
+            ui.label(stats.packets_received.to_string());
+            ui.end_row();
+            ui.label("Avg. Packet");
+            ui.label(average_size(stats.average_sent_size()));
+            ui.label(average_size(stats.average_received_size()));
+            ui.end_row();
+            ui.label("Throughput");
+            ui.label(format!("{}/s", format_bytes(current.sent)));
+            ui.label(format!("{}/s", format_bytes(current.received)));
+            ui.end_row();
+            ui.label("Peak");
+            ui.label(format!("{}/s", format_bytes(stats.peak.sent)));
+            ui.label(format!("{}/s", format_bytes(stats.peak.received)));
+            ui.end_row();
+        });
+    ui.add_space(4.);
+
+    Grid::new("stats-times").num_columns(2).show(ui, |ui| {
+        ui.label("Duration");
+        ui.label(stats.duration().map_or("-".to_string(), |duration| {
+            let seconds = duration.as_secs();
+            format!(
+                "{}:{:02}:{:02}",
+                seconds / 3600,
+                seconds / 60 % 60,
+                seconds % 60
+            )
+        }));
+        ui.end_row();
+        let rtt = &stats.round_trips;
+        ui.label("Round Trip");
+        ui.label(match rtt.average() {
+            Some(average) => format!(
+                "{:.1} ms avg, {:.1}–{:.1} ms",
+                average.as_secs_f64() * 1000.,
+                rtt.min.as_secs_f64() * 1000.,
+                rtt.max.as_secs_f64() * 1000.
+            ),
+            None => "-".to_string(),
+        })
+        .on_hover_text("Time from sending data until receiving the next data");
+        ui.end_row();
+    });
+    ui.add_space(8.);
+
+    ui.horizontal(|ui| {
+        ui.strong("Throughput");
+        ui.colored_label(SENT_COLOR, "⏺ Sent");
+        ui.colored_label(RECEIVED_COLOR, "⏺ Received");
+    });
+    throughput_graph_ui(ui, stats);
+}
+
+const SENT_COLOR: Color32 = Color32::from_rgb(0x4a, 0x9e, 0xff);
+const RECEIVED_COLOR: Color32 = Color32::from_rgb(0xff, 0x9e, 0x3d);
+
+/// Plots bytes per second over the last [`stats::HISTORY_SECONDS`], newest on the right.
+fn throughput_graph_ui(ui: &mut egui::Ui, stats: &Stats) {
+    let size = egui::vec2(ui.available_width(), 120.);
+    let (rect, _) = ui.allocate_exact_size(size, egui::Sense::hover());
+    let painter = ui.painter_at(rect);
+    painter.rect_filled(rect, 2., ui.visuals().extreme_bg_color);
+
+    let max = stats
+        .history
+        .iter()
+        .map(|second| second.sent.max(second.received))
+        .max()
+        .unwrap_or_default()
+        .max(1);
+    let step = rect.width() / (stats::HISTORY_SECONDS - 1) as f32;
+    // Right-align the history so that a short one grows in from the right
+    let offset = stats::HISTORY_SECONDS - stats.history.len();
+    let line = |bytes: fn(&stats::Throughput) -> u64| {
+        stats
+            .history
+            .iter()
+            .enumerate()
+            .map(|(i, second)| {
+                egui::pos2(
+                    rect.left() + (offset + i) as f32 * step,
+                    rect.bottom() - bytes(second) as f32 / max as f32 * (rect.height() - 4.),
+                )
+            })
+            .collect::<Vec<_>>()
+    };
+    painter.line(line(|s| s.received), Stroke::new(1.5, RECEIVED_COLOR));
+    painter.line(line(|s| s.sent), Stroke::new(1.5, SENT_COLOR));
+    painter.text(
+        rect.left_top() + egui::vec2(4., 2.),
+        Align2::LEFT_TOP,
+        format!("{}/s", format_bytes(max)),
+        egui::TextStyle::Small.resolve(ui.style()),
+        ui.visuals().weak_text_color(),
+    );
+}
+
+pub fn format_bytes(bytes: u64) -> String {
+    match bytes {
+        0..1024 => format!("{bytes} B"),
+        1024..1_048_576 => format!("{:.1} KiB", bytes as f64 / 1024.),
+        _ => format!("{:.1} MiB", bytes as f64 / 1_048_576.),
+    }
+}
+
+#[derive(Default, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub enum FragmentationKind {
+    #[default]
+    None,
+    FixedSize,
+    Offsets,
+    Random,
+}
+
+impl std::fmt::Display for FragmentationKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::None => "Unfragmented",
+                Self::FixedSize => "Fixed Size",
+                Self::Offsets => "At Offsets",
+                Self::Random => "Random Sizes",
+            }
+        )
+    }
+}
+
+/// Editable state backing the [`SendOptions`] of a connection.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SendOptionsUI {
+    kind: FragmentationKind,
+    fixed_size: usize,
+    /// Comma or whitespace separated byte offsets
+    offsets: String,
+    random_min: usize,
+    random_max: usize,
+    delay_ms: u64,
+}
+
+impl Default for SendOptionsUI {
+    fn default() -> Self {
+        Self {
+            kind: FragmentationKind::default(),
+            fixed_size: 1,
+            offsets: String::new(),
+            random_min: 1,
+            random_max: 8,
+            delay_ms: 0,
+        }
+    }
+}
+
+impl SendOptionsUI {
+    pub fn options(&self) -> SendOptions {
+        let fragmentation = match self.kind {
+            FragmentationKind::None => Fragmentation::None,
+            FragmentationKind::FixedSize => Fragmentation::FixedSize(self.fixed_size),
+            FragmentationKind::Offsets => Fragmentation::Offsets(
+                self.offsets
+                    .split(|c: char| c == ',' || c.is_whitespace())
+                    .filter_map(|o| o.trim().parse().ok())
+                    .collect(),
+            ),
+            FragmentationKind::Random => Fragmentation::Random {
+                min: self.random_min,
+                max: self.random_max,
+            },
+        };
+
+        SendOptions {
+            fragmentation,
+            delay: Duration::from_millis(self.delay_ms),
+        }
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ComboBox::from_id_salt("fragmentation-kind")
+                .selected_text(self.kind.to_string())
+                .show_ui(ui, |ui| {
+                    for kind in [
+                        FragmentationKind::None,
+                        FragmentationKind::FixedSize,
+                        FragmentationKind::Offsets,
+                        FragmentationKind::Random,
+                    ] {
+                        ui.selectable_value(&mut self.kind, kind, kind.to_string());
+                    }
+                });
+
+            match self.kind {
+                FragmentationKind::None => return,
+                FragmentationKind::FixedSize => {
+                    ui.add(
+                        DragValue::new(&mut self.fixed_size)
+                            .range(1..=usize::MAX)
+                            .suffix(" B"),
+                    );
+                }
+                FragmentationKind::Offsets => {
+                    ui.add(
+                        TextEdit::singleline(&mut self.offsets)
+                            .desired_width(172.)
+                            .hint_text("4, 10, 32"),
+                    );
+                }
+                FragmentationKind::Random => {
+                    ui.add(
+                        DragValue::new(&mut self.random_min)
+                            .range(1..=self.random_max)
+                            .suffix(" B"),
+                    );
+                    ui.label("to");
+                    ui.add(
+                        DragValue::new(&mut self.random_max)
+                            .range(self.random_min..=usize::MAX)
+                            .suffix(" B"),
+                    );
+                }
+            }
+
+            ui.separator();
+            ui.label("Delay");
+            ui.add(DragValue::new(&mut self.delay_ms).suffix(" ms"));
+        });
+    }
+}
+
+/// A single row of a log. `show_addresses` names the peer of connects and disconnects, for logs
+/// that span several connections.
+/// Height of log rows that aren't packets, and the least packets take up.
+const LOG_ROW_HEIGHT: f32 = 20.;
+const LOG_LABEL_WIDTH: f32 = 108.;
+const PACKET_MARGIN: i8 = 4;
+
+/// How logs are laid out in the available width. Row heights follow from this without laying
+/// anything out, so that only the rows that are scrolled into view have to be.
+pub struct LogLayout<'a> {
+    pub hex: HexLayout,
+    /// Template whose fields the bits of packets are coloured by
+    pub template: Option<&'a Template>,
+    /// Packets are broken into lines of this many bytes
+    pub bytes_per_line: usize,
+    /// Digits of the offsets in front of lines, 0 if they're hidden
+    offset_digits: usize,
+    line_height: f32,
+    weak_height: f32,
+    spacing: f32,
+}
+
+impl<'a> LogLayout<'a> {
+    pub fn new(ui: &egui::Ui, template: Option<&'a Template>, time_format: TimeFormat) -> Self {
+        let font = egui::TextStyle::Monospace.resolve(ui.style());
+        let char_width = ui.fonts(|f| f.glyph_width(&font, '0'));
+        let spacing = ui.spacing().item_spacing;
+
+        // Timestamp, label and the margins and stroke of the packet's frame
+        let prefix = time_format.width() as f32 * char_width
+            + LOG_LABEL_WIDTH
+            + 2. * spacing.x
+            + 2. * (PACKET_MARGIN as f32 + 1.);
+        let chars = ((ui.available_width() - prefix) / char_width).max(0.) as usize;
+        let hex = HexLayout::load(ui.ctx());
+        // Offsets of most packets fit in this many digits, longer ones widen their gutter
+        let offset_digits = hex.offset_digits(u16::MAX as usize);
+        let gutter = if offset_digits == 0 {
+            0
+        } else {
+            offset_digits + 2
+        };
+        let bytes_per_line = hex.bytes_per_row(chars.saturating_sub(gutter), false);
+
+        Self {
+            hex,
+            template,
+            bytes_per_line,
+            offset_digits,
+            line_height: ui.text_style_height(&egui::TextStyle::Monospace),
+            weak_height: ui.text_style_height(&egui::TextStyle::Body),
+            spacing: spacing.y,
+        }
+    }
+
+    pub fn height(&self, log: &Log) -> f32 {
+        match &log.data {
+            LogData::SentPacket(packet) | LogData::ReceivedPacket(packet) => {
+                let lines = packet.data.len().div_ceil(self.bytes_per_line).max(1);
+                let mut height = lines as f32 * self.line_height + 2. * (PACKET_MARGIN as f32 + 1.);
+                if matches!(log.data, LogData::SentPacket(_)) && !packet.fragments.is_empty() {
+                    height += self.spacing + self.weak_height;
+                }
+                height.max(LOG_ROW_HEIGHT)
+            }
+            _ => LOG_ROW_HEIGHT,
+        }
+    }
+}
+
+pub fn log_ui(
+    ui: &mut egui::Ui,
+    log: &Log,
+    time: &str,
+    show_addresses: bool,
+    highlights: Highlights,
+    layout: &LogLayout,
+) -> egui::Response {
+    ui.horizontal(|ui| {
+        ui.monospace(time);
+        match &log.data {
+            LogData::ClientConnect(addr) => {
+                log_text_ui(
+                    ui,
+                    if show_addresses {
+                        format!("{} Connected", addr)
+                    } else {
+                        "Connected".into()
+                    },
+                );
+            }
+            LogData::ClientDisconnect(addr) => {
+                log_text_ui(
+                    ui,
+                    if show_addresses {
+                        format!("{} Disconnected", addr)
+                    } else {
+                        "Disconnected".into()
+                    },
+                );
+            }
+            LogData::SentPacket(packet) => {
+                ui.add_sized((LOG_LABEL_WIDTH, LOG_ROW_HEIGHT), Label::new("You"));
+                ui.vertical(|ui| {
+                    packet_data_ui(ui, &packet.data, highlights, layout);
+                    if !packet.fragments.is_empty() {
+                        ui.weak(fragments_summary(&packet.fragments));
+                    }
+                });
+            }
+            LogData::ServerStarted => {
+                log_text_ui(ui, "Server Started".into());
+            }
+            LogData::ServerStopped => {
+                log_text_ui(ui, "Server Stopped".into());
+            }
+            LogData::ReceivedPacket(packet) => {
+                ui.add_sized(
+                    (LOG_LABEL_WIDTH, LOG_ROW_HEIGHT),
+                    Label::new(&packet.address).truncate(),
+                );
+                packet_data_ui(ui, &packet.data, highlights, layout);
+            }
+            LogData::ConnectTimedOut => {
+                log_text_ui(ui, "Failed to Connect: Timed Out".into());
+            }
+            LogData::ConnectError(error) => {
+                log_text_ui(ui, format!("Failed to Connect: {}", error));
+            }
+            LogData::FatalReadError(error) => {
+                log_text_ui(ui, format!("Fatal Read Error: {error}"));
+            }
+            LogData::FatalWriteError(error) => {
+                log_text_ui(ui, format!("Fatal Write Error: {error}"));
+            }
+            LogData::ServerStartError(error) => {
+                log_text_ui(ui, format!("Failed to Start Server: {error}"));
+            }
+            LogData::Script(message) => {
+                log_text_ui(ui, format!("Script: {message}"));
+            }
+            LogData::ScriptError(error) => {
+                log_text_ui(ui, format!("Script Error: {error}"));
+            }
+        };
+    })
+    .response
+}
+
+/// Shows text on a single line so that the row keeps its height, the rest is shown on hover.
+fn log_text_ui(ui: &mut egui::Ui, text: String) {
+    ui.add(Label::new(egui::RichText::new(text).monospace()).truncate());
+}
+
+/// Shows `data` as hex broken into lines, with the byte ranges in `highlights` marked.
+fn packet_data_ui(ui: &mut egui::Ui, data: &[u8], highlights: Highlights, layout: &LogLayout) {
+    let font = egui::TextStyle::Monospace.resolve(ui.style());
+    let text_color = ui.visuals().text_color();
+    let weak_color = ui.visuals().weak_text_color();
+    let match_color = ui.visuals().warn_fg_color.gamma_multiply(0.35);
+    let current_color = ui.visuals().selection.bg_fill;
+    let format = |color, background| TextFormat {
+        font_id: font.clone(),
+        color,
+        background,
+        ..Default::default()
+    };
+    // The highlight a byte is in, if any. Bytes are walked in order, and so are the highlights.
+    let mut ranges = highlights.ranges.iter().enumerate().peekable();
+    let mut highlight = |i: usize| {
+        while ranges.next_if(|(_, range)| range.end <= i).is_some() {}
+        ranges
+            .peek()
+            .filter(|(_, range)| range.start <= i)
+            .map(|(n, _)| *n)
+    };
+    let background = |highlight: Option<usize>| match highlight {
+        Some(n) if highlights.current == Some(n) => current_color,
+        Some(_) => match_color,
+        None => Color32::TRANSPARENT,
+    };
+
+    let group = layout.hex.format.group.max(1);
+    let mut job = LayoutJob::default();
+    for (line, bytes) in data.chunks(layout.bytes_per_line).enumerate() {
+        let start = line * layout.bytes_per_line;
+        if line > 0 {
+            job.append("\n", 0., format(text_color, Color32::TRANSPARENT));
+        }
+        if layout.offset_digits > 0 {
+            let offset = layout.hex.format_offset(start, layout.offset_digits);
+            job.append(&offset, 0., format(weak_color, Color32::TRANSPARENT));
+            job.append("  ", 0., format(text_color, Color32::TRANSPARENT));
+        }
+        let mut previous = None;
+        for (i, &byte) in (start..).zip(bytes) {
+            let current = highlight(i);
+            if i != start && (i - start).is_multiple_of(group) {
+                // Separators are marked only inside of a highlight
+                let between = if current == previous { current } else { None };
+                job.append(" ", 0., format(text_color, background(between)));
+            }
+            append_byte(
+                &mut job,
+                layout.hex.format,
+                byte,
+                i,
+                layout.template,
+                format(text_color, background(current)),
+            );
+            previous = current;
+        }
+    }
+
+    egui::Frame::new()
+        .fill(ui.visuals().extreme_bg_color)
+        .stroke(Stroke::new(
+            1.,
+            ui.visuals().widgets.noninteractive.bg_stroke.color,
+        ))
+        .corner_radius(2)
+        .inner_margin(egui::Margin::same(PACKET_MARGIN))
+        .show(ui, |ui| {
+            ui.set_min_width(ui.available_width());
+            ui.add(Label::new(job).extend());
+        });
+}
+
+fn fragments_summary(fragments: &[usize]) -> String {
+    let mut start = 0;
+    let ranges = fragments
+        .iter()
+        .map(|&end| {
+            let range = format!("{start}..{end}");
+            start = end;
+            range
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    format!("Sent in {} fragments: {ranges}", fragments.len())
+}
+
+/// A flow of an imported capture, shown as the log of the side Palm would play when replaying it.
+pub struct CaptureView {
+    pub id: u32,
+    pub name: String,
+    flows: Vec<Flow>,
+    selected: usize,
+    logs: LogStore,
+    filter: LogFilter,
+    inspector: Inspector,
+    replay: ReplayOptions,
+    /// Address to connect to or port to listen on when replaying
+    target: String,
+}
+
+impl CaptureView {
+    pub fn new(id: u32, name: String, flows: Vec<Flow>) -> Self {
+        Self::playing(id, name, flows, Side::default())
+    }
+
+    /// Makes a view that replays `side` of the flows by default.
+    pub fn playing(id: u32, name: String, flows: Vec<Flow>, side: Side) -> Self {
+        let mut view = Self {
+            id,
+            name,
+            flows,
+            selected: 0,
+            logs: LogStore::default(),
+            filter: LogFilter::new(format!("capture:{id}")).opened(),
+            inspector: Inspector::new(format!("capture:{id}")),
+            replay: ReplayOptions {
+                side,
+                ..Default::default()
+            },
+            target: String::new(),
+        };
+        view.update();
+        view
+    }
+
+    fn flow(&self) -> &Flow {
+        &self.flows[self.selected]
+    }
+
+    /// Refreshes the log and target after the flow or side changed.
+    fn update(&mut self) {
+        let flow = &self.flows[self.selected];
+        self.target = match self.replay.side {
+            Side::Client => flow.server.to_string(),
+            Side::Server => flow.server.port().to_string(),
+        };
+        self.logs = flow.logs(self.replay.side).into();
+    }
+
+    /// The scenario replaying the selected flow, if it's a TCP flow and the target is valid.
+    pub fn replay_scenario(&self) -> Option<Scenario> {
+        let flow = self.flow();
+        if flow.protocol != Protocol::Tcp {
+            return None;
+        }
+
+        let mut scenario = flow.to_scenario(&self.replay);
+        scenario.target = Some(match self.replay.side {
+            Side::Client => Target::Connect(self.target.trim().to_string()),
+            Side::Server => Target::Listen(self.target.trim().parse().ok()?),
+        });
+        Some(scenario)
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) -> Option<PaneAction> {
+        let mut replay = None;
+
+        TopBottomPanel::top(format!("capture-options:{}", self.id)).show_inside(ui, |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Flow");
+                let mut selected = self.selected;
+                ComboBox::from_id_salt(format!("capture-flow:{}", self.id))
+                    .selected_text(flow_summary(self.flow()))
+                    .width(ui.available_width())
+                    .show_ui(ui, |ui| {
+                        for (i, flow) in self.flows.iter().enumerate() {
+                            ui.selectable_value(&mut selected, i, flow_summary(flow));
+                        }
+                    });
+                if selected != self.selected {
+                    self.selected = selected;
+                    self.update();
+                }
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("Play as");
+                let side = self.replay.side;
+                ui.selectable_value(&mut self.replay.side, Side::Client, "Client");
+                ui.selectable_value(&mut self.replay.side, Side::Server, "Server");
+                if side != self.replay.side {
+                    self.update();
+                }
+
+                ui.separator();
+                ui.add(
+                    TextEdit::singleline(&mut self.target)
+                        .desired_width(172.)
+                        .hint_text(match self.replay.side {
+                            Side::Client => "127.0.0.1:54321",
+                            Side::Server => "54321",
+                        }),
+                );
+                ui.checkbox(&mut self.replay.timing, "Original Timing");
+                ComboBox::from_id_salt(format!("capture-replies:{}", self.id))
+                    .selected_text(replies_label(self.replay.replies))
+                    .show_ui(ui, |ui| {
+                        for replies in [Replies::Ignore, Replies::Length, Replies::Exact] {
+                            ui.selectable_value(
+                                &mut self.replay.replies,
+                                replies,
+                                replies_label(replies),
+                            );
+                        }
+                    });
+
+                let scenario = self.replay_scenario();
+                let button = ui
+                    .add_enabled(scenario.is_some(), Button::new("Replay in New Tab"))
+                    .on_disabled_hover_text(if self.flow().protocol == Protocol::Tcp {
+                        "Invalid target"
+                    } else {
+                        "Only TCP flows can be replayed"
+                    });
+                if button.clicked() {
+                    replay = scenario.map(PaneAction::Replay);
+                }
+            });
+        });
+
+        let picked =
+            inspected_logs_ui(ui, &mut self.filter, &mut self.inspector, &self.logs, false);
+        replay.or(picked.map(PaneAction::Compare))
+    }
+}
+
+fn flow_summary(flow: &Flow) -> String {
+    format!(
+        "{flow} ({} messages, {} bytes)",
+        flow.messages.len(),
+        flow.total_len()
+    )
+}
+
+fn replies_label(replies: Replies) -> &'static str {
+    match replies {
+        Replies::Ignore => "Don't Wait for Replies",
+        Replies::Length => "Wait for Reply Lengths",
+        Replies::Exact => "Expect Exact Replies",
+    }
+}
+
+/// A read-only log loaded from a file.
+pub struct LogView {
+    pub id: u32,
+    pub name: String,
+    logs: LogStore,
+    filter: LogFilter,
+    inspector: Inspector,
+}
+
+impl LogView {
+    pub fn new(id: u32, name: String, logs: Vec<Log>) -> Self {
+        Self {
+            id,
+            name,
+            logs: logs.into(),
+            filter: LogFilter::new(format!("log-view:{id}")).opened(),
+            inspector: Inspector::new(format!("log-view:{id}")),
+        }
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) -> Option<PaneAction> {
+        inspected_logs_ui(ui, &mut self.filter, &mut self.inspector, &self.logs, true)
+            .map(PaneAction::Compare)
+    }
+}
+
+/// Shows read-only logs with the inspector beside them while a packet is inspected. Returns the
+/// packet log picked to compare.
+fn inspected_logs_ui(
+    ui: &mut egui::Ui,
+    filter: &mut LogFilter,
+    inspector: &mut Inspector,
+    logs: &LogStore,
+    show_addresses: bool,
+) -> Option<Log> {
+    if inspector.is_inspecting_packet() {
+        SidePanel::right(format!("inspector:{}", inspector.id()))
+            .resizable(true)
+            .default_width(300.)
+            .show_inside(ui, |ui| inspector.ui(ui, egui::Id::NULL, None));
+    }
+    let picked = CentralPanel::default()
+        .show_inside(ui, |ui| {
+            filter.show(ui, logs, show_addresses, inspector.template())
+        })
+        .inner;
+    match picked {
+        Some(RowAction::Compare(log)) => Some(log),
+        Some(RowAction::Inspect(log)) => {
+            inspector.inspect(&log);
+            None
+        }
+        None => None,
+    }
+}
+
+/// A packet picked for comparison, along with the title of the pane it was picked in.
+#[derive(Clone)]
+pub struct ComparedPacket {
+    pub source: String,
+    pub log: Log,
+}
+
+impl ComparedPacket {
+    fn data(&self) -> &[u8] {
+        match &self.log.data {
+            LogData::SentPacket(packet) | LogData::ReceivedPacket(packet) => &packet.data,
+            _ => &[],
+        }
+    }
+
+    pub fn label(&self) -> String {
+        format!("{} · {}", self.source, packet_summary(&self.log))
+    }
+}
+
+/// When a packet log happened, where it went and how big it is.
+pub fn packet_summary(log: &Log) -> String {
+    let (direction, len) = match &log.data {
+        LogData::SentPacket(packet) => (format!("Sent to {}", packet.address), packet.data.len()),
+        LogData::ReceivedPacket(packet) => (
+            format!("Received from {}", packet.address),
+            packet.data.len(),
+        ),
+        _ => (String::new(), 0),
+    };
+    format!(
+        "{} · {direction} · {len} bytes",
+        log.timestamp.format("%H:%M:%S%.3f")
+    )
+}
+
+const DIFF_BYTES_PER_ROW: usize = 16;
+
+/// Two packets side by side in hex and ASCII, aligned so that the bytes they have in common line
+/// up, with inserted, deleted and changed bytes highlighted.
+pub struct DiffView {
+    pub id: u32,
+    left: ComparedPacket,
+    right: ComparedPacket,
+    cells: Vec<diff::Cell>,
+    /// Offsets of the first byte of each row on the left and right side
+    row_offsets: Vec<(usize, usize)>,
+}
+
+impl DiffView {
+    pub fn new(id: u32, left: ComparedPacket, right: ComparedPacket) -> Self {
+        let mut view = Self {
+            id,
+            left,
+            right,
+            cells: Vec::new(),
+            row_offsets: Vec::new(),
+        };
+        view.update();
+        view
+    }
+
+    fn update(&mut self) {
+        self.cells = diff::diff(self.left.data(), self.right.data());
+        let mut offsets = (0, 0);
+        self.row_offsets = self
+            .cells
+            .chunks(DIFF_BYTES_PER_ROW)
+            .map(|row| {
+                let start = offsets;
+                offsets.0 += row.iter().filter(|cell| cell.left.is_some()).count();
+                offsets.1 += row.iter().filter(|cell| cell.right.is_some()).count();
+                start
+            })
+            .collect();
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        let colors = DiffColors::new(ui.visuals());
+
+        TopBottomPanel::top(format!("diff-header:{}", self.id)).show_inside(ui, |ui| {
+            Grid::new(format!("diff-packets:{}", self.id))
+                .num_columns(2)
+                .show(ui, |ui| {
+                    ui.strong("Left");
+                    ui.label(self.left.label());
+                    ui.end_row();
+                    ui.strong("Right");
+                    ui.label(self.right.label());
+                    ui.end_row();
+                });
+            ui.horizontal(|ui| {
+                if ui.button("⇄ Swap").clicked() {
+                    std::mem::swap(&mut self.left, &mut self.right);
+                    self.update();
+                }
+                ui.separator();
+                let count = |change| self.cells.iter().filter(|c| c.change == change).count();
+                let (changed, deleted, inserted) = (
+                    count(Change::Changed),
+                    count(Change::Deleted),
+                    count(Change::Inserted),
+                );
+                if changed + deleted + inserted == 0 {
+                    ui.label("Identical");
+                } else {
+                    for (text, color) in [
+                        (format!("{changed} changed"), colors.changed),
+                        (format!("{deleted} deleted"), colors.deleted),
+                        (format!("{inserted} inserted"), colors.inserted),
+                    ] {
+                        ui.label(egui::RichText::new(text).background_color(color));
+                    }
+                }
+            });
+        });
+
+        CentralPanel::default().show_inside(ui, |ui| {
+            let font = egui::TextStyle::Monospace.resolve(ui.style());
+            let row_height = ui.fonts(|f| f.row_height(&font));
+            let format = HexLayout::load(ui.ctx()).format;
+            ScrollArea::both()
+                .id_salt(format!("diff-scroll:{}", self.id))
+                .auto_shrink(false)
+                .show_rows(ui, row_height, self.row_offsets.len(), |ui, rows| {
+                    ui.spacing_mut().item_spacing.y = 0.;
+                    for row in rows {
+                        let job = self.row_job(row, &font, ui.visuals(), &colors, &format);
+                        ui.add(Label::new(job).extend());
+                    }
+                });
+        });
+    }
+
+    fn row_job(
+        &self,
+        row: usize,
+        font: &egui::FontId,
+        visuals: &egui::Visuals,
+        colors: &DiffColors,
+        format: &HexFormat,
+    ) -> LayoutJob {
+        let cells = &self.cells[row * DIFF_BYTES_PER_ROW..]
+            [..DIFF_BYTES_PER_ROW.min(self.cells.len() - row * DIFF_BYTES_PER_ROW)];
+        let plain = TextFormat::simple(font.clone(), visuals.text_color());
+        let weak = TextFormat::simple(font.clone(), visuals.weak_text_color());
+        let highlighted = |change| TextFormat {
+            background: colors.of(change),
+            ..plain.clone()
+        };
+
+        let mut job = LayoutJob::default();
+        let (left_offset, right_offset) = self.row_offsets[row];
+        for (side, offset) in [(0, left_offset), (1, right_offset)] {
+            if side == 1 {
+                job.append(" │ ", 0., weak.clone());
+            }
+            let byte = |cell: &diff::Cell| if side == 0 { cell.left } else { cell.right };
+
+            let offset = if format.uppercase {
+                format!("{offset:08X}  ")
+            } else {
+                format!("{offset:08x}  ")
+            };
+            job.append(&offset, 0., weak.clone());
+            for i in 0..DIFF_BYTES_PER_ROW {
+                if i != 0 && i.is_multiple_of(format.group.max(1)) {
+                    job.append(" ", 0., plain.clone());
+                }
+                match cells.get(i) {
+                    Some(cell) => job.append(
+                        &byte(cell).map_or("  ".to_string(), |b| format.byte(b)),
+                        0.,
+                        highlighted(cell.change),
+                    ),
+                    None => job.append("  ", 0., plain.clone()),
+                }
+            }
+            job.append("  ", 0., plain.clone());
+            for i in 0..DIFF_BYTES_PER_ROW {
+                match cells.get(i) {
+                    Some(cell) => {
+                        let c = match byte(cell) {
+                            Some(b) if b.is_ascii_graphic() || b == b' ' => b as char,
+                            Some(_) => '.',
+                            None => ' ',
+                        };
+                        job.append(&c.to_string(), 0., highlighted(cell.change));
+                    }
+                    None => job.append(" ", 0., plain.clone()),
+                }
+            }
+        }
+        job
+    }
+}
+
+struct DiffColors {
+    changed: Color32,
+    deleted: Color32,
+    inserted: Color32,
+}
+
+impl DiffColors {
+    fn new(visuals: &egui::Visuals) -> Self {
+        Self {
+            changed: visuals.warn_fg_color.gamma_multiply(0.35),
+            deleted: visuals.error_fg_color.gamma_multiply(0.35),
+            inserted: Color32::from_rgb(0x3c, 0xb0, 0x3c).gamma_multiply(0.35),
+        }
+    }
+
+    fn of(&self, change: Change) -> Color32 {
+        match change {
+            Change::Equal => Color32::TRANSPARENT,
+            Change::Changed => self.changed,
+            Change::Deleted => self.deleted,
+            Change::Inserted => self.inserted,
+        }
+    }
+}
+
+/// Something a pane asks for that needs other panes, which only the tree can add.
+pub enum PaneAction {
+    /// Replay a scenario in a new tab
+    Replay(Scenario),
+    /// Compare a packet log with another one
+    Compare(Log),
+}
+
+pub fn error_dialog(title: &str, description: String) {
+    rfd::MessageDialog::new()
+        .set_level(rfd::MessageLevel::Error)
+        .set_title(title)
+        .set_description(description)
+        .show();
+}
+
+// Panes live in the tile tree and are few, so their size doesn't matter
+#[allow(clippy::large_enum_variant)]
 pub enum Pane {
     Tab(Tab),
+    Capture(CaptureView),
+    Logs(LogView),
+    Diff(DiffView),
 }
 
 #[derive(Default)]
 pub struct TreeBehavior {
     pub spawn_tab_into: Option<TileId>,
+    pub open_file_into: Option<TileId>,
+    /// A scenario to open in a new tab next to the given tile
+    pub replay: Option<(TileId, Scenario)>,
+    /// The packet picked first for comparison, while waiting for the second one
+    pub comparing: Option<ComparedPacket>,
+    /// Two packets to compare in a new pane next to the given tile
+    pub compare: Option<(TileId, ComparedPacket, ComparedPacket)>,
+    pub save_workspace: bool,
+    pub open_workspace: bool,
 }
 
 impl Behavior<Pane> for TreeBehavior {
@@ -414,8 +2063,8 @@ impl Behavior<Pane> for TreeBehavior {
         match pane {
             Pane::Tab(tab) => {
                 let detailed_title = match (
-                    tab.client_safe().and_then(|c| Some(c.address.trim())),
-                    tab.server_safe().and_then(|s| Some(s.port.trim())),
+                    tab.client_safe().map(|c| c.address.trim()),
+                    tab.server_safe().map(|s| s.port.trim()),
                 ) {
                     (Some(client_addr), None) => client_addr
                         .is_empty()
@@ -434,6 +2083,9 @@ impl Behavior<Pane> for TreeBehavior {
                     format!("{} Tab {}", tab.mode(), tab.id).into()
                 }
             }
+            Pane::Capture(view) => view.name.clone().into(),
+            Pane::Logs(view) => view.name.clone().into(),
+            Pane::Diff(_) => "Diff".into(),
         }
     }
 
@@ -443,7 +2095,13 @@ impl Behavior<Pane> for TreeBehavior {
         tile_id: egui_tiles::TileId,
         pane: &mut Pane,
     ) -> egui_tiles::UiResponse {
-        match pane {
+        let action = match pane {
+            Pane::Capture(view) => view.ui(ui),
+            Pane::Logs(view) => view.ui(ui),
+            Pane::Diff(view) => {
+                view.ui(ui);
+                None
+            }
             Pane::Tab(tab) => {
                 TopBottomPanel::top(format!("tab-mode-selector:{}", tab.id)).show_inside(
                     ui,
@@ -479,7 +2137,7 @@ impl Behavior<Pane> for TreeBehavior {
                                 match tab.net_state() {
                                     NetState::Inactive => {
                                         if ui.button("Connect").clicked() {
-                                            tab.start_client();
+                                            tab.start_client().unwrap();
                                         }
                                     }
                                     NetState::Active => {
@@ -502,7 +2160,7 @@ impl Behavior<Pane> for TreeBehavior {
                                 match tab.net_state() {
                                     NetState::Inactive => {
                                         if ui.button("Start").clicked() {
-                                            tab.start_server();
+                                            tab.start_server().unwrap();
                                         }
                                     }
                                     NetState::Active => {
@@ -514,12 +2172,85 @@ impl Behavior<Pane> for TreeBehavior {
                                         ui.add_enabled(false, Button::new("Starting"));
                                     }
                                 };
-                                if !tab.server().is_server_log_focused() {
-                                    if ui.button("End Focused Connection").clicked() {
-                                        tab.server()
-                                            .focused_connection_ui()
-                                            .unwrap()
-                                            .with_backend(tab.server(), |c| c.shutdown())
+                                if !tab.server().is_server_log_focused()
+                                    && ui.button("End Focused Connection").clicked()
+                                {
+                                    tab.server()
+                                        .focused_connection_ui()
+                                        .unwrap()
+                                        .with_backend(tab.server(), |c| c.shutdown())
+                                }
+                            }
+                            ui.separator();
+                            ui.toggle_value(&mut tab.script.open, "Script");
+                            ui.toggle_value(&mut tab.scenario.open, "Scenario");
+                            ui.toggle_value(&mut tab.filter.open, "🔍 Filter");
+                            ui.toggle_value(&mut tab.stats_open, "📈 Stats");
+                            ui.toggle_value(&mut tab.inspector.open, "🔬 Inspector");
+                            ui.menu_button("⚙", |ui| {
+                                ui.menu_button("🕓 Timestamps", |ui| {
+                                    time_format_ui(ui, &mut tab.filter.time_format);
+                                });
+                                ui.horizontal(|ui| {
+                                    ui.label("Keep Last");
+                                    ui.add(
+                                        DragValue::new(&mut tab.log_capacity)
+                                            .range(1_000..=10_000_000)
+                                            .speed(1_000),
+                                    );
+                                    ui.label("Logs");
+                                });
+                            });
+                            ui.separator();
+                            let mut recording = tab.is_recording();
+                            if ui.toggle_value(&mut recording, "⏺ Record").changed() {
+                                if recording {
+                                    tab.start_recording();
+                                } else if let Some((session, missed)) = tab.stop_recording() {
+                                    if missed > 0 {
+                                        rfd::MessageDialog::new()
+                                            .set_level(rfd::MessageLevel::Warning)
+                                            .set_title("Events Missed")
+                                            .set_description(format!(
+                                                "{missed} events came too fast to be recorded and \
+                                                 are missing from the session."
+                                            ))
+                                            .show();
+                                    }
+                                    if let Some(path) = rfd::FileDialog::new()
+                                        .add_filter("Palm Session", &[session::EXTENSION])
+                                        .set_file_name(format!("session.{}", session::EXTENSION))
+                                        .save_file()
+                                    {
+                                        if let Err(error) = session.save(&path) {
+                                            error_dialog(
+                                                "Saving Session Failed",
+                                                format!("{error:#}"),
+                                            );
+                                        }
+                                    }
+                                }
+                            }
+                            if ui.button("Export Log…").clicked() {
+                                if let Some(path) = rfd::FileDialog::new()
+                                    .add_filter("JSON Lines", &["jsonl"])
+                                    .add_filter("CSV", &["csv"])
+                                    .set_file_name("palm.jsonl")
+                                    .save_file()
+                                {
+                                    if let Err(error) = tab.export_logs(&path) {
+                                        error_dialog("Export Failed", format!("{error:#}"));
+                                    }
+                                }
+                            }
+                            if ui.button("Export pcapng…").clicked() {
+                                if let Some(path) = rfd::FileDialog::new()
+                                    .add_filter("pcapng", &["pcapng"])
+                                    .set_file_name("palm.pcapng")
+                                    .save_file()
+                                {
+                                    if let Err(error) = tab.export_pcapng(&path) {
+                                        error_dialog("Export Failed", format!("{error:#}"));
                                     }
                                 }
                             }
@@ -529,22 +2260,35 @@ impl Behavior<Pane> for TreeBehavior {
                 TopBottomPanel::bottom(format!("tab-input:{}", tab.id))
                     .resizable(true)
                     .show_inside(ui, |ui| {
+                        if let Some(send_options) = tab.send_options_mut() {
+                            send_options.ui(ui);
+                        }
+                        tab.find_ui(ui);
                         ui.with_layout(Layout::left_to_right(Align::BOTTOM), |ui| {
                             let mut empty_draft_data = Vec::new();
-                            let draft_data = tab.draft_data_mut();
-                            let draft_data_len = draft_data.as_ref().and_then(|d| Some(d.len()));
+                            let editor_id = tab.draft_editor_id();
+                            let draft = focused_connection_ui_mut(&mut tab.client, &mut tab.server)
+                                .map(|c| (&mut c.draft_data, &mut c.draft_history));
+                            let draft_data_len = draft.as_ref().map(|(d, _)| d.len());
+                            let editor = match draft {
+                                Some((data, history)) => HexEditor::new(data).history(history),
+                                None => HexEditor::new(&mut empty_draft_data),
+                            }
+                            .id(editor_id)
+                            .highlights(tab.find.matches())
+                            .template(tab.inspector.template());
 
                             ui.add_sized(
                                 (
                                     ui.available_width() - 64.,
                                     ui.available_height() - ui.spacing().item_spacing.y,
                                 ),
-                                HexEditor::new(draft_data.unwrap_or(&mut empty_draft_data)),
+                                editor,
                             );
                             if ui
                                 .add_enabled(
                                     tab.net_state() == NetState::Active
-                                        && draft_data_len != None
+                                        && draft_data_len.is_some()
                                         && draft_data_len != Some(0),
                                     Button::new("Send"),
                                 )
@@ -602,74 +2346,106 @@ impl Behavior<Pane> for TreeBehavior {
                         },
                     );
                 }
-                CentralPanel::default().show_inside(ui, |ui| {
-                    ScrollArea::vertical().show(ui, |ui| {
-                        let server_log_focused = matches!(
-                            tab.server_safe()
-                                .and_then(|s| Some(s.is_server_log_focused())),
-                            Some(true)
-                        );
-
-                        for log in tab.update_and_read_logs() {
-                            ui.horizontal(|ui| {
-                                ui.monospace(log.timestamp.format("%H:%M:%S").to_string());
-                                match &log.data {
-                                    LogData::ClientConnect(addr) => {
-                                        ui.monospace(if server_log_focused {
-                                            format!("{} Connected", addr)
-                                        } else {
-                                            "Connected".into()
-                                        });
-                                    }
-                                    LogData::ClientDisconnect(addr) => {
-                                        ui.monospace(if server_log_focused {
-                                            format!("{} Disconnected", addr)
-                                        } else {
-                                            "Disconnected".into()
-                                        });
-                                    }
-                                    LogData::SentPacket(packet) => {
-                                        ui.add_sized((108., 20.), Label::new("You"));
-                                        let mut hex_formatted = hex_encode_formatted(&packet.data);
-                                        ui.add(
-                                            TextEdit::multiline(&mut hex_formatted)
-                                                .code_editor()
-                                                .desired_width(f32::INFINITY),
-                                        );
-                                    }
-                                    LogData::ServerStarted => {
-                                        ui.monospace("Server Started");
+                if tab.stats_open {
+                    SidePanel::right(format!("tab-stats:{}", tab.id))
+                        .resizable(true)
+                        .default_width(300.)
+                        .show_inside(ui, |ui| {
+                            ui.strong(
+                                match tab.server_safe().and_then(|s| s.focused_connection()) {
+                                    Some(conn_addr) => format!("Stats of {conn_addr}"),
+                                    None if tab.is_server() => {
+                                        "Stats of All Connections".to_string()
                                     }
-                                    LogData::ServerStopped => {
-                                        ui.monospace("Server Stopped");
-                                    }
-                                    LogData::ReceivedPacket(packet) => {
-                                        ui.add_sized((108., 20.), Label::new(&packet.address));
-                                        let mut hex_formatted = hex_encode_formatted(&packet.data);
-                                        ui.add(
-                                            TextEdit::multiline(&mut hex_formatted)
-                                                .code_editor()
-                                                .desired_width(f32::INFINITY),
-                                        );
-                                    }
-                                    LogData::ConnectTimedOut => {
-                                        ui.monospace("Failed to Connect: Timed Out");
-                                    }
-                                    LogData::ConnectError(error) => {
-                                        ui.monospace(format!("Failed to Connect: {}", error));
-                                    }
-                                    LogData::FatalReadError(error) => {
-                                        ui.monospace(format!("Fatal Read Error: {error}"));
-                                    }
-                                    LogData::ServerStartError(error) => {
-                                        ui.monospace(format!("Failed to Start Server: {error}"));
+                                    None => "Stats".to_string(),
+                                },
+                            );
+                            ui.add_space(4.);
+                            stats_ui(ui, &tab.stats());
+                        });
+                }
+                if tab.inspector.open {
+                    SidePanel::right(format!("tab-inspector:{}", tab.id))
+                        .resizable(true)
+                        .default_width(300.)
+                        .show_inside(ui, |ui| tab.inspector_ui(ui));
+                }
+                if tab.scenario.open {
+                    SidePanel::right(format!("tab-scenario:{}", tab.id))
+                        .resizable(true)
+                        .default_width(360.)
+                        .show_inside(ui, |ui| scenario_ui(tab, ui));
+                }
+                if tab.script.open {
+                    SidePanel::right(format!("tab-script:{}", tab.id))
+                        .resizable(true)
+                        .default_width(320.)
+                        .show_inside(ui, |ui| {
+                            ui.horizontal(|ui| {
+                                ui.strong("Script");
+                                if tab.is_script_running() {
+                                    if ui.button("Stop").clicked() {
+                                        tab.stop_script();
                                     }
-                                };
+                                } else if ui
+                                    .add_enabled(
+                                        tab.net_state() == NetState::Active,
+                                        Button::new("Run"),
+                                    )
+                                    .clicked()
+                                {
+                                    tab.run_script();
+                                }
                             });
-                        }
-                    });
-                });
+                            let hint = match tab.mode() {
+                                Mode::Client => {
+                                    "send(\"PING\");\nlet reply = expect(\"PONG\", 1000);"
+                                }
+                                Mode::Server => {
+                                    "fn on_data(address, data) {\n    send(address, data);\n}"
+                                }
+                            };
+                            ScrollArea::vertical().show(ui, |ui| {
+                                ui.add(
+                                    TextEdit::multiline(&mut tab.script.source)
+                                        .code_editor()
+                                        .desired_rows(24)
+                                        .desired_width(f32::INFINITY)
+                                        .hint_text(hint),
+                                );
+                            });
+                        });
+                }
+                let picked = CentralPanel::default()
+                    .show_inside(ui, |ui| {
+                        tab.update_logs();
+                        tab.logs_ui(ui)
+                    })
+                    .inner;
+                match picked {
+                    Some(RowAction::Compare(log)) => Some(PaneAction::Compare(log)),
+                    Some(RowAction::Inspect(log)) => {
+                        tab.inspector.inspect(&log);
+                        None
+                    }
+                    None => None,
+                }
             }
+        };
+
+        match action {
+            Some(PaneAction::Replay(scenario)) => self.replay = Some((tile_id, scenario)),
+            Some(PaneAction::Compare(log)) => {
+                let packet = ComparedPacket {
+                    source: self.tab_title_for_pane(pane).text().to_string(),
+                    log,
+                };
+                match self.comparing.take() {
+                    Some(first) => self.compare = Some((tile_id, first, packet)),
+                    None => self.comparing = Some(packet),
+                }
+            }
+            None => {}
         }
 
         egui_tiles::UiResponse::None
@@ -691,6 +2467,35 @@ impl Behavior<Pane> for TreeBehavior {
             if ui.button("➕").clicked() {
                 self.spawn_tab_into = Some(tile_id);
             }
+            if ui
+                .button("📂")
+                .on_hover_text("Open Log, Session or Capture…")
+                .clicked()
+            {
+                self.open_file_into = Some(tile_id);
+            }
+            ui.menu_button("🗄", |ui| {
+                if ui.button("Open Workspace…").clicked() {
+                    self.open_workspace = true;
+                }
+                if ui.button("Save Workspace As…").clicked() {
+                    self.save_workspace = true;
+                }
+            })
+            .response
+            .on_hover_text("Workspace");
+            ui.menu_button("🔢", HexLayout::menu_ui)
+                .response
+                .on_hover_text("Hex Display");
+            if let Some(first) = &self.comparing {
+                ui.separator();
+                if ui.button("✕").on_hover_text("Cancel Comparison").clicked() {
+                    self.comparing = None;
+                } else {
+                    ui.weak(format!("⇄ Compare {} with…", first.label()))
+                        .on_hover_text("Right-click another packet to compare it with this one");
+                }
+            }
         });
     }
 
@@ -719,10 +2524,78 @@ impl Behavior<Pane> for TreeBehavior {
     }
 
     fn simplification_options(&self) -> egui_tiles::SimplificationOptions {
-        let mut options = egui_tiles::SimplificationOptions::default();
+        egui_tiles::SimplificationOptions {
+            all_panes_must_have_tabs: true,
+            ..Default::default()
+        }
+    }
+}
 
-        options.all_panes_must_have_tabs = true;
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+
+    /// Polls the tab's server until `done` holds for a connection's logs.
+    fn poll_until(tab: &mut Tab, done: impl Fn(&LogData) -> bool) {
+        let start = Instant::now();
+        loop {
+            tab.server_mut().update_and_process_logs();
+            let backend = tab.server().backend();
+            if backend.with_connections(|conns| {
+                conns
+                    .iter()
+                    .any(|c| c.logs().iter().any(|log| done(&log.data)))
+            }) {
+                return;
+            }
+            assert!(start.elapsed() < Duration::from_secs(5), "Timed out");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn server_logs_are_exported_once() {
+        let rt = Arc::new(Runtime::new().unwrap());
+        let mut tab = Tab::new(0, rt.clone());
+        tab.set_mode(Mode::Server);
+        tab.server_mut().port = "0".into();
+        tab.start_server().unwrap();
+        let port = loop {
+            if let Some(port) = tab.server().backend().port() {
+                break port;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        };
+
+        let client = rt
+            .block_on(Connection::connect(format!("127.0.0.1:{port}")))
+            .unwrap();
+        client.send_data(b"hi".to_vec()).unwrap();
+        poll_until(&mut tab, |data| matches!(data, LogData::ReceivedPacket(_)));
+        client.shutdown();
+        // The server logs the disconnect before the connection does, so it has been polled too
+        poll_until(&mut tab, |data| {
+            matches!(data, LogData::ClientDisconnect(_))
+        });
 
-        options
+        let path = std::env::temp_dir().join(format!("palm-export-{}.jsonl", std::process::id()));
+        tab.export_logs(&path).unwrap();
+        let logs = logfile::read(File::open(&path).unwrap(), logfile::Format::JsonLines);
+        std::fs::remove_file(&path).unwrap();
+
+        let events: Vec<&str> = logs
+            .unwrap()
+            .iter()
+            .map(|log| match log.data {
+                LogData::ServerStarted => "started",
+                LogData::ClientConnect(_) => "connect",
+                LogData::ReceivedPacket(_) => "received",
+                LogData::ClientDisconnect(_) => "disconnect",
+                _ => "other",
+            })
+            .collect();
+        assert_eq!(events, ["started", "connect", "received", "disconnect"]);
     }
 }
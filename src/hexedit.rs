@@ -1,278 +1,1233 @@
-use eframe::egui::text_edit::TextEditState;
-use eframe::egui::{self, TextEdit};
-use eframe::egui::{Key, Widget};
-use regex::Regex;
+use std::{
+    ops::Range,
+    path::Path,
+    sync::atomic::{AtomicU64, Ordering},
+};
 
-use crate::util::hex_encode_formatted;
+use eframe::egui::text::LayoutJob;
+use eframe::egui::{
+    self, Color32, EventFilter, FontId, Id, Key, Pos2, Rect, Sense, TextFormat, Widget,
+};
+use eframe::egui::{vec2, Align2, ScrollArea, Stroke};
 
+use palm_core::{
+    inspect::{self, Endian, Kind},
+    literal::{self, Format},
+    template::Template,
+    util::HexFormat,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    gui::{error_dialog, format_bytes},
+    workspace::MAX_AUTOSAVED_DRAFT,
+};
+
+/// How many edits can be undone.
+const HISTORY_LIMIT: usize = 1000;
+
+/// Tallest the scrolled content gets. Scroll offsets beyond this lose precision as `f32`.
+const MAX_SCROLL_HEIGHT: f32 = 1_000_000.;
+
+/// Undo and redo history of a buffer edited with a [`HexEditor`], kept alongside the buffer so
+/// that every buffer has its own.
+pub struct History {
+    undo: Vec<Edit>,
+    redo: Vec<Edit>,
+    /// Whether the next typed byte joins the last edit
+    typing: bool,
+    revision: u64,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self {
+            undo: Vec::new(),
+            redo: Vec::new(),
+            typing: false,
+            revision: next_revision(),
+        }
+    }
+}
+
+/// Revisions are unique across histories, so that they tell buffers apart too.
+fn next_revision() -> u64 {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    NEXT.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Bytes replaced at a position of the buffer.
+struct Edit {
+    at: usize,
+    removed: Vec<u8>,
+    inserted: Vec<u8>,
+}
+
+impl History {
+    /// Changes whenever the buffer is edited, so that what's worked out from it can be kept until
+    /// then rather than redone every frame.
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// Replaces `range` of `buffer` with `data` so that it can be undone. Bytes typed one after
+    /// another are undone together.
+    pub fn splice(
+        &mut self,
+        buffer: &mut Vec<u8>,
+        range: Range<usize>,
+        data: Vec<u8>,
+        typed: bool,
+    ) {
+        if range.is_empty() && data.is_empty() {
+            return;
+        }
+        let at = range.start;
+        let removed: Vec<u8> = buffer.splice(range, data.iter().copied()).collect();
+        self.redo.clear();
+        self.revision = next_revision();
+
+        match self.undo.last_mut() {
+            Some(last) if typed && self.typing && last.at + last.inserted.len() == at => {
+                last.removed.extend(removed);
+                last.inserted.extend(data);
+            }
+            _ => {
+                if self.undo.len() == HISTORY_LIMIT {
+                    self.undo.remove(0);
+                }
+                self.undo.push(Edit {
+                    at,
+                    removed,
+                    inserted: data,
+                });
+            }
+        }
+        self.typing = typed;
+    }
+
+    /// Reverts the last edit, returning the position after the restored bytes.
+    pub fn undo(&mut self, buffer: &mut Vec<u8>) -> Option<usize> {
+        let edit = self.undo.pop()?;
+        let at = edit.at.min(buffer.len());
+        buffer.splice(
+            at..(at + edit.inserted.len()).min(buffer.len()),
+            edit.removed.iter().copied(),
+        );
+        self.typing = false;
+        self.revision = next_revision();
+        let cursor = at + edit.removed.len();
+        self.redo.push(edit);
+        Some(cursor)
+    }
+
+    /// Applies the last undone edit again, returning the position after the inserted bytes.
+    pub fn redo(&mut self, buffer: &mut Vec<u8>) -> Option<usize> {
+        let edit = self.redo.pop()?;
+        let at = edit.at.min(buffer.len());
+        buffer.splice(
+            at..(at + edit.removed.len()).min(buffer.len()),
+            edit.inserted.iter().copied(),
+        );
+        self.typing = false;
+        self.revision = next_revision();
+        let cursor = at + edit.inserted.len();
+        self.undo.push(edit);
+        Some(cursor)
+    }
+}
+
+/// Edits a buffer as hex, with its printable characters in a column beside it. Both columns share
+/// the cursor and selection; typing in the hex column enters digits, or bits when bytes are shown
+/// as bits, and typing in the ASCII column enters the typed characters' bytes.
 pub struct HexEditor<'a> {
     buffer: &'a mut Vec<u8>,
-    view: String,
-    desired_width: Option<f32>,
+    history: Option<&'a mut History>,
+    id: Option<Id>,
+    read_only: bool,
+    highlights: &'a [Range<usize>],
+    template: Option<&'a Template>,
+}
+
+/// Colour of the bits of the `index`th field of a template.
+pub fn field_color(index: usize) -> Color32 {
+    const COLORS: [Color32; 3] = [
+        Color32::from_rgb(0x4a, 0x9e, 0xe0),
+        Color32::from_rgb(0xe0, 0x8e, 0x3a),
+        Color32::from_rgb(0x4c, 0xb0, 0x6c),
+    ];
+    COLORS[index % COLORS.len()]
+}
+
+/// Appends the `index`th byte of a buffer to `job`. Shown as bits, its bits are coloured by the
+/// fields of `template` they're in.
+pub fn append_byte(
+    job: &mut LayoutJob,
+    hex: HexFormat,
+    byte: u8,
+    index: usize,
+    template: Option<&Template>,
+    format: TextFormat,
+) {
+    let Some(template) = template.filter(|_| hex.binary) else {
+        job.append(&hex.byte(byte), 0., format);
+        return;
+    };
+    for (bit, digit) in hex.byte(byte).chars().enumerate() {
+        let color = template
+            .field_at(index * 8 + bit)
+            .map_or(format.color, |(i, _)| field_color(i));
+        job.append(
+            &digit.to_string(),
+            0.,
+            TextFormat {
+                color,
+                ..format.clone()
+            },
+        );
+    }
+}
+
+/// The values in `data` of the fields of `template` with bits in `bits`, one per line.
+pub fn fields_text(template: &Template, data: &[u8], bits: Range<usize>) -> Option<String> {
+    let lines: Vec<String> = template
+        .fields
+        .iter()
+        .filter(|field| field.bits.start < bits.end && bits.start < field.bits.end)
+        .map(|field| {
+            let value = field.value(data).map_or("-".to_string(), |v| v.to_string());
+            format!("{} = {value}", field.name)
+        })
+        .collect();
+    (!lines.is_empty()).then(|| lines.join("\n"))
+}
+
+/// Copies `data` in a format picked from a menu.
+pub fn copy_as_ui(ui: &mut egui::Ui, data: &[u8]) {
+    ui.menu_button("📋 Copy as", |ui| {
+        for format in Format::ALL {
+            if ui.button(format.name()).clicked() {
+                ui.ctx().copy_text(literal::format(data, format));
+            }
+        }
+    });
+}
+
+/// Saves `data` to a file picked in a dialog.
+pub fn save_dialog(data: &[u8], file_name: &str) {
+    let Some(path) = rfd::FileDialog::new().set_file_name(file_name).save_file() else {
+        return;
+    };
+    if let Err(error) = std::fs::write(&path, data) {
+        error_dialog(
+            "Saving Failed",
+            format!("Failed to write {}: {error}", path.display()),
+        );
+    }
+}
+
+/// Reads the file at `path` to load into an editor, asking first if it's too large to be autosaved.
+/// `None` if it can't be read or loading it was cancelled.
+fn read_file(path: &Path) -> Option<Vec<u8>> {
+    let size = std::fs::metadata(path).map_or(0, |metadata| metadata.len());
+    if size > MAX_AUTOSAVED_DRAFT as u64 {
+        let answer = rfd::MessageDialog::new()
+            .set_level(rfd::MessageLevel::Warning)
+            .set_title("Large File")
+            .set_description(format!(
+                "{} is {}. Drafts over {} are not autosaved, only kept when saving the workspace \
+                 to a file. Load it anyway?",
+                path.display(),
+                format_bytes(size),
+                format_bytes(MAX_AUTOSAVED_DRAFT as u64)
+            ))
+            .set_buttons(rfd::MessageButtons::YesNo)
+            .show();
+        if answer != rfd::MessageDialogResult::Yes {
+            return None;
+        }
+    }
+    match std::fs::read(path) {
+        Ok(data) => Some(data),
+        Err(error) => {
+            error_dialog(
+                "Opening Failed",
+                format!("Failed to read {}: {error}", path.display()),
+            );
+            None
+        }
+    }
+}
+
+/// The bytes selected in the editor with `id`, or an empty range at the cursor if none are.
+pub fn selection(ctx: &egui::Context, id: Id) -> Range<usize> {
+    ctx.data(|r| r.get_temp::<State>(id))
+        .unwrap_or_default()
+        .selection()
+}
+
+/// Selects `range` in the editor with `id`, with the cursor at its end.
+pub fn select(ctx: &egui::Context, id: Id, range: Range<usize>) {
+    ctx.data_mut(|w| {
+        let state = w.get_temp_mut_or_default::<State>(id);
+        state.set_cursor(range.end);
+        state.anchor = range.start;
+        state.scroll_to_cursor = true;
+    });
+}
+
+/// The column of the editor that has the cursor.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+enum Column {
+    #[default]
+    Hex,
+    Ascii,
+}
+
+/// What the editor keeps between frames.
+#[derive(Clone, Default)]
+struct State {
+    /// Position between bytes the cursor is at
+    cursor: usize,
+    /// The other end of the selection, the same as `cursor` if nothing is selected
+    anchor: usize,
+    column: Column,
+    /// The value of the digits typed in the hex column and how many there are, waiting for the
+    /// rest of the byte
+    pending: Option<(u8, usize)>,
+    overwrite: bool,
+    /// The byte a selection being dragged started on
+    drag_origin: Option<usize>,
+    /// Whether to scroll the cursor into view on the next frame
+    scroll_to_cursor: bool,
+    /// Format to parse the next paste as, after picking one to paste from
+    paste_format: Option<Format>,
+}
+
+impl State {
+    fn selection(&self) -> Range<usize> {
+        self.cursor.min(self.anchor)..self.cursor.max(self.anchor)
+    }
+
+    fn set_cursor(&mut self, cursor: usize) {
+        self.cursor = cursor;
+        self.anchor = cursor;
+        self.pending = None;
+    }
+}
+
+/// How hex is laid out in editors and logs, the same everywhere and kept across runs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HexLayout {
+    pub format: HexFormat,
+    /// Bytes per row, `None` to fit as many as there's room for
+    pub bytes_per_row: Option<usize>,
+    pub offsets: Offsets,
+}
+
+/// How the offsets of rows are shown in the gutter.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Offsets {
+    #[default]
+    Hidden,
+    Hex,
+    Decimal,
+}
+
+impl HexLayout {
+    fn id() -> Id {
+        Id::new("hex-layout")
+    }
+
+    pub fn load(ctx: &egui::Context) -> Self {
+        ctx.data_mut(|d| d.get_persisted(Self::id()))
+            .unwrap_or_default()
+    }
+
+    pub fn store(self, ctx: &egui::Context) {
+        ctx.data_mut(|d| d.insert_persisted(Self::id(), self));
+    }
+
+    /// Bytes per row that fit in `chars` characters, with an ASCII column beside the hex if
+    /// `ascii`. Rows of more than 8 bytes are kept to multiples of 8 to keep offsets round.
+    pub fn bytes_per_row(&self, chars: usize, ascii: bool) -> usize {
+        if let Some(bytes) = self.bytes_per_row {
+            return bytes;
+        }
+        let fits = |n: usize| self.format.width(n) + if ascii { n + 2 } else { 0 } <= chars;
+        let group = self.format.group.max(1);
+        let mut bytes = if fits(group) {
+            group
+        } else {
+            (1..group).rev().find(|&n| fits(n)).unwrap_or(1)
+        };
+        while fits(bytes + group) {
+            bytes += group;
+        }
+        if bytes >= 8 {
+            bytes -= bytes % 8;
+        }
+        bytes
+    }
+
+    /// How many digits offsets up to `len` take in the gutter, 0 if it's hidden.
+    pub fn offset_digits(&self, len: usize) -> usize {
+        let digits = match self.offsets {
+            Offsets::Hidden => return 0,
+            Offsets::Hex => format!("{len:X}").len(),
+            Offsets::Decimal => len.to_string().len(),
+        };
+        digits.max(4)
+    }
+
+    pub fn format_offset(&self, offset: usize, digits: usize) -> String {
+        match (self.offsets, self.format.uppercase) {
+            (Offsets::Hidden, _) => String::new(),
+            (Offsets::Hex, true) => format!("{offset:0digits$X}"),
+            (Offsets::Hex, false) => format!("{offset:0digits$x}"),
+            (Offsets::Decimal, _) => format!("{offset:digits$}"),
+        }
+    }
+
+    /// Lets the layout be changed, storing it right away.
+    pub fn menu_ui(ui: &mut egui::Ui) {
+        let mut layout = Self::load(ui.ctx());
+        let old = layout;
+        egui::Grid::new("hex-layout-menu")
+            .num_columns(2)
+            .show(ui, |ui| {
+                ui.label("Bytes");
+                ui.horizontal(|ui| {
+                    ui.selectable_value(&mut layout.format.binary, false, "Hex");
+                    ui.selectable_value(&mut layout.format.binary, true, "Binary");
+                });
+                ui.end_row();
+                ui.label("Group");
+                ui.horizontal(|ui| {
+                    for group in [1, 2, 4, 8] {
+                        ui.selectable_value(&mut layout.format.group, group, group.to_string());
+                    }
+                });
+                ui.end_row();
+                ui.label("Bytes per Row");
+                ui.horizontal(|ui| {
+                    ui.selectable_value(&mut layout.bytes_per_row, None, "Auto");
+                    for bytes in [8, 16, 32] {
+                        ui.selectable_value(
+                            &mut layout.bytes_per_row,
+                            Some(bytes),
+                            bytes.to_string(),
+                        );
+                    }
+                });
+                ui.end_row();
+                ui.label("Offsets");
+                ui.horizontal(|ui| {
+                    ui.selectable_value(&mut layout.offsets, Offsets::Hidden, "Hidden");
+                    ui.selectable_value(&mut layout.offsets, Offsets::Hex, "Hex");
+                    ui.selectable_value(&mut layout.offsets, Offsets::Decimal, "Decimal");
+                });
+                ui.end_row();
+                ui.label("Case");
+                ui.horizontal(|ui| {
+                    ui.selectable_value(&mut layout.format.uppercase, true, "AB");
+                    ui.selectable_value(&mut layout.format.uppercase, false, "ab");
+                });
+                ui.end_row();
+            });
+        if layout != old {
+            layout.store(ui.ctx());
+        }
+    }
+}
+
+/// Where bytes are placed. Rows are placed relative to the first one shown, as offsets into
+/// tens of megabytes of rows would be too large for `f32` to place precisely.
+struct Grid {
+    /// Top left of `first_row`
+    origin: Pos2,
+    first_row: usize,
+    char_width: f32,
+    row_height: f32,
+    layout: HexLayout,
+    bytes_per_row: usize,
+    /// Digits of the offsets in the gutter, 0 if there is none
+    offset_digits: usize,
+}
+
+impl Grid {
+    fn new(
+        origin: Pos2,
+        width: f32,
+        char_width: f32,
+        row_height: f32,
+        layout: HexLayout,
+        len: usize,
+    ) -> Self {
+        let offset_digits = layout.offset_digits(len);
+        let chars = (width / char_width).floor() as usize;
+        let mut grid = Self {
+            origin,
+            first_row: 0,
+            char_width,
+            row_height,
+            layout,
+            bytes_per_row: 1,
+            offset_digits,
+        };
+        grid.bytes_per_row = layout.bytes_per_row(chars.saturating_sub(grid.gutter()), true);
+        grid
+    }
+
+    /// Characters taken by the offsets and the gap after them.
+    fn gutter(&self) -> usize {
+        if self.offset_digits == 0 {
+            0
+        } else {
+            self.offset_digits + 2
+        }
+    }
+
+    /// Rows needed to show `len` bytes, with room for the cursor after the last one.
+    fn rows(&self, len: usize) -> usize {
+        len / self.bytes_per_row + 1
+    }
+
+    fn hex_left(&self) -> f32 {
+        self.gutter() as f32 * self.char_width
+    }
+
+    fn ascii_left(&self) -> f32 {
+        self.hex_left()
+            + (self.layout.format.width(self.bytes_per_row) + 2) as f32 * self.char_width
+    }
+
+    /// How far `row` is below the first row shown.
+    fn row_y(&self, row: usize) -> f32 {
+        (row as isize - self.first_row as isize) as f32 * self.row_height
+    }
+
+    /// The rect of the `col`th byte of `row` in `column`.
+    fn byte_rect(&self, column: Column, row: usize, col: usize) -> Rect {
+        let (x, width) = match column {
+            Column::Hex => (
+                self.hex_left() + self.layout.format.offset(col) as f32 * self.char_width,
+                self.layout.format.digits() as f32 * self.char_width,
+            ),
+            Column::Ascii => (
+                self.ascii_left() + col as f32 * self.char_width,
+                self.char_width,
+            ),
+        };
+        Rect::from_min_size(
+            self.origin + vec2(x, self.row_y(row)),
+            vec2(width, self.row_height),
+        )
+    }
+
+    fn row_rect(&self, row: usize) -> Rect {
+        Rect::from_min_size(
+            self.origin + vec2(0., self.row_y(row)),
+            vec2(
+                self.ascii_left() + self.bytes_per_row as f32 * self.char_width,
+                self.row_height,
+            ),
+        )
+    }
+
+    /// The byte under `pos` and its column, or `len` if `pos` is past the last byte.
+    fn byte_at(&self, pos: Pos2, len: usize) -> (usize, Column) {
+        let pos = pos - self.origin;
+        let row =
+            (self.first_row as isize + (pos.y / self.row_height).floor() as isize).max(0) as usize;
+        let (col, column) = if pos.x < self.ascii_left() - self.char_width {
+            // Groups are their digits and a space
+            let group = self.layout.format.group.max(1);
+            let group_width = self.layout.format.digits() * group + 1;
+            let chars = ((pos.x - self.hex_left()) / self.char_width).max(0.) as usize;
+            let in_group = (chars % group_width / self.layout.format.digits()).min(group - 1);
+            (chars / group_width * group + in_group, Column::Hex)
+        } else {
+            let x = pos.x - self.ascii_left();
+            ((x / self.char_width).max(0.) as usize, Column::Ascii)
+        };
+        let col = col.min(self.bytes_per_row - 1);
+        ((row * self.bytes_per_row + col).min(len), column)
+    }
+
+    /// The byte and bit under `pos` when bytes are shown as bits, with bits counted from the most
+    /// significant one. `None` if `pos` isn't on a bit.
+    fn bit_at(&self, pos: Pos2, len: usize) -> Option<(usize, usize)> {
+        if !self.layout.format.binary {
+            return None;
+        }
+        let (byte, column) = self.byte_at(pos, len);
+        let rect = self.byte_rect(column, byte / self.bytes_per_row, byte % self.bytes_per_row);
+        (byte < len && column == Column::Hex && rect.contains(pos))
+            .then(|| ((pos.x - rect.left()) / self.char_width) as usize)
+            .map(|bit| (byte, bit.min(7)))
+    }
 }
 
 impl<'a> HexEditor<'a> {
     pub fn new(buffer: &'a mut Vec<u8>) -> Self {
         Self {
-            view: hex::encode_upper(&buffer)
-                .chars()
-                .enumerate()
-                .flat_map(|(i, c)| {
-                    if i != 0 && i % 2 == 0 {
-                        Some(' ')
-                    } else {
-                        None
-                    }
-                    .into_iter()
-                    .chain(std::iter::once(c))
-                })
-                .collect::<String>(),
             buffer,
-            desired_width: None,
+            history: None,
+            id: None,
+            read_only: false,
+            highlights: &[],
+            template: None,
         }
     }
 
-    /// Set to 0.0 to keep as small as possible.
-    /// Set to [`f32::INFINITY`] to take up all available space (i.e. disable automatic word wrap).
+    /// Names the bits of the buffer by the fields of `template`, coloured when bytes are shown as
+    /// bits and listed when hovering them.
+    #[inline]
+    pub fn template(mut self, template: Option<&'a Template>) -> Self {
+        self.template = template;
+        self
+    }
+
+    /// Sets the id to use with [`selection`] and [`select`], instead of one based on the parent.
+    #[inline]
+    pub fn id(mut self, id: Id) -> Self {
+        self.id = Some(id);
+        self
+    }
+
+    /// Marks `ranges` of the buffer, such as search matches. They must be sorted.
     #[inline]
-    pub fn desired_width(mut self, desired_width: f32) -> Self {
-        self.desired_width = Some(desired_width);
+    pub fn highlights(mut self, ranges: &'a [Range<usize>]) -> Self {
+        self.highlights = ranges;
         self
     }
 
-    fn handle_event(&self, event: &egui::Event, ctx: &egui::Context) -> (EventHandleResult, bool) {
+    /// Only allows moving around, selecting and copying.
+    #[inline]
+    pub fn read_only(mut self) -> Self {
+        self.read_only = true;
+        self
+    }
+
+    /// Makes edits undoable with Ctrl+Z and redoable with Ctrl+Shift+Z or Ctrl+Y.
+    #[inline]
+    pub fn history(mut self, history: &'a mut History) -> Self {
+        self.history = Some(history);
+        self
+    }
+
+    fn handle_event(&self, event: &egui::Event) -> EventHandleResult {
         match event {
             egui::Event::Key {
                 key,
-                physical_key,
+                physical_key: _,
                 pressed,
-                repeat,
+                repeat: _,
                 modifiers,
-            } => match key {
-                Key::ArrowLeft if *pressed => {
-                    (EventHandleResult::CursorLeft(modifiers.shift), true)
-                }
-                Key::ArrowRight if *pressed => {
-                    (EventHandleResult::CursorRight(modifiers.shift), true)
-                }
-                Key::Backspace if *pressed => (EventHandleResult::Delete, true),
-                _ => (EventHandleResult::NoAction, false),
-            },
-            egui::Event::Cut => (EventHandleResult::Cut, true),
-            egui::Event::Text(text) => (EventHandleResult::Text(text.clone()), true),
-            egui::Event::Paste(text) => (EventHandleResult::Paste(text.clone()), true),
-            _ => (EventHandleResult::NoAction, false),
+            } if *pressed => {
+                let movement = match key {
+                    Key::ArrowLeft => Movement::Left,
+                    Key::ArrowRight => Movement::Right,
+                    Key::ArrowUp => Movement::Up,
+                    Key::ArrowDown => Movement::Down,
+                    Key::Home if modifiers.command => Movement::Start,
+                    Key::End if modifiers.command => Movement::End,
+                    Key::Home => Movement::RowStart,
+                    Key::End => Movement::RowEnd,
+                    Key::PageUp => Movement::PageUp,
+                    Key::PageDown => Movement::PageDown,
+                    Key::Tab => return EventHandleResult::SwitchColumn,
+                    Key::Backspace => return EventHandleResult::Delete,
+                    Key::Delete => return EventHandleResult::ForwardDelete,
+                    Key::Insert => return EventHandleResult::ToggleOverwrite,
+                    Key::A if modifiers.command => return EventHandleResult::SelectAll,
+                    Key::Z if modifiers.command && modifiers.shift => {
+                        return EventHandleResult::Redo
+                    }
+                    Key::Z if modifiers.command => return EventHandleResult::Undo,
+                    Key::Y if modifiers.command => return EventHandleResult::Redo,
+                    _ => return EventHandleResult::NoAction,
+                };
+                EventHandleResult::Cursor(movement, modifiers.shift)
+            }
+            egui::Event::Copy => EventHandleResult::Copy,
+            egui::Event::Cut => EventHandleResult::Cut,
+            egui::Event::Text(text) => EventHandleResult::Text(text.clone()),
+            egui::Event::Paste(text) => EventHandleResult::Paste(text.clone()),
+            _ => EventHandleResult::NoAction,
         }
     }
 
     fn process_event_result(
         &mut self,
         result: EventHandleResult,
-        focused: bool,
-        partial_nibble: &mut PartialNibble,
-        state: &mut TextEditState,
+        state: &mut State,
+        grid: &Grid,
+        rows_per_page: usize,
         ctx: &egui::Context,
     ) {
-        if !focused {
+        let len = self.buffer.len();
+        let selection = state.selection();
+        if self.read_only && result.edits() {
             return;
         }
         match result {
+            EventHandleResult::Cursor(movement, shift_pressed) => {
+                // Typing somewhere else is a separate edit
+                if let Some(history) = &mut self.history {
+                    history.typing = false;
+                }
+                let bytes_per_row = grid.bytes_per_row;
+                let byte = state.cursor;
+                let row_start = byte - byte % bytes_per_row;
+                let page = bytes_per_row * rows_per_page.max(1);
+                let byte = match movement {
+                    Movement::Left => byte.saturating_sub(1),
+                    Movement::Right => byte + 1,
+                    Movement::Up => byte.saturating_sub(bytes_per_row),
+                    Movement::Down => byte + bytes_per_row,
+                    Movement::RowStart => row_start,
+                    Movement::RowEnd => row_start + bytes_per_row,
+                    Movement::PageUp => byte.saturating_sub(page),
+                    Movement::PageDown => byte + page,
+                    Movement::Start => 0,
+                    Movement::End => len,
+                };
+                let anchor = state.anchor;
+                state.set_cursor(byte.min(len));
+                if shift_pressed {
+                    state.anchor = anchor;
+                }
+            }
+            EventHandleResult::SwitchColumn => {
+                state.column = match state.column {
+                    Column::Hex => Column::Ascii,
+                    Column::Ascii => Column::Hex,
+                };
+                state.pending = None;
+            }
+            EventHandleResult::SelectAll => {
+                state.set_cursor(len);
+                state.anchor = 0;
+            }
+            EventHandleResult::ToggleOverwrite => state.overwrite = !state.overwrite,
             EventHandleResult::Delete => {
-                if let Some(cursor_range) = state.cursor.char_range() {
-                    let p_buf_index = view_index_to_buffer_index(cursor_range.primary.index);
-                    let s_buf_index = view_index_to_buffer_index(cursor_range.secondary.index);
-                    if s_buf_index == p_buf_index && p_buf_index != 0 {
-                        self.buffer.remove(p_buf_index - 1);
-                    } else {
-                        self.buffer.drain(
-                            usize::min(p_buf_index, s_buf_index)
-                                ..usize::max(p_buf_index, s_buf_index),
-                        );
-                    }
+                if state.pending.take().is_some() && selection.is_empty() {
+                    return;
                 }
+                let range = if selection.is_empty() {
+                    selection.start.saturating_sub(1)..selection.start
+                } else {
+                    selection
+                };
+                state.set_cursor(range.start);
+                self.splice(range, Vec::new(), false);
             }
-            EventHandleResult::Paste(text) => {
-                if let Some(mut cursor_range) = state.cursor.char_range() {
-                    let p_buf_index = view_index_to_buffer_index(cursor_range.primary.index);
-                    let s_buf_index = view_index_to_buffer_index(cursor_range.secondary.index);
-
-                    let mut cleaned_text: String =
-                        text.chars().filter(|c| c.is_digit(16)).collect();
-
-                    if cleaned_text.len() % 2 != 0 {
-                        cleaned_text.pop();
-                    }
-
-                    if let Ok(data) = hex::decode(cleaned_text) {
-                        let data_len = data.len();
-                        self.buffer.splice(
-                            usize::min(p_buf_index, s_buf_index)
-                                ..usize::max(p_buf_index, s_buf_index),
-                            data,
-                        );
-
-                        // Move cursor to right after what we just inserted (and reset selection)
-                        cursor_range.primary.index =
-                            cursor_range.primary.index.min(cursor_range.secondary.index)
-                                + data_len * 2;
-                        cursor_range.secondary.index = cursor_range.primary.index;
-                        state.cursor.set_char_range(Some(cursor_range));
-                    }
+            EventHandleResult::ForwardDelete => {
+                let range = if selection.is_empty() {
+                    selection.start..(selection.start + 1).min(len)
+                } else {
+                    selection
+                };
+                state.set_cursor(range.start);
+                self.splice(range, Vec::new(), false);
+            }
+            EventHandleResult::Copy | EventHandleResult::Cut => {
+                if selection.is_empty() {
+                    return;
+                }
+                let bytes = &self.buffer[selection.clone()];
+                ctx.copy_text(match state.column {
+                    Column::Hex => grid.layout.format.encode(bytes),
+                    Column::Ascii => String::from_utf8_lossy(bytes).into_owned(),
+                });
+                if result == EventHandleResult::Cut {
+                    state.set_cursor(selection.start);
+                    self.splice(selection, Vec::new(), false);
                 }
             }
-            EventHandleResult::Cut => {
-                if let Some(mut cursor_range) = state.cursor.char_range() {
-                    let pc_i = cursor_range.primary.index;
-                    let sc_i = cursor_range.secondary.index;
-                    let p_buf_index = view_index_to_buffer_index(pc_i);
-                    let s_buf_index = view_index_to_buffer_index(sc_i);
-
-                    ctx.copy_text(self.view[pc_i.min(sc_i)..pc_i.max(sc_i)].trim().into());
-                    self.buffer
-                        .drain(p_buf_index.min(s_buf_index)..p_buf_index.max(s_buf_index));
-
-                    // Move cursor to beginnning of what we just cut
-                    cursor_range.primary.index = pc_i.min(sc_i);
-                    cursor_range.secondary.index = cursor_range.primary.index;
-                    state.cursor.set_char_range(Some(cursor_range));
+            EventHandleResult::Paste(text) => {
+                let data = match (state.paste_format.take(), state.column) {
+                    (Some(format), _) => literal::parse(&text, format),
+                    // Bits are taken as such when bytes are shown as bits
+                    (None, Column::Hex) if grid.layout.format.binary => {
+                        inspect::write(Kind::Bits, &text, Endian::default())
+                            .or_else(|_| literal::parse_any(&text))
+                    }
+                    (None, Column::Hex) => literal::parse_any(&text),
+                    (None, Column::Ascii) => Ok(text.into_bytes()),
+                };
+                // Text that doesn't parse is left out rather than pasted mangled
+                if let Ok(data) = data {
+                    self.write(state, data, false);
                 }
             }
-            EventHandleResult::Text(text) => {
-                if let Some(mut cursor_range) = state.cursor.char_range() {
-                    let p_buf_index = view_index_to_buffer_index(cursor_range.primary.index);
-                    let s_buf_index = view_index_to_buffer_index(cursor_range.secondary.index);
-                    if let Some(partial_nibble_inner) = partial_nibble.0 {
-                        if let Ok(byte) = hex::decode(format!("{partial_nibble_inner}{text}")) {
-                            self.buffer.splice(
-                                usize::min(p_buf_index, s_buf_index)
-                                    ..usize::max(p_buf_index, s_buf_index),
-                                byte,
-                            );
-                            partial_nibble.0 = None;
-                            // Move cursor to right after what we just inserted (and reset selection)
-                            cursor_range.primary.index = usize::min(
-                                cursor_range.primary.index,
-                                cursor_range.secondary.index,
-                            ) + 2;
-                            cursor_range.secondary.index = cursor_range.primary.index;
-                            state.cursor.set_char_range(Some(cursor_range));
+            EventHandleResult::Text(text) => match state.column {
+                Column::Hex => {
+                    let format = grid.layout.format;
+                    for digit in text.chars().filter_map(|c| c.to_digit(format.radix())) {
+                        let (value, count) = state.pending.take().unwrap_or_default();
+                        let value = (u32::from(value) * format.radix() + digit) as u8;
+                        if count + 1 == format.digits() {
+                            self.write(state, vec![value], true);
+                        } else {
+                            state.pending = Some((value, count + 1));
                         }
-                    } else if Regex::new(r"^[0-9A-Fa-f]+$").unwrap().is_match(&text) {
-                        partial_nibble.0 = Some(text.chars().next().unwrap());
                     }
                 }
+                Column::Ascii => self.write(state, text.into_bytes(), true),
+            },
+            EventHandleResult::Undo | EventHandleResult::Redo => {
+                let cursor = match (&mut self.history, result) {
+                    (Some(history), EventHandleResult::Undo) => history.undo(self.buffer),
+                    (Some(history), _) => history.redo(self.buffer),
+                    (None, _) => None,
+                };
+                if let Some(cursor) = cursor {
+                    state.set_cursor(cursor);
+                }
             }
-            _ => {}
+            EventHandleResult::NoAction => {}
         }
     }
 
-    fn sync_view(&mut self) {
-        self.view = hex_encode_formatted(&self.buffer);
+    /// Puts `data` at the cursor and moves the cursor after it.
+    fn write(&mut self, state: &mut State, data: Vec<u8>, typed: bool) {
+        let range = self.replaced_range(state, data.len());
+        state.set_cursor(range.start + data.len());
+        self.splice(range, data, typed);
     }
-}
 
-impl Widget for HexEditor<'_> {
-    fn ui(mut self, ui: &mut egui::Ui) -> egui::Response {
-        let mut event_results = vec![];
+    fn splice(&mut self, range: Range<usize>, data: Vec<u8>, typed: bool) {
+        match &mut self.history {
+            Some(history) => history.splice(self.buffer, range, data, typed),
+            None => {
+                self.buffer.splice(range, data);
+            }
+        }
+    }
 
-        // Consume events pertaining to the Hex Editor's behavior and temporarily store them
-        let mut consumed_events = Vec::new();
-        ui.input_mut(|i| {
-            i.events.retain(|event| {
-                let (result, should_consume) = self.handle_event(&event, ui.ctx());
+    /// The bytes that `len` new bytes typed or pasted at the cursor replace: the selection, or in
+    /// overwrite mode as many bytes after the cursor if nothing is selected.
+    fn replaced_range(&self, state: &State, len: usize) -> Range<usize> {
+        let selection = state.selection();
+        if selection.is_empty() && state.overwrite {
+            selection.start..(selection.start + len).min(self.buffer.len())
+        } else {
+            selection
+        }
+    }
 
-                event_results.push(result);
+    /// Replaces everything with the contents of the file at `path`.
+    fn load(&mut self, state: &mut State, path: &Path) {
+        if let Some(data) = read_file(path) {
+            state.set_cursor(0);
+            state.pending = None;
+            self.splice(0..self.buffer.len(), data, false);
+        }
+    }
 
-                if should_consume {
-                    consumed_events.push(event.clone())
+    /// Copies, pastes or saves the selection, or everything if nothing is selected, in other
+    /// formats, or opens a file in place of everything.
+    fn context_menu_ui(&mut self, ui: &mut egui::Ui, state: &mut State) {
+        let selection = state.selection();
+        let bytes = if selection.is_empty() {
+            &self.buffer[..]
+        } else {
+            &self.buffer[selection]
+        };
+        copy_as_ui(ui, bytes);
+        if ui.button("💾 Save to File…").clicked() {
+            save_dialog(bytes, "data.bin");
+        }
+        if self.read_only {
+            return;
+        }
+        if ui.button("📂 Open File…").clicked() {
+            if let Some(path) = rfd::FileDialog::new().pick_file() {
+                self.load(state, &path);
+            }
+        }
+        ui.menu_button("📥 Paste from", |ui| {
+            for format in Format::ALL {
+                if ui.button(format.name()).clicked() {
+                    state.paste_format = Some(format);
+                    ui.ctx()
+                        .send_viewport_cmd(egui::ViewportCommand::RequestPaste);
                 }
-                !should_consume
-            });
+            }
         });
-        let mut textedit = TextEdit::multiline(&mut self.view);
+    }
 
-        if let Some(desired_width) = self.desired_width {
-            textedit = textedit.desired_width(desired_width);
-        }
+    fn paint_row(&self, ui: &egui::Ui, grid: &Grid, row: usize, state: &State, focused: bool) {
+        let font_id = egui::TextStyle::Monospace.resolve(ui.style());
+        let visuals = ui.visuals();
+        let selection = state.selection();
+        let selected = |i: usize| selection.contains(&i);
+        let highlight_color = visuals.warn_fg_color.gamma_multiply(0.35);
+        let selection_color = |column: Column| {
+            if focused && state.column == column {
+                visuals.selection.bg_fill
+            } else {
+                visuals.selection.bg_fill.linear_multiply(0.4)
+            }
+        };
+        let format = |color: Color32, background: Color32| TextFormat {
+            font_id: font_id.clone(),
+            color,
+            background,
+            ..Default::default()
+        };
 
-        let output = textedit.show(ui);
+        let start = row * grid.bytes_per_row;
+        let end = (start + grid.bytes_per_row).min(self.buffer.len());
+        let first_highlight = self.highlights.partition_point(|r| r.end <= start);
+        let highlighted = |i: usize| {
+            self.highlights[first_highlight..]
+                .iter()
+                .take_while(|r| r.start <= i)
+                .any(|r| r.contains(&i))
+        };
+        let group = grid.layout.format.group.max(1);
+        let mut hex = LayoutJob::default();
+        let mut ascii = LayoutJob::default();
+        for (i, &byte) in (start..end).zip(&self.buffer[start..end]) {
+            let (hex_background, ascii_background) = if selected(i) {
+                (selection_color(Column::Hex), selection_color(Column::Ascii))
+            } else if highlighted(i) {
+                (highlight_color, highlight_color)
+            } else {
+                (Color32::TRANSPARENT, Color32::TRANSPARENT)
+            };
+            if i != start && (i - start).is_multiple_of(group) {
+                let background =
+                    if selected(i - 1) == selected(i) && highlighted(i - 1) == highlighted(i) {
+                        hex_background
+                    } else {
+                        Color32::TRANSPARENT
+                    };
+                hex.append(" ", 0., format(visuals.text_color(), background));
+            }
+            append_byte(
+                &mut hex,
+                grid.layout.format,
+                byte,
+                i,
+                self.template,
+                format(visuals.text_color(), hex_background),
+            );
 
-        let mut state = output.state.clone();
-        let mut partial_nibble = PartialNibble(None);
+            let (c, color) = match byte {
+                0x20..=0x7e => (byte as char, visuals.text_color()),
+                _ => ('.', visuals.weak_text_color()),
+            };
+            ascii.append(&c.to_string(), 0., format(color, ascii_background));
+        }
 
-        ui.data(|r| {
-            partial_nibble = r.get_temp(output.response.id).unwrap_or_default();
-        });
+        let row_rect = grid.row_rect(row);
+        let painter = ui.painter();
+        if grid.offset_digits > 0 {
+            painter.text(
+                row_rect.min,
+                Align2::LEFT_TOP,
+                grid.layout.format_offset(start, grid.offset_digits),
+                font_id.clone(),
+                visuals.weak_text_color(),
+            );
+        }
+        let hex = ui.fonts(|f| f.layout_job(hex));
+        let ascii = ui.fonts(|f| f.layout_job(ascii));
+        painter.galley(
+            row_rect.min + vec2(grid.hex_left(), 0.),
+            hex,
+            visuals.text_color(),
+        );
+        painter.galley(
+            row_rect.min + vec2(grid.ascii_left(), 0.),
+            ascii,
+            visuals.text_color(),
+        );
 
-        if let Some(mut cursor_range) = state.cursor.char_range() {
-            let primary = &mut cursor_range.primary.index;
-            let secondary = &mut cursor_range.secondary.index;
-            *primary = if *primary != 0 {
-                *primary - *primary % 3 + 2
-            } else {
-                0
-            };
-            *secondary = if *secondary != 0 {
-                *secondary - *secondary % 3 + 2
-            } else {
-                0
+        if state.cursor / grid.bytes_per_row != row {
+            return;
+        }
+        let col = state.cursor % grid.bytes_per_row;
+        if focused {
+            let rect = grid.byte_rect(state.column, row, col);
+            painter.vline(
+                rect.left(),
+                rect.y_range(),
+                Stroke::new(2., visuals.text_cursor.stroke.color),
+            );
+            if let Some((value, count)) = state.pending {
+                let rect = Rect::from_min_size(
+                    rect.min,
+                    vec2(count as f32 * grid.char_width, grid.row_height),
+                );
+                let typed = match grid.layout.format {
+                    HexFormat { binary: true, .. } => format!("{value:0count$b}"),
+                    HexFormat {
+                        uppercase: true, ..
+                    } => format!("{value:X}"),
+                    _ => format!("{value:x}"),
+                };
+                painter.rect_filled(rect, 0., visuals.extreme_bg_color);
+                painter.text(
+                    rect.min,
+                    Align2::LEFT_TOP,
+                    typed,
+                    font_id.clone(),
+                    visuals.strong_text_color(),
+                );
+            }
+        }
+        // Show where the cursor is in the other column too
+        if state.cursor < self.buffer.len() {
+            let other = match state.column {
+                Column::Hex => Column::Ascii,
+                Column::Ascii => Column::Hex,
             };
+            painter.rect_stroke(
+                grid.byte_rect(other, row, col),
+                0.,
+                Stroke::new(1., visuals.weak_text_color()),
+                egui::StrokeKind::Inside,
+            );
+        }
+    }
+}
+
+impl Widget for HexEditor<'_> {
+    fn ui(mut self, ui: &mut egui::Ui) -> egui::Response {
+        let id = self
+            .id
+            .unwrap_or_else(|| ui.make_persistent_id("hex-editor"));
+        let mut state: State = ui.data(|r| r.get_temp(id)).unwrap_or_default();
+        let focused = ui.memory(|m| m.has_focus(id));
+        let font_id: FontId = egui::TextStyle::Monospace.resolve(ui.style());
+        let (char_width, row_height) =
+            ui.fonts(|f| (f.glyph_width(&font_id, '0'), f.row_height(&font_id)));
+        let layout = HexLayout::load(ui.ctx());
+
+        let mut frame = egui::Frame::canvas(ui.style());
+        if focused {
+            frame = frame.stroke(ui.visuals().selection.stroke);
+        }
+        let response = frame
+            .show(ui, |ui| {
+                ScrollArea::vertical()
+                    .id_salt(id)
+                    .auto_shrink(false)
+                    .show_viewport(ui, |ui, viewport| {
+                        let origin = ui.max_rect().min;
+                        let mut grid = Grid::new(
+                            origin,
+                            ui.available_width(),
+                            char_width,
+                            row_height,
+                            layout,
+                            self.buffer.len(),
+                        );
+                        let rows = grid.rows(self.buffer.len());
+                        let height = (rows as f32 * row_height)
+                            .min(MAX_SCROLL_HEIGHT)
+                            .max(viewport.height());
+                        let rect = Rect::from_min_size(origin, vec2(ui.available_width(), height));
+
+                        // Past MAX_SCROLL_HEIGHT the scroll offset is scaled to the rows, so that
+                        // the whole buffer is still scrolled through
+                        let visible_rows = viewport.height() / row_height;
+                        let scroll_rows = (rows as f64 - visible_rows as f64).max(0.);
+                        let scroll_height = (height - viewport.height()).max(0.) as f64;
+                        let top = if scroll_height > 0. {
+                            viewport.min.y as f64 / scroll_height * scroll_rows
+                        } else {
+                            0.
+                        };
+                        grid.first_row = top as usize;
+                        grid.origin.y =
+                            origin.y + viewport.min.y - (top.fract() as f32 * row_height);
+                        ui.allocate_rect(rect, Sense::hover());
+                        let response = ui.interact(rect, id, Sense::click_and_drag());
+                        state.cursor = state.cursor.min(self.buffer.len());
+                        state.anchor = state.anchor.min(self.buffer.len());
 
-            for result in &event_results {
-                match *result {
-                    EventHandleResult::CursorLeft(shift_pressed) => {
-                        if *primary >= 3 {
-                            *primary -= 3;
+                        // Clicking puts the cursor before a byte, dragging selects whole bytes
+                        if response.is_pointer_button_down_on()
+                            && ui.input(|i| i.pointer.primary_down())
+                        {
+                            if let Some(pos) = response.interact_pointer_pos() {
+                                let (byte, column) = grid.byte_at(pos, self.buffer.len());
+                                let origin = match state.drag_origin {
+                                    Some(origin) => origin,
+                                    None => {
+                                        response.request_focus();
+                                        if let Some(history) = &mut self.history {
+                                            history.typing = false;
+                                        }
+                                        state.column = column;
+                                        state.drag_origin = Some(byte);
+                                        byte
+                                    }
+                                };
+                                state.set_cursor(byte);
+                                if byte > origin {
+                                    state.anchor = origin;
+                                    state.cursor = (byte + 1).min(self.buffer.len());
+                                } else if byte < origin {
+                                    state.anchor = (origin + 1).min(self.buffer.len());
+                                }
+                            }
                         } else {
-                            *primary = 0;
+                            state.drag_origin = None;
                         }
-                        if !shift_pressed {
-                            *secondary = *primary;
+
+                        // Clicking a bit without dragging flips it
+                        if response.clicked() && !self.read_only {
+                            if let Some((byte, bit)) = response
+                                .interact_pointer_pos()
+                                .and_then(|pos| grid.bit_at(pos, self.buffer.len()))
+                            {
+                                let flipped = self.buffer[byte] ^ (0x80 >> bit);
+                                self.splice(byte..byte + 1, vec![flipped], false);
+                                state.set_cursor(byte);
+                            }
                         }
-                    }
-                    EventHandleResult::CursorRight(shift_pressed) => {
-                        if *primary == 0 {
-                            *primary = 2;
-                        } else if *primary + 3 <= self.view.len() {
-                            *primary += 3;
+                        let response = match (self.template, response.hover_pos()) {
+                            (Some(template), Some(pos)) => {
+                                let bits = match grid.bit_at(pos, self.buffer.len()) {
+                                    Some((byte, bit)) => byte * 8 + bit..byte * 8 + bit + 1,
+                                    None => {
+                                        let (byte, _) = grid.byte_at(pos, self.buffer.len());
+                                        byte * 8..byte * 8 + 8
+                                    }
+                                };
+                                match fields_text(template, self.buffer, bits) {
+                                    Some(text) => response.on_hover_text_at_pointer(text),
+                                    None => response,
+                                }
+                            }
+                            _ => response,
+                        };
+
+                        let focused = response.has_focus();
+                        if focused {
+                            ui.memory_mut(|m| {
+                                m.set_focus_lock_filter(
+                                    id,
+                                    EventFilter {
+                                        tab: true,
+                                        horizontal_arrows: true,
+                                        vertical_arrows: true,
+                                        escape: false,
+                                    },
+                                )
+                            });
                         }
-                        if !shift_pressed {
-                            *secondary = *primary;
+                        // The paste requested from the context menu may come after focus moved
+                        if focused || state.paste_format.is_some() {
+                            let events = ui.input(|i| i.events.clone());
+                            let rows_per_page = (viewport.height() / row_height) as usize;
+                            for event in &events {
+                                let result = self.handle_event(event);
+                                let pasted = matches!(result, EventHandleResult::Paste(_));
+                                if result != EventHandleResult::NoAction && (focused || pasted) {
+                                    state.scroll_to_cursor = true;
+                                    self.process_event_result(
+                                        result,
+                                        &mut state,
+                                        &grid,
+                                        rows_per_page,
+                                        ui.ctx(),
+                                    );
+                                }
+                            }
+                        }
+                        if std::mem::take(&mut state.scroll_to_cursor) {
+                            let row = (state.cursor / grid.bytes_per_row) as f64;
+                            let bottom = row + 1. - visible_rows as f64;
+                            let scroll_to = if row < top {
+                                Some(row)
+                            } else if bottom > top {
+                                Some(bottom)
+                            } else {
+                                None
+                            };
+                            if let Some(row) = scroll_to.filter(|_| scroll_rows > 0.) {
+                                // Scrolled by as much as needed, as scrolling to a rect leaves
+                                // some spacing around it that can be several rows here
+                                let offset = (row / scroll_rows * scroll_height) as f32;
+                                ui.scroll_with_delta(vec2(0., viewport.min.y - offset));
+                            }
                         }
-                    }
-                    _ => {}
-                }
-            }
-            state.cursor.set_char_range(Some(cursor_range));
-        }
 
-        for result in event_results {
-            self.process_event_result(
-                result,
-                output.response.has_focus(),
-                &mut partial_nibble,
-                &mut state,
-                ui.ctx(),
-            );
-        }
-        self.sync_view();
-        ui.data_mut(|w| w.insert_temp(output.response.id, partial_nibble));
-        state.store(ui.ctx(), output.response.id);
+                        let last = (grid.first_row + visible_rows.ceil() as usize + 1).min(rows);
+                        for row in grid.first_row..last {
+                            self.paint_row(ui, &grid, row, &state, response.has_focus());
+                        }
+                        response.context_menu(|ui| self.context_menu_ui(ui, &mut state));
 
-        // Restore temporarily consumed events if we don't have focus
-        if !output.response.has_focus() {
-            ui.input_mut(|w| w.events.append(&mut consumed_events));
-        }
+                        // Files dropped on the editor are loaded into it
+                        if !self.read_only && response.contains_pointer() {
+                            let (hovered, dropped) = ui.input(|i| {
+                                let dropped = i.raw.dropped_files.first();
+                                (
+                                    !i.raw.hovered_files.is_empty(),
+                                    dropped.and_then(|file| file.path.clone()),
+                                )
+                            });
+                            if let Some(path) = dropped {
+                                self.load(&mut state, &path);
+                            } else if hovered {
+                                let rect = ui.clip_rect();
+                                let visuals = ui.visuals();
+                                ui.painter().rect_filled(
+                                    rect,
+                                    0.,
+                                    visuals.selection.bg_fill.gamma_multiply(0.5),
+                                );
+                                ui.painter().text(
+                                    rect.center(),
+                                    Align2::CENTER_CENTER,
+                                    "Drop to Load",
+                                    egui::TextStyle::Heading.resolve(ui.style()),
+                                    visuals.strong_text_color(),
+                                );
+                            }
+                        }
+                        response
+                    })
+                    .inner
+            })
+            .inner;
 
-        output.response
+        if state.overwrite {
+            ui.painter().text(
+                ui.min_rect().right_top() + vec2(-4., 2.),
+                Align2::RIGHT_TOP,
+                "OVR",
+                egui::TextStyle::Small.resolve(ui.style()),
+                ui.visuals().weak_text_color(),
+            );
+        }
+        ui.data_mut(|w| w.insert_temp(id, state));
+        response
     }
 }
 
-#[derive(Clone, Default)]
-struct PartialNibble(Option<char>);
-
-fn view_index_to_buffer_index(view_cursor: usize) -> usize {
-    (view_cursor + 2) / 3
+#[derive(PartialEq, Eq, Clone, Copy)]
+enum Movement {
+    Left,
+    Right,
+    Up,
+    Down,
+    RowStart,
+    RowEnd,
+    PageUp,
+    PageDown,
+    Start,
+    End,
 }
 
 #[derive(PartialEq, Eq)]
@@ -280,8 +1235,162 @@ enum EventHandleResult {
     Text(String),
     Paste(String),
     Delete,
+    ForwardDelete,
+    Copy,
     Cut,
-    CursorLeft(bool),
-    CursorRight(bool),
+    /// Moves the cursor, extending the selection if shift is pressed
+    Cursor(Movement, bool),
+    SwitchColumn,
+    SelectAll,
+    ToggleOverwrite,
+    Undo,
+    Redo,
     NoAction,
 }
+
+impl EventHandleResult {
+    fn edits(&self) -> bool {
+        matches!(
+            self,
+            Self::Text(_)
+                | Self::Paste(_)
+                | Self::Delete
+                | Self::ForwardDelete
+                | Self::Cut
+                | Self::ToggleOverwrite
+                | Self::Undo
+                | Self::Redo
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Types `data` a byte at a time from `at`, inserting or overwriting like the editor does.
+    fn type_bytes(
+        history: &mut History,
+        buffer: &mut Vec<u8>,
+        at: usize,
+        data: &[u8],
+        overwrite: bool,
+    ) {
+        for (i, &byte) in data.iter().enumerate() {
+            let at = at + i;
+            let end = if overwrite {
+                (at + 1).min(buffer.len())
+            } else {
+                at
+            };
+            history.splice(buffer, at..end, vec![byte], true);
+        }
+    }
+
+    #[test]
+    fn typing_is_undone_at_once() {
+        let mut history = History::default();
+        let mut buffer = b"ab".to_vec();
+        type_bytes(&mut history, &mut buffer, 1, b"xyz", false);
+        assert_eq!(buffer, b"axyzb");
+
+        assert_eq!(history.undo(&mut buffer), Some(1));
+        assert_eq!(buffer, b"ab");
+        assert_eq!(history.undo(&mut buffer), None);
+
+        assert_eq!(history.redo(&mut buffer), Some(4));
+        assert_eq!(buffer, b"axyzb");
+        assert_eq!(history.redo(&mut buffer), None);
+    }
+
+    #[test]
+    fn overwriting_is_undone_at_once() {
+        let mut history = History::default();
+        let mut buffer = b"abcd".to_vec();
+        // Running past the end appends
+        type_bytes(&mut history, &mut buffer, 2, b"XYZ", true);
+        assert_eq!(buffer, b"abXYZ");
+        assert_eq!(history.undo(&mut buffer), Some(4));
+        assert_eq!(buffer, b"abcd");
+    }
+
+    #[test]
+    fn typing_elsewhere_or_other_edits_start_a_new_step() {
+        let mut history = History::default();
+        let mut buffer = b"abcd".to_vec();
+        type_bytes(&mut history, &mut buffer, 0, b"1", false);
+        type_bytes(&mut history, &mut buffer, 3, b"2", false);
+        // A paste isn't typing, and typing after it isn't part of it
+        history.splice(&mut buffer, 0..0, b"PP".to_vec(), false);
+        type_bytes(&mut history, &mut buffer, 2, b"3", false);
+        assert_eq!(buffer, b"PP31ab2cd");
+
+        let mut undone = Vec::new();
+        while history.undo(&mut buffer).is_some() {
+            undone.push(String::from_utf8(buffer.clone()).unwrap());
+        }
+        assert_eq!(undone, ["PP1ab2cd", "1ab2cd", "1abcd", "abcd"]);
+    }
+
+    #[test]
+    fn typing_after_an_undo_starts_a_new_step() {
+        let mut history = History::default();
+        let mut buffer = Vec::new();
+        type_bytes(&mut history, &mut buffer, 0, b"ab", false);
+        type_bytes(&mut history, &mut buffer, 2, b"cd", false);
+        history.undo(&mut buffer);
+        type_bytes(&mut history, &mut buffer, 0, b"x", false);
+        assert_eq!(buffer, b"x");
+        history.undo(&mut buffer);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn new_edits_clear_what_can_be_redone() {
+        let mut history = History::default();
+        let mut buffer = b"abc".to_vec();
+        history.splice(&mut buffer, 0..1, Vec::new(), false);
+        history.undo(&mut buffer);
+        assert_eq!(buffer, b"abc");
+
+        history.splice(&mut buffer, 3..3, b"d".to_vec(), false);
+        assert_eq!(history.redo(&mut buffer), None);
+        assert_eq!(buffer, b"abcd");
+    }
+
+    #[test]
+    fn revisions_change_with_every_edit() {
+        let mut history = History::default();
+        let mut buffer = b"abc".to_vec();
+        let mut revisions = vec![history.revision()];
+        history.splice(&mut buffer, 0..1, b"x".to_vec(), false);
+        revisions.push(history.revision());
+        // Doing nothing isn't an edit
+        history.splice(&mut buffer, 1..1, Vec::new(), false);
+        assert_eq!(history.revision(), revisions[1]);
+        history.undo(&mut buffer);
+        revisions.push(history.revision());
+        history.redo(&mut buffer);
+        revisions.push(history.revision());
+
+        revisions.sort_unstable();
+        revisions.dedup();
+        assert_eq!(revisions.len(), 4);
+        assert_ne!(history.revision(), History::default().revision());
+    }
+
+    #[test]
+    fn only_the_latest_edits_are_kept() {
+        let mut history = History::default();
+        let mut buffer = Vec::new();
+        for _ in 0..HISTORY_LIMIT + 5 {
+            history.splice(&mut buffer, 0..0, vec![0], false);
+        }
+        let mut undone = 0;
+        while history.undo(&mut buffer).is_some() {
+            undone += 1;
+        }
+        assert_eq!(undone, HISTORY_LIMIT);
+        assert_eq!(buffer.len(), 5);
+    }
+}
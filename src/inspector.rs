@@ -0,0 +1,297 @@
+//! The data inspector: the bytes selected in a hex editor read as numbers, times and text, and the
+//! fields of a template read from the start of the bytes.
+
+use std::path::PathBuf;
+
+use eframe::egui::{self, Button, Grid, Id, Key, RichText, TextEdit};
+use palm_core::{
+    backend::{Log, LogData},
+    inspect::{self, Endian, Kind},
+    template::Template,
+};
+
+use crate::{
+    gui::packet_summary,
+    hexedit::{self, HexEditor, History},
+};
+
+/// How many bytes from the cursor on are inspected when nothing is selected.
+const UNSELECTED_LEN: usize = 16;
+
+pub struct Inspector {
+    pub open: bool,
+    id: String,
+    endian: Endian,
+    /// A packet inspected instead of the draft
+    packet: Option<InspectedPacket>,
+    /// The value being edited and its text so far
+    editing: Option<(Kind, String)>,
+    error: Option<String>,
+    template: Option<Template>,
+    template_path: Option<PathBuf>,
+    template_error: Option<String>,
+}
+
+struct InspectedPacket {
+    summary: String,
+    data: Vec<u8>,
+}
+
+impl Inspector {
+    pub fn new(id: impl Into<String>) -> Self {
+        Self {
+            open: false,
+            id: id.into(),
+            endian: Endian::default(),
+            packet: None,
+            editing: None,
+            error: None,
+            template: None,
+            template_path: None,
+            template_error: None,
+        }
+    }
+
+    /// The template whose fields are read, which editors and logs name bits by too.
+    pub fn template(&self) -> Option<&Template> {
+        self.template.as_ref()
+    }
+
+    pub fn template_path(&self) -> Option<&PathBuf> {
+        self.template_path.as_ref()
+    }
+
+    pub fn load_template(&mut self, path: PathBuf) {
+        match Template::load(&path) {
+            Ok(template) => {
+                self.template = Some(template);
+                self.template_error = None;
+            }
+            Err(error) => {
+                self.template = None;
+                self.template_error = Some(format!("{error:#}"));
+            }
+        }
+        self.template_path = Some(path);
+    }
+
+    /// Opens the inspector on a packet log, with its bytes shown in a read-only editor.
+    pub fn inspect(&mut self, log: &Log) {
+        let (LogData::SentPacket(packet) | LogData::ReceivedPacket(packet)) = &log.data else {
+            return;
+        };
+        self.packet = Some(InspectedPacket {
+            summary: packet_summary(log),
+            data: packet.data.clone(),
+        });
+        self.open = true;
+        self.editing = None;
+        self.error = None;
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn is_inspecting_packet(&self) -> bool {
+        self.packet.is_some()
+    }
+
+    /// Shows the values of the bytes selected in the inspected packet, or in the editor with
+    /// `editor` id if there is none. Editing a value rewrites the editor's bytes.
+    pub fn ui(
+        &mut self,
+        ui: &mut egui::Ui,
+        editor: Id,
+        draft: Option<(&mut Vec<u8>, &mut History)>,
+    ) {
+        ui.horizontal(|ui| {
+            ui.strong("Inspector");
+            ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                ui.selectable_value(&mut self.endian, Endian::Big, "BE")
+                    .on_hover_text("Big Endian");
+                ui.selectable_value(&mut self.endian, Endian::Little, "LE")
+                    .on_hover_text("Little Endian");
+            });
+        });
+        ui.add_space(4.);
+
+        if let Some(mut packet) = self.packet.take() {
+            let mut close = false;
+            ui.horizontal(|ui| {
+                close = ui
+                    .small_button("✕")
+                    .on_hover_text("Stop Inspecting")
+                    .clicked();
+                ui.weak(&packet.summary);
+            });
+            let editor = Id::new(("inspected-packet", &self.id));
+            ui.add_sized(
+                (ui.available_width(), 160.),
+                HexEditor::new(&mut packet.data)
+                    .id(editor)
+                    .read_only()
+                    .template(self.template.as_ref()),
+            );
+            ui.add_space(4.);
+
+            let selection = hexedit::selection(ui.ctx(), editor);
+            let bytes = inspected(&packet.data, selection.start, selection.end);
+            self.values_ui(ui, bytes, false);
+            self.template_ui(ui, &packet.data);
+            if !close {
+                self.packet = Some(packet);
+            }
+            return;
+        }
+
+        let Some((buffer, history)) = draft else {
+            ui.weak("Focus a connection to inspect its draft");
+            return;
+        };
+        let selection = hexedit::selection(ui.ctx(), editor);
+        let start = selection.start.min(buffer.len());
+        let bytes = inspected(buffer, start, selection.end);
+        if let Some((len, data)) = self.values_ui(ui, bytes, true) {
+            let end = start + data.len();
+            history.splice(buffer, start..start + len, data, false);
+            hexedit::select(ui.ctx(), editor, start..end);
+        }
+        self.template_ui(ui, buffer);
+    }
+
+    /// Lets a template be loaded and shows the values of its fields in `data`.
+    fn template_ui(&mut self, ui: &mut egui::Ui, data: &[u8]) {
+        ui.separator();
+        ui.horizontal(|ui| {
+            ui.strong("Template");
+            if ui.button("Load…").clicked() {
+                if let Some(path) = rfd::FileDialog::new()
+                    .add_filter("Template", &["toml"])
+                    .pick_file()
+                {
+                    self.load_template(path);
+                }
+            }
+            if let Some(path) = self.template_path.clone() {
+                if ui.button("Reload").clicked() {
+                    self.load_template(path);
+                }
+                if ui.button("✕").on_hover_text("Unload Template").clicked() {
+                    self.template = None;
+                    self.template_path = None;
+                    self.template_error = None;
+                }
+            }
+        });
+        if let Some(error) = &self.template_error {
+            ui.colored_label(ui.visuals().error_fg_color, error);
+        }
+        let Some(template) = &self.template else {
+            ui.weak("Load a template to name the bits of packets");
+            return;
+        };
+
+        ui.label(&template.name);
+        Grid::new(("inspector-template", &self.id))
+            .num_columns(3)
+            .striped(true)
+            .show(ui, |ui| {
+                for (i, field) in template.fields.iter().enumerate() {
+                    ui.label(RichText::new(&field.name).color(hexedit::field_color(i)));
+                    ui.weak(format!("{}..{}", field.bits.start, field.bits.end))
+                        .on_hover_text("Bits, from the most significant bit of the first byte");
+                    match field.value(data) {
+                        Some(value) => ui.monospace(value.to_string()),
+                        None => ui.weak("-"),
+                    };
+                    ui.end_row();
+                }
+            });
+    }
+
+    /// Shows what `bytes` read as, returning how many of them an edited value replaces and its
+    /// new bytes.
+    fn values_ui(
+        &mut self,
+        ui: &mut egui::Ui,
+        bytes: &[u8],
+        editable: bool,
+    ) -> Option<(usize, Vec<u8>)> {
+        let mut edit = None;
+        Grid::new(("inspector", &self.id))
+            .num_columns(2)
+            .striped(true)
+            .show(ui, |ui| {
+                for kind in Kind::ALL {
+                    ui.label(kind.name());
+                    let value = inspect::read(kind, bytes, self.endian);
+                    let len = value.as_ref().map(|(_, len)| *len);
+                    let text = value.map_or("-".to_string(), |(text, _)| text);
+
+                    if !editable {
+                        ui.add(
+                            TextEdit::singleline(&mut text.as_str()).desired_width(f32::INFINITY),
+                        );
+                        ui.end_row();
+                        continue;
+                    }
+
+                    let mut text = match &self.editing {
+                        Some((editing, edited)) if *editing == kind => edited.clone(),
+                        _ => text,
+                    };
+                    let response = ui.add(
+                        TextEdit::singleline(&mut text)
+                            .id(Id::new(("inspector-value", &self.id, kind)))
+                            .desired_width(f32::INFINITY),
+                    );
+                    if response.has_focus() {
+                        self.editing = Some((kind, text.clone()));
+                    }
+                    if response.lost_focus() {
+                        if ui.input(|i| i.key_pressed(Key::Enter)) {
+                            match inspect::write(kind, &text, self.endian) {
+                                Ok(data) => {
+                                    self.error = None;
+                                    // Values that didn't fit replace as many bytes as there are
+                                    let len = len.unwrap_or(data.len().min(bytes.len()));
+                                    edit = Some((len, data));
+                                }
+                                Err(error) => self.error = Some(format!("{error:#}")),
+                            }
+                        }
+                        self.editing = None;
+                    }
+                    ui.end_row();
+                }
+            });
+
+        if let Some(error) = &self.error {
+            let dismissed = ui
+                .horizontal(|ui| {
+                    let dismissed = ui.add(Button::new("✕").small()).clicked();
+                    ui.colored_label(ui.visuals().error_fg_color, error);
+                    dismissed
+                })
+                .inner;
+            if dismissed {
+                self.error = None;
+            }
+        } else if editable {
+            ui.weak("Edit a value and press Enter to rewrite its bytes");
+        }
+        edit
+    }
+}
+
+/// The selected bytes, or some from the cursor on if nothing is selected.
+fn inspected(data: &[u8], start: usize, end: usize) -> &[u8] {
+    let start = start.min(data.len());
+    let end = if end > start {
+        end
+    } else {
+        start + UNSELECTED_LEN
+    };
+    &data[start..end.min(data.len())]
+}
@@ -2,20 +2,48 @@
 
 use std::sync::Arc;
 
+use clap::Parser;
 use eframe::egui::{CentralPanel, Context};
 use eframe::{egui, Frame};
 use egui_tiles::TileId;
+use palm_core::{
+    backend::NetState,
+    capture, logfile,
+    session::{self, Session},
+};
 use simplelog::*;
 use tokio::runtime::Runtime;
 
-use crate::gui::{Pane, Tab, TreeBehavior};
+use crate::cli::Cli;
+use crate::gui::{error_dialog, CaptureView, DiffView, LogView, Pane, Tab, TreeBehavior};
+use crate::workspace::Workspace;
 
-pub mod backend;
+pub mod cli;
+pub mod filter;
+pub mod find;
 pub mod gui;
 pub mod hexedit;
-pub mod util;
+pub mod inspector;
+pub mod script;
+pub mod workspace;
+
+const LOG_EXTENSIONS: &[&str] = &["jsonl", "csv"];
+const CAPTURE_EXTENSIONS: &[&str] = &["pcapng", "pcap", "cap"];
 
 fn main() {
+    let cli = Cli::parse();
+    if let Some(command) = cli.command {
+        // Keep stdout for the traffic itself
+        TermLogger::init(
+            LevelFilter::Warn,
+            Config::default(),
+            TerminalMode::Stderr,
+            ColorChoice::Auto,
+        )
+        .expect("Failed to initialize logger");
+        std::process::exit(cli::run(command));
+    }
+
     TermLogger::init(
         LevelFilter::Info,
         Config::default(),
@@ -32,7 +60,7 @@ fn main() {
     eframe::run_native(
         "Palm",
         options,
-        Box::new(|cc| Ok(Box::<Palm>::new(Palm::new()))),
+        Box::new(|cc| Ok(Box::<Palm>::new(Palm::new(cc)))),
     )
     .unwrap();
 }
@@ -46,42 +74,199 @@ struct Palm {
 }
 
 impl Palm {
-    pub fn new() -> Self {
+    /// Restores the workspace Palm was closed with, if any.
+    pub fn new(cc: &eframe::CreationContext) -> Self {
         let rt = Arc::new(Runtime::new().expect("Failed to create tokio runtime"));
 
-        Self {
+        let mut palm = Self {
             rt: rt.clone(),
 
             behavior: TreeBehavior::default(),
             tree: egui_tiles::Tree::new_tabs("root", vec![Pane::Tab(Tab::new(1, rt.clone()))]),
             next_tab_id: 2,
+        };
+        if let Some(workspace) = cc
+            .storage
+            .and_then(|storage| eframe::get_value::<Workspace>(storage, workspace::STORAGE_KEY))
+        {
+            palm.restore_workspace(workspace);
+        }
+        palm
+    }
+
+    /// Replaces all tabs with those of `workspace`, unless it has none.
+    fn restore_workspace(&mut self, workspace: Workspace) {
+        let (tree, next_tab_id) = workspace.restore(&self.rt);
+        if !tree.is_empty() {
+            self.tree = tree;
+            self.next_tab_id = next_tab_id;
+        }
+    }
+
+    pub fn save_workspace(&self) {
+        let Some(path) = rfd::FileDialog::new()
+            .add_filter("Palm Workspace", &[workspace::EXTENSION])
+            .set_file_name(format!("workspace.{}", workspace::EXTENSION))
+            .save_file()
+        else {
+            return;
+        };
+        if let Err(error) = Workspace::new(&self.tree, self.next_tab_id, false).save(&path) {
+            error_dialog("Saving Workspace Failed", format!("{error:#}"));
+        }
+    }
+
+    pub fn open_workspace(&mut self) {
+        let active = self.tree.tiles.tiles().any(|tile| {
+            matches!(tile, egui_tiles::Tile::Pane(Pane::Tab(tab)) if tab.net_state() != NetState::Inactive)
+        });
+        if active {
+            error_dialog(
+                "Failed to Open Workspace",
+                "Disconnect all clients and stop all servers first".to_string(),
+            );
+            return;
+        }
+
+        let Some(path) = rfd::FileDialog::new()
+            .add_filter("Palm Workspace", &[workspace::EXTENSION])
+            .pick_file()
+        else {
+            return;
+        };
+        match Workspace::load(&path) {
+            Ok(workspace) => self.restore_workspace(workspace),
+            Err(error) => error_dialog(
+                "Failed to Open Workspace",
+                format!("{}: {error:#}", path.display()),
+            ),
         }
     }
 
-    pub fn spawn_tab(&mut self, parent: TileId) {
-        let tile_id = self
-            .tree
-            .tiles
-            .insert_pane(Pane::Tab(Tab::new(self.next_tab_id, self.rt.clone())));
+    /// Adds a pane to the tabs container `parent` and makes it the active tab.
+    fn add_pane(&mut self, parent: TileId, pane: Pane) -> TileId {
+        let tile_id = self.tree.tiles.insert_pane(pane);
 
         if let Some(egui_tiles::Tile::Container(egui_tiles::Container::Tabs(tabs))) =
             self.tree.tiles.get_mut(parent)
         {
             tabs.add_child(tile_id);
+            tabs.set_active(tile_id);
         }
 
         self.next_tab_id += 1;
+        tile_id
+    }
+
+    pub fn spawn_tab(&mut self, parent: TileId) -> &mut Tab {
+        let tab = Tab::new(self.next_tab_id, self.rt.clone());
+        let tile_id = self.add_pane(parent, Pane::Tab(tab));
+        match self.tree.tiles.get_mut(tile_id) {
+            Some(egui_tiles::Tile::Pane(Pane::Tab(tab))) => tab,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Asks for an exported log, a session or a packet capture and opens it in a read-only tab.
+    pub fn open_file(&mut self, parent: TileId) {
+        let Some(path) = rfd::FileDialog::new()
+            .add_filter(
+                "Log, Session or Capture",
+                &[LOG_EXTENSIONS, &[session::EXTENSION], CAPTURE_EXTENSIONS].concat(),
+            )
+            .add_filter("Log", LOG_EXTENSIONS)
+            .add_filter("Palm Session", &[session::EXTENSION])
+            .add_filter("Packet Capture", CAPTURE_EXTENSIONS)
+            .pick_file()
+        else {
+            return;
+        };
+
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let extension = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+            .unwrap_or_default();
+
+        let error = if extension == session::EXTENSION {
+            match Session::load(&path) {
+                Ok(session) if session.connections.is_empty() => {
+                    "The session has no recorded connections".to_string()
+                }
+                Ok(session) => {
+                    let view =
+                        CaptureView::playing(self.next_tab_id, name, session.flows(), session.side);
+                    self.add_pane(parent, Pane::Capture(view));
+                    return;
+                }
+                Err(e) => format!("{e:#}"),
+            }
+        } else if LOG_EXTENSIONS.contains(&extension.as_str()) {
+            match logfile::read_file(&path) {
+                Ok(logs) => {
+                    let view = LogView::new(self.next_tab_id, name, logs);
+                    self.add_pane(parent, Pane::Logs(view));
+                    return;
+                }
+                Err(e) => format!("{e:#}"),
+            }
+        } else {
+            match capture::read(&path) {
+                Ok(flows) if flows.is_empty() => "No TCP or UDP traffic found".to_string(),
+                Ok(flows) => {
+                    let view = CaptureView::new(self.next_tab_id, name, flows);
+                    self.add_pane(parent, Pane::Capture(view));
+                    return;
+                }
+                Err(e) => format!("{e:#}"),
+            }
+        };
+
+        error_dialog(
+            "Failed to Open File",
+            format!("{}: {error}", path.display()),
+        );
     }
 }
 
 impl eframe::App for Palm {
-    fn update(&mut self, ctx: &Context, frame: &mut Frame) {
+    fn update(&mut self, ctx: &Context, _frame: &mut Frame) {
         if let Some(tile_id) = self.behavior.spawn_tab_into.take() {
             self.spawn_tab(tile_id);
         }
+        if let Some(tile_id) = self.behavior.open_file_into.take() {
+            self.open_file(tile_id);
+        }
+        if std::mem::take(&mut self.behavior.save_workspace) {
+            self.save_workspace();
+        }
+        if std::mem::take(&mut self.behavior.open_workspace) {
+            self.open_workspace();
+        }
+        if let Some((tile_id, scenario)) = self.behavior.replay.take() {
+            if let Some(parent) = self.tree.tiles.parent_of(tile_id) {
+                let tab = self.spawn_tab(parent);
+                tab.scenario.set(scenario);
+                tab.scenario.open = true;
+            }
+        }
+        if let Some((tile_id, left, right)) = self.behavior.compare.take() {
+            if let Some(parent) = self.tree.tiles.parent_of(tile_id) {
+                let view = DiffView::new(self.next_tab_id, left, right);
+                self.add_pane(parent, Pane::Diff(view));
+            }
+        }
 
         CentralPanel::default().show(ctx, |ui| {
             self.tree.ui(&mut self.behavior, ui);
         });
     }
+
+    fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        let workspace = Workspace::new(&self.tree, self.next_tab_id, true);
+        eframe::set_value(storage, workspace::STORAGE_KEY, &workspace);
+    }
 }
@@ -0,0 +1,570 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use regex::bytes::Regex;
+use rhai::{
+    Blob, CallFnOptions, Dynamic, Engine, EvalAltResult, ImmutableString, Position, Scope, AST, INT,
+};
+use tokio::{
+    runtime::Handle,
+    sync::broadcast::{self, error::RecvError},
+    task::JoinHandle,
+    time::{timeout_at, Instant},
+};
+
+use palm_core::{
+    backend::{ConnectionHandle, Log, LogData, ServerHandle},
+    util::{hex_encode_formatted, parse_hex},
+};
+
+/// How long blocking script functions wait at most before checking whether the script was stopped.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+const DEFAULT_EXPECT_TIMEOUT_MS: INT = 5000;
+/// What timeouts too long to be represented as an [`Instant`] are cut to, about 30 years.
+const FAR_FUTURE: Duration = Duration::from_secs(86400 * 365 * 30);
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+/// A script running on the tokio runtime.
+pub struct ScriptTask {
+    stop: Arc<AtomicBool>,
+    handle: JoinHandle<()>,
+}
+
+impl ScriptTask {
+    /// Stops the script the next time it executes an operation or a blocking function polls.
+    pub fn stop(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_running(&self) -> bool {
+        !self.handle.is_finished()
+    }
+}
+
+/// Runs `source` against a client connection.
+///
+/// Available functions: `send(data)`, `recv(timeout_ms)`, `expect(pattern[, timeout_ms])`,
+/// `sleep(ms)`, `log(message)` and `close()`.
+pub fn run_client(source: String, conn: ConnectionHandle, rt: &Handle) -> ScriptTask {
+    let stop = Arc::new(AtomicBool::new(false));
+    let inbox = Arc::new(Mutex::new(Inbox::new(conn.subscribe())));
+    let log_conn = conn.clone();
+
+    spawn(
+        source,
+        stop.clone(),
+        rt,
+        move |log| {
+            let _ = log_conn.log(log);
+        },
+        move |engine, rt| {
+            let c = conn.clone();
+            engine.register_fn("send", move |data: Blob| -> ScriptResult<()> {
+                c.send_data(data).map_err(script_error)
+            });
+            let c = conn.clone();
+            engine.register_fn("send", move |data: ImmutableString| -> ScriptResult<()> {
+                c.send_data(data.as_bytes().to_vec()).map_err(script_error)
+            });
+            let c = conn.clone();
+            engine.register_fn("close", move || c.shutdown());
+
+            let (i, r, s) = (inbox.clone(), rt.clone(), stop.clone());
+            engine.register_fn("recv", move |timeout_ms: INT| -> ScriptResult<Dynamic> {
+                let mut inbox = i.lock().unwrap();
+                let deadline = deadline(timeout_ms);
+                while inbox.buffer.is_empty() {
+                    if !inbox.fill(&r, &s, deadline)? {
+                        return Ok(Dynamic::UNIT);
+                    }
+                }
+                Ok(Dynamic::from_blob(std::mem::take(&mut inbox.buffer)))
+            });
+
+            let (i, r, s) = (inbox.clone(), rt.clone(), stop.clone());
+            let expect = move |pattern: Pattern, timeout_ms: INT| -> ScriptResult<Blob> {
+                let mut inbox = i.lock().unwrap();
+                let deadline = deadline(timeout_ms);
+                loop {
+                    if let Some(end) = pattern.find_end(&inbox.buffer) {
+                        return Ok(inbox.buffer.drain(..end).collect());
+                    }
+                    if !inbox.fill(&r, &s, deadline)? {
+                        return Err(format!(
+                            "Expected {} within {timeout_ms}ms, received: {}",
+                            pattern,
+                            hex_encode_formatted(&inbox.buffer)
+                        )
+                        .into());
+                    }
+                }
+            };
+            let e = expect.clone();
+            engine.register_fn("expect", move |p: ImmutableString, t: INT| {
+                e(Pattern::regex(&p)?, t)
+            });
+            let e = expect.clone();
+            engine.register_fn("expect", move |p: ImmutableString| {
+                e(Pattern::regex(&p)?, DEFAULT_EXPECT_TIMEOUT_MS)
+            });
+            let e = expect.clone();
+            engine.register_fn("expect", move |p: Blob, t: INT| e(Pattern::Bytes(p), t));
+            engine.register_fn("expect", move |p: Blob| {
+                expect(Pattern::Bytes(p), DEFAULT_EXPECT_TIMEOUT_MS)
+            });
+        },
+        |_, _, _, _| Ok(()),
+    )
+}
+
+/// Runs `source` against a server. After the script's top level statements are run,
+/// its `on_connect(address)`, `on_data(address, data)` and `on_disconnect(address)` functions
+/// are called as the corresponding events happen, until the server stops.
+///
+/// Available functions: `send(address, data)`, `broadcast(data)`, `close(address)`,
+/// `sleep(ms)` and `log(message)`.
+pub fn run_server(source: String, server: ServerHandle, rt: &Handle) -> ScriptTask {
+    let stop = Arc::new(AtomicBool::new(false));
+    let mut events = server.subscribe();
+    let log_server = server.clone();
+
+    spawn(
+        source,
+        stop.clone(),
+        rt,
+        move |log| {
+            let _ = log_server.log(log);
+        },
+        move |engine, _| {
+            let s = server.clone();
+            engine.register_fn(
+                "send",
+                move |address: ImmutableString, data: Blob| -> ScriptResult<()> {
+                    connection(&s, &address)?
+                        .send_data(data)
+                        .map_err(script_error)
+                },
+            );
+            let s = server.clone();
+            engine.register_fn(
+                "send",
+                move |address: ImmutableString, data: ImmutableString| -> ScriptResult<()> {
+                    connection(&s, &address)?
+                        .send_data(data.as_bytes().to_vec())
+                        .map_err(script_error)
+                },
+            );
+            let s = server.clone();
+            engine.register_fn("broadcast", move |data: Blob| -> ScriptResult<()> {
+                s.broadcast(data).map_err(script_error)
+            });
+            let s = server.clone();
+            engine.register_fn(
+                "broadcast",
+                move |data: ImmutableString| -> ScriptResult<()> {
+                    s.broadcast(data.as_bytes().to_vec()).map_err(script_error)
+                },
+            );
+            let s = server.clone();
+            engine.register_fn(
+                "close",
+                move |address: ImmutableString| -> ScriptResult<()> {
+                    connection(&s, &address)?.shutdown();
+                    Ok(())
+                },
+            );
+        },
+        move |engine, scope, ast, (rt, stop)| {
+            let has_fn = |name: &str| ast.iter_functions().any(|f| f.name == name);
+
+            loop {
+                if stop.load(Ordering::Relaxed) {
+                    return Err(terminated());
+                }
+
+                let log =
+                    match rt.block_on(timeout_at(Instant::now() + POLL_INTERVAL, events.recv())) {
+                        Ok(Ok(log)) => log,
+                        Ok(Err(RecvError::Lagged(missed))) => return Err(missed_events(missed)),
+                        Err(_) => continue,
+                        Ok(Err(RecvError::Closed)) => return Ok(()),
+                    };
+
+                // The top level statements were already run, so only call the function itself
+                let options = CallFnOptions::new().eval_ast(false);
+                match log.data {
+                    LogData::ClientConnect(address) if has_fn("on_connect") => {
+                        let _ = engine.call_fn_with_options::<Dynamic>(
+                            options,
+                            scope,
+                            ast,
+                            "on_connect",
+                            (address,),
+                        )?;
+                    }
+                    LogData::ReceivedPacket(packet) if has_fn("on_data") => {
+                        let _ = engine.call_fn_with_options::<Dynamic>(
+                            options,
+                            scope,
+                            ast,
+                            "on_data",
+                            (packet.address, packet.data),
+                        )?;
+                    }
+                    LogData::ClientDisconnect(address) if has_fn("on_disconnect") => {
+                        let _ = engine.call_fn_with_options::<Dynamic>(
+                            options,
+                            scope,
+                            ast,
+                            "on_disconnect",
+                            (address,),
+                        )?;
+                    }
+                    LogData::ServerStopped => return Ok(()),
+                    _ => {}
+                }
+            }
+        },
+    )
+}
+
+/// Compiles and runs a script in a blocking task. `register` adds the target specific functions
+/// to the engine and `after` is run once the script's top level statements have finished.
+fn spawn(
+    source: String,
+    stop: Arc<AtomicBool>,
+    rt: &Handle,
+    log: impl Fn(Log) + Send + Sync + Clone + 'static,
+    register: impl FnOnce(&mut Engine, &Handle) + Send + 'static,
+    after: impl FnOnce(&Engine, &mut Scope, &AST, (&Handle, &AtomicBool)) -> ScriptResult<()>
+        + Send
+        + 'static,
+) -> ScriptTask {
+    let task_stop = stop.clone();
+    let task_rt = rt.clone();
+    let handle = rt.spawn_blocking(move || {
+        let stop = task_stop;
+        let rt = task_rt;
+        let mut engine = Engine::new();
+
+        let l = log.clone();
+        engine.on_print(move |s| l(Log::script(s.to_string())));
+        let l = log.clone();
+        engine.on_debug(move |s, _, _| l(Log::script(s.to_string())));
+        let l = log.clone();
+        engine.register_fn("log", move |message: Dynamic| {
+            l(Log::script(message.to_string()))
+        });
+
+        let s = stop.clone();
+        engine.on_progress(move |_| s.load(Ordering::Relaxed).then_some(Dynamic::UNIT));
+
+        let (r, s) = (rt.clone(), stop.clone());
+        engine.register_fn("sleep", move |ms: INT| -> ScriptResult<()> {
+            let deadline = deadline(ms);
+            while Instant::now() < deadline {
+                if s.load(Ordering::Relaxed) {
+                    return Err(terminated());
+                }
+                r.block_on(tokio::time::sleep_until(
+                    deadline.min(Instant::now() + POLL_INTERVAL),
+                ));
+            }
+            Ok(())
+        });
+        engine.register_fn("hex", |data: Blob| hex_encode_formatted(data));
+        engine.register_fn("unhex", |text: ImmutableString| -> ScriptResult<Blob> {
+            parse_hex(&text).map_err(script_error)
+        });
+
+        register(&mut engine, &rt);
+
+        let result = engine.compile(&source).map_err(Into::into).and_then(|ast| {
+            let mut scope = Scope::new();
+            engine.run_ast_with_scope(&mut scope, &ast)?;
+            after(&engine, &mut scope, &ast, (&rt, &stop))
+        });
+
+        match result {
+            Ok(()) => log(Log::script("Script finished".into())),
+            Err(error) if matches!(*error, EvalAltResult::ErrorTerminated(..)) => {
+                log(Log::script("Script stopped".into()))
+            }
+            Err(error) => log(Log::script_error(error.to_string())),
+        }
+    });
+
+    ScriptTask { stop, handle }
+}
+
+fn connection(server: &ServerHandle, address: &str) -> ScriptResult<ConnectionHandle> {
+    server
+        .connection(address)
+        .ok_or_else(|| format!("No connection with address {address}").into())
+}
+
+fn script_error(error: impl std::fmt::Display) -> Box<EvalAltResult> {
+    error.to_string().into()
+}
+
+fn terminated() -> Box<EvalAltResult> {
+    EvalAltResult::ErrorTerminated(Dynamic::UNIT, Position::NONE).into()
+}
+
+fn missed_events(missed: u64) -> Box<EvalAltResult> {
+    format!("Missed {missed} events").into()
+}
+
+/// When a timeout of `timeout_ms` from now ends, at the latest in a far future that's never
+/// reached in practice.
+fn deadline(timeout_ms: INT) -> Instant {
+    let now = Instant::now();
+    now.checked_add(Duration::from_millis(timeout_ms.max(0) as u64))
+        .unwrap_or_else(|| now + FAR_FUTURE)
+}
+
+/// Data received by a client script that has not been consumed yet.
+struct Inbox {
+    events: broadcast::Receiver<Log>,
+    buffer: Vec<u8>,
+    closed: bool,
+}
+
+impl Inbox {
+    fn new(events: broadcast::Receiver<Log>) -> Self {
+        Self {
+            events,
+            buffer: Vec::new(),
+            closed: false,
+        }
+    }
+
+    /// Waits for more data until `deadline`.
+    /// Returns `false` if none arrived in time or the connection was closed, and fails if events
+    /// were missed as the data can't be trusted then.
+    fn fill(&mut self, rt: &Handle, stop: &AtomicBool, deadline: Instant) -> ScriptResult<bool> {
+        while !self.closed && Instant::now() < deadline {
+            if stop.load(Ordering::Relaxed) {
+                return Err(terminated());
+            }
+
+            let poll_deadline = deadline.min(Instant::now() + POLL_INTERVAL);
+            match rt.block_on(timeout_at(poll_deadline, self.events.recv())) {
+                Ok(Ok(log)) => match log.data {
+                    LogData::ReceivedPacket(packet) => {
+                        self.buffer.extend(packet.data);
+                        return Ok(true);
+                    }
+                    LogData::ClientDisconnect(_) => self.closed = true,
+                    _ => {}
+                },
+                Ok(Err(RecvError::Closed)) => self.closed = true,
+                Ok(Err(RecvError::Lagged(missed))) => return Err(missed_events(missed)),
+                Err(_) => {}
+            }
+        }
+        Ok(false)
+    }
+}
+
+#[derive(Clone)]
+enum Pattern {
+    Regex(Regex),
+    Bytes(Vec<u8>),
+}
+
+impl Pattern {
+    fn regex(pattern: &str) -> ScriptResult<Self> {
+        Regex::new(pattern).map(Self::Regex).map_err(script_error)
+    }
+
+    /// Returns the offset right after the first match in `data`.
+    fn find_end(&self, data: &[u8]) -> Option<usize> {
+        match self {
+            Self::Regex(regex) => regex.find(data).map(|m| m.end()),
+            Self::Bytes(bytes) if bytes.is_empty() => Some(0),
+            Self::Bytes(bytes) => data
+                .windows(bytes.len())
+                .position(|w| w == bytes.as_slice())
+                .map(|i| i + bytes.len()),
+        }
+    }
+}
+
+impl std::fmt::Display for Pattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Regex(regex) => write!(f, "/{}/", regex.as_str()),
+            Self::Bytes(bytes) => write!(f, "[{}]", hex_encode_formatted(bytes)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use palm_core::backend::{Connection, DataPacket, Server};
+    use tokio::runtime::Runtime;
+
+    use super::*;
+
+    fn received(data: &[u8]) -> Log {
+        Log::received(DataPacket::new("127.0.0.1:1".into(), data.to_vec()))
+    }
+
+    #[test]
+    fn deadlines_are_in_the_future() {
+        let rt = Runtime::new().unwrap();
+        let _guard = rt.enter();
+        let now = Instant::now();
+        assert!(deadline(0) >= now);
+        assert!(deadline(-5) <= Instant::now());
+        let second = deadline(1000) - now;
+        assert!(second >= Duration::from_secs(1) && second < Duration::from_secs(2));
+        // Too far out to be represented
+        assert!(deadline(INT::MAX) - now >= FAR_FUTURE);
+    }
+
+    #[test]
+    fn inbox_buffers_received_data() {
+        let rt = Runtime::new().unwrap();
+        let (events_tx, events) = broadcast::channel(16);
+        let mut inbox = Inbox::new(events);
+        let stop = AtomicBool::new(false);
+        // Blocking script functions run on a thread of the runtime
+        let _guard = rt.enter();
+        let soon = || Instant::now() + Duration::from_millis(50);
+
+        events_tx.send(Log::script("ignored".into())).unwrap();
+        events_tx.send(received(b"ab")).unwrap();
+        events_tx.send(received(b"cd")).unwrap();
+        assert!(inbox.fill(rt.handle(), &stop, soon()).unwrap());
+        assert_eq!(inbox.buffer, b"ab");
+        assert!(inbox.fill(rt.handle(), &stop, soon()).unwrap());
+        assert_eq!(inbox.buffer, b"abcd");
+        // Nothing more arrives in time
+        assert!(!inbox.fill(rt.handle(), &stop, soon()).unwrap());
+        assert!(!inbox.closed);
+
+        events_tx
+            .send(Log::disconnect("127.0.0.1:1".into()))
+            .unwrap();
+        assert!(!inbox.fill(rt.handle(), &stop, soon()).unwrap());
+        assert!(inbox.closed);
+        assert_eq!(inbox.buffer, b"abcd");
+    }
+
+    #[test]
+    fn inbox_fails_when_events_were_missed() {
+        let rt = Runtime::new().unwrap();
+        let (events_tx, events) = broadcast::channel(2);
+        let mut inbox = Inbox::new(events);
+        for data in [b"a", b"b", b"c"] {
+            events_tx.send(received(data)).unwrap();
+        }
+        let _guard = rt.enter();
+        let deadline = Instant::now() + Duration::from_secs(1);
+        let error = inbox
+            .fill(rt.handle(), &AtomicBool::new(false), deadline)
+            .unwrap_err();
+        assert_eq!(error.to_string(), "Runtime error: Missed 1 events");
+    }
+
+    #[test]
+    fn inbox_stops_when_asked() {
+        let rt = Runtime::new().unwrap();
+        let (_events_tx, events) = broadcast::channel(2);
+        let _guard = rt.enter();
+        let deadline = Instant::now() + Duration::from_secs(60);
+        let error = Inbox::new(events)
+            .fill(rt.handle(), &AtomicBool::new(true), deadline)
+            .unwrap_err();
+        assert!(matches!(*error, EvalAltResult::ErrorTerminated(..)));
+    }
+
+    /// Waits for the first event `f` picks something out of.
+    fn wait_for<T>(
+        rt: &Runtime,
+        events: &mut broadcast::Receiver<Log>,
+        mut f: impl FnMut(LogData) -> Option<T>,
+    ) -> T {
+        rt.block_on(async {
+            tokio::time::timeout(Duration::from_secs(5), async {
+                loop {
+                    if let Some(found) = f(events.recv().await.unwrap().data) {
+                        return found;
+                    }
+                }
+            })
+            .await
+            .expect("Timed out waiting for an event")
+        })
+    }
+
+    #[test]
+    fn client_script_talks_to_a_server() {
+        let rt = Runtime::new().unwrap();
+        let server = rt.block_on(Server::listen(0)).unwrap();
+        let mut server_events = server.subscribe();
+        let conn = rt
+            .block_on(Connection::connect(format!(
+                "127.0.0.1:{}",
+                server.port().unwrap()
+            )))
+            .unwrap();
+        let mut client_events = conn.subscribe();
+
+        let script = r#"
+            send("ping");
+            let reply = expect("po+ng");
+            log("got " + hex(reply));
+        "#;
+        run_client(script.into(), conn.handle(), rt.handle());
+
+        let (address, data) = wait_for(&rt, &mut server_events, |log| match log {
+            LogData::ReceivedPacket(packet) => Some((packet.address, packet.data)),
+            _ => None,
+        });
+        assert_eq!(data, b"ping");
+        // Split up so that the script has to put it back together
+        let reply = server.handle().connection(&address).unwrap();
+        reply.send_data(b"po".to_vec()).unwrap();
+        reply.send_data(b"ong".to_vec()).unwrap();
+
+        let mut messages = Vec::new();
+        wait_for(&rt, &mut client_events, |log| match log {
+            LogData::Script(message) => {
+                messages.push(message);
+                (messages.last().unwrap() == "Script finished").then_some(())
+            }
+            LogData::ScriptError(error) => panic!("{error}"),
+            _ => None,
+        });
+        assert_eq!(messages, ["got 70 6F 6F 6E 67", "Script finished"]);
+    }
+
+    #[test]
+    fn expect_fails_once_the_timeout_passes() {
+        let rt = Runtime::new().unwrap();
+        let server = rt.block_on(Server::listen(0)).unwrap();
+        let conn = rt
+            .block_on(Connection::connect(format!(
+                "127.0.0.1:{}",
+                server.port().unwrap()
+            )))
+            .unwrap();
+        let mut events = conn.subscribe();
+
+        run_client(r#"expect("never", 50);"#.into(), conn.handle(), rt.handle());
+        let error = wait_for(&rt, &mut events, |log| match log {
+            LogData::ScriptError(error) => Some(error),
+            _ => None,
+        });
+        assert!(error.contains("Expected /never/ within 50ms"), "{error}");
+    }
+}
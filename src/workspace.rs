@@ -0,0 +1,117 @@
+//! Saving the open tabs and their layout, either automatically when Palm closes or as named
+//! workspace files. Only tabs are kept; views of logs, captures and diffs are left out.
+
+use std::{
+    collections::HashSet,
+    io::{BufReader, BufWriter},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use egui_tiles::{Tile, TileId, Tiles, Tree};
+use serde::{Deserialize, Serialize};
+use tokio::runtime::Runtime;
+
+use crate::{
+    filter::TimeFormat,
+    gui::{Mode, Pane, SendOptionsUI, Tab},
+};
+
+pub const EXTENSION: &str = "palmws";
+
+/// Key of the workspace in eframe's storage.
+pub const STORAGE_KEY: &str = "workspace";
+
+/// Longest draft kept when saving automatically, which happens every so often while Palm runs.
+/// Longer ones are only kept in workspace files.
+pub const MAX_AUTOSAVED_DRAFT: usize = 1024 * 1024;
+
+#[derive(Serialize, Deserialize)]
+pub struct Workspace {
+    tree: Tree<TabState>,
+    next_tab_id: u32,
+}
+
+/// What's kept of a [`Tab`].
+#[derive(Serialize, Deserialize)]
+pub struct TabState {
+    pub id: u32,
+    pub mode: Mode,
+    pub address: String,
+    pub port: String,
+    /// The client's draft, as hex
+    pub draft: String,
+    pub send_options: SendOptionsUI,
+    pub script: String,
+    pub scenario: Option<PathBuf>,
+    /// Template loaded in the inspector, missing from older workspaces
+    #[serde(default)]
+    pub template: Option<PathBuf>,
+    pub log_capacity: usize,
+    pub time_format: TimeFormat,
+}
+
+impl Workspace {
+    /// The workspace of the open tabs. Long drafts are left out if it's `autosaved`.
+    pub fn new(tree: &Tree<Pane>, next_tab_id: u32, autosaved: bool) -> Self {
+        let max_draft = if autosaved {
+            MAX_AUTOSAVED_DRAFT
+        } else {
+            usize::MAX
+        };
+        let mut tiles = Tiles::default();
+        for (&id, tile) in tree.tiles.iter() {
+            match tile {
+                Tile::Pane(Pane::Tab(tab)) => tiles.insert(id, Tile::Pane(tab.state(max_draft))),
+                Tile::Pane(_) => {}
+                Tile::Container(container) => tiles.insert(id, Tile::Container(container.clone())),
+            }
+        }
+        forget_missing_children(&mut tiles);
+
+        let tree = match tree.root().filter(|root| tiles.get(*root).is_some()) {
+            Some(root) => Tree::new(tree.id(), root, tiles),
+            None => Tree::empty(tree.id()),
+        };
+        Self { tree, next_tab_id }
+    }
+
+    /// Makes the tabs anew, with nothing connected or started yet.
+    pub fn restore(self, rt: &Arc<Runtime>) -> (Tree<Pane>, u32) {
+        let mut tiles = Tiles::default();
+        for (&id, tile) in self.tree.tiles.iter() {
+            let tile = match tile {
+                Tile::Pane(state) => Tile::Pane(Pane::Tab(Tab::restore(rt.clone(), state))),
+                Tile::Container(container) => Tile::Container(container.clone()),
+            };
+            tiles.insert(id, tile);
+        }
+
+        let tree = match self.tree.root() {
+            Some(root) => Tree::new(self.tree.id(), root, tiles),
+            None => Tree::empty(self.tree.id()),
+        };
+        (tree, self.next_tab_id)
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let file = std::fs::File::create(path)?;
+        serde_json::to_writer_pretty(BufWriter::new(file), self)?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let file = std::fs::File::open(path)?;
+        Ok(serde_json::from_reader(BufReader::new(file))?)
+    }
+}
+
+/// Removes children of containers that weren't kept.
+fn forget_missing_children(tiles: &mut Tiles<TabState>) {
+    let kept: HashSet<TileId> = tiles.tile_ids().collect();
+    for tile in tiles.tiles_mut() {
+        if let Tile::Container(container) = tile {
+            container.retain(|child| kept.contains(&child));
+        }
+    }
+}